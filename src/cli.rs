@@ -0,0 +1,314 @@
+use std::{fmt::Display, rc::Rc};
+
+use crate::{codegen::Syntax, optimize::OptLevel};
+
+pub const USAGE: &str = "\
+Usage: toylang [options] <input>...
+
+Reads the program from stdin when <input> is `-`. The top-level statements of
+several inputs are combined, in order, into one program.
+
+Options:
+    -o <output>      Write <output>.asm, <output>.obj and <output>.exe instead of
+                     naming them after the first input, or `out` if that is stdin
+    --emit-asm       Only write the assembly, without assembling or linking it
+    --interpret      Run the program without compiling it and exit with its exit
+                     code, which needs neither nasm nor gcc
+    -c               Only assemble the program into <output>.obj, without linking it
+    --syntax <s>     Write the assembly for nasm (the default) or for the GNU
+                     assembler with `gas`, which writes <output>.s instead
+    --link <file>    Link the program against <file> as well, e.g. an object file
+                     or a DLL, on top of kernel32.dll
+    --no-comments    Leave comments out of the emitted assembly
+    --dump-tokens    Print the tokens of the inputs instead of compiling it
+    --dump-symbols   Print the variables of every frame with their types and
+                     stack slots
+    --warn-shadowing Warn when a variable shadows one of an enclosing scope
+    --optimize <n>   Optimize at level 0 (the default), 1 or 2
+    -O0, -O1, -O2    Short for `--optimize <n>`
+    -h, --help       Print this message
+    -V, --version    Print the compiler's version
+";
+
+/// The `<input>` that stands for stdin.
+pub const STDIN_INPUT: &str = "-";
+
+#[derive(Debug)]
+pub struct Args {
+    /// The input files in the order they were given, with at most one of them `-`.
+    pub inputs: Vec<Rc<str>>,
+    pub output: Rc<str>,
+    pub emit_asm_only: bool,
+    pub assemble_only: bool,
+    pub interpret: bool,
+    /// Files to link against besides the default ones, in the order they were given.
+    pub link_files: Vec<Rc<str>>,
+    pub emit_comments: bool,
+    pub dump_tokens: bool,
+    pub dump_symbols: bool,
+    pub warn_shadowing: bool,
+    pub opt_level: OptLevel,
+    pub syntax: Syntax,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError {
+    HelpRequested,
+    VersionRequested,
+    MissingInput,
+    MissingValue(String),
+    InvalidValue(String, String),
+    UnknownFlag(String),
+    UnexpectedArgument(String),
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HelpRequested => write!(f, "help requested"),
+            Self::VersionRequested => write!(f, "version requested"),
+            Self::MissingInput => write!(f, "no input file given"),
+            Self::MissingValue(flag) => write!(f, "`{}` expects a value", flag),
+            Self::InvalidValue(flag, value) => {
+                write!(f, "invalid value `{}` for `{}`", value, flag)
+            }
+            Self::UnknownFlag(flag) => write!(f, "unknown option `{}`", flag),
+            Self::UnexpectedArgument(arg) => write!(f, "unexpected argument `{}`", arg),
+        }
+    }
+}
+
+/// Parses the command line arguments, not including the program name.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, CliError> {
+    let mut args = args.into_iter();
+    let mut inputs: Vec<Rc<str>> = Vec::new();
+    let mut output: Option<Rc<str>> = None;
+    let mut emit_asm_only = false;
+    let mut assemble_only = false;
+    let mut interpret = false;
+    let mut link_files: Vec<Rc<str>> = Vec::new();
+    let mut emit_comments = true;
+    let mut dump_tokens = false;
+    let mut dump_symbols = false;
+    let mut warn_shadowing = false;
+    let mut opt_level = OptLevel::default();
+    let mut syntax = Syntax::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::HelpRequested),
+            "-V" | "--version" => return Err(CliError::VersionRequested),
+            "-o" => match args.next() {
+                Some(value) => output = Some(Rc::from(value)),
+                None => return Err(CliError::MissingValue(arg)),
+            },
+            "--emit-asm" => emit_asm_only = true,
+            "-c" => assemble_only = true,
+            "--interpret" => interpret = true,
+            "--link" => match args.next() {
+                Some(value) => link_files.push(Rc::from(value)),
+                None => return Err(CliError::MissingValue(arg)),
+            },
+            "--no-comments" => emit_comments = false,
+            "--dump-tokens" => dump_tokens = true,
+            "--dump-symbols" => dump_symbols = true,
+            "--warn-shadowing" => warn_shadowing = true,
+            "--optimize" => match args.next() {
+                Some(value) => match value.parse() {
+                    Ok(level) => opt_level = level,
+                    Err(_) => return Err(CliError::InvalidValue(arg, value)),
+                },
+                None => return Err(CliError::MissingValue(arg)),
+            },
+            "--syntax" => match args.next() {
+                Some(value) => match value.parse() {
+                    Ok(value) => syntax = value,
+                    Err(_) => return Err(CliError::InvalidValue(arg, value)),
+                },
+                None => return Err(CliError::MissingValue(arg)),
+            },
+            "-O0" => opt_level = OptLevel::O0,
+            "-O1" => opt_level = OptLevel::O1,
+            "-O2" => opt_level = OptLevel::O2,
+            flag if flag.starts_with('-') && flag != STDIN_INPUT => {
+                return Err(CliError::UnknownFlag(arg))
+            }
+            // stdin can only be read once.
+            STDIN_INPUT if inputs.iter().any(|input| input.as_ref() == STDIN_INPUT) => {
+                return Err(CliError::UnexpectedArgument(arg))
+            }
+            _ => inputs.push(Rc::from(arg)),
+        }
+    }
+
+    let Some(first) = inputs.first() else {
+        return Err(CliError::MissingInput);
+    };
+    return Ok(Args {
+        output: output.unwrap_or_else(|| match first.as_ref() {
+            STDIN_INPUT => Rc::from("out"),
+            _ => first.clone(),
+        }),
+        inputs,
+        emit_asm_only,
+        assemble_only,
+        interpret,
+        link_files,
+        emit_comments,
+        dump_tokens,
+        dump_symbols,
+        warn_shadowing,
+        opt_level,
+        syntax,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, CliError> {
+        return parse_args(args.iter().map(|arg| String::from(*arg)));
+    }
+
+    #[test]
+    fn input_and_output() {
+        let args = parse(&["prog.tl"]).unwrap();
+        assert_eq!(args.inputs, [Rc::from("prog.tl")]);
+        assert_eq!(args.output.as_ref(), "prog.tl");
+        assert!(!args.emit_asm_only);
+        assert!(!args.assemble_only);
+        assert!(args.emit_comments);
+
+        let args = parse(&["-o", "out", "--emit-asm", "prog.tl", "--no-comments"]).unwrap();
+        assert_eq!(args.inputs, [Rc::from("prog.tl")]);
+        assert_eq!(args.output.as_ref(), "out");
+        assert!(args.emit_asm_only);
+        assert!(!args.emit_comments);
+        assert!(!args.dump_tokens);
+        assert!(!args.warn_shadowing);
+
+        assert!(parse(&["--dump-tokens", "prog.tl"]).unwrap().dump_tokens);
+        assert!(!parse(&["prog.tl"]).unwrap().dump_symbols);
+        assert!(parse(&["--dump-symbols", "prog.tl"]).unwrap().dump_symbols);
+        assert!(parse(&["-c", "prog.tl"]).unwrap().assemble_only);
+        assert!(!parse(&["prog.tl"]).unwrap().interpret);
+        assert!(parse(&["--interpret", "prog.tl"]).unwrap().interpret);
+        assert!(
+            parse(&["--warn-shadowing", "prog.tl"])
+                .unwrap()
+                .warn_shadowing
+        );
+    }
+
+    #[test]
+    fn stdin_input() {
+        let args = parse(&["-"]).unwrap();
+        assert_eq!(args.inputs, [Rc::from(STDIN_INPUT)]);
+        assert_eq!(args.output.as_ref(), "out");
+
+        let args = parse(&["-o", "prog", "-"]).unwrap();
+        assert_eq!(args.output.as_ref(), "prog");
+    }
+
+    #[test]
+    fn several_inputs() {
+        let args = parse(&["a.tl", "-O1", "b.tl", "-", "-o", "prog"]).unwrap();
+        let inputs: Vec<&str> = args.inputs.iter().map(|input| input.as_ref()).collect();
+        assert_eq!(inputs, ["a.tl", "b.tl", "-"]);
+        assert_eq!(args.output.as_ref(), "prog");
+
+        assert_eq!(parse(&["a.tl", "b.tl"]).unwrap().output.as_ref(), "a.tl");
+        assert_eq!(parse(&["-", "b.tl"]).unwrap().output.as_ref(), "out");
+    }
+
+    #[test]
+    fn link_files() {
+        assert!(parse(&["prog.tl"]).unwrap().link_files.is_empty());
+        let args = parse(&["--link", "rt.obj", "prog.tl", "--link", "user32.dll"]).unwrap();
+        assert_eq!(
+            args.link_files,
+            [Rc::from("rt.obj"), Rc::from("user32.dll")]
+        );
+        assert_eq!(
+            parse(&["prog.tl", "--link"]).unwrap_err(),
+            CliError::MissingValue(String::from("--link"))
+        );
+    }
+
+    #[test]
+    fn optimization_level() {
+        assert_eq!(parse(&["prog.tl"]).unwrap().opt_level, OptLevel::O0);
+        assert_eq!(parse(&["-O2", "prog.tl"]).unwrap().opt_level, OptLevel::O2);
+        let args = parse(&["prog.tl", "--optimize", "1"]).unwrap();
+        assert_eq!(args.opt_level, OptLevel::O1);
+
+        assert_eq!(
+            parse(&["--optimize", "3", "prog.tl"]).unwrap_err(),
+            CliError::InvalidValue(String::from("--optimize"), String::from("3"))
+        );
+        assert_eq!(
+            parse(&["prog.tl", "--optimize"]).unwrap_err(),
+            CliError::MissingValue(String::from("--optimize"))
+        );
+    }
+
+    #[test]
+    fn assembler_syntax() {
+        assert_eq!(parse(&["prog.tl"]).unwrap().syntax, Syntax::Nasm);
+        let args = parse(&["--syntax", "gas", "prog.tl"]).unwrap();
+        assert_eq!(args.syntax, Syntax::Gas);
+        assert_eq!(
+            parse(&["--syntax", "att", "prog.tl"]).unwrap_err(),
+            CliError::InvalidValue(String::from("--syntax"), String::from("att"))
+        );
+        assert_eq!(
+            parse(&["prog.tl", "--syntax"]).unwrap_err(),
+            CliError::MissingValue(String::from("--syntax"))
+        );
+    }
+
+    #[test]
+    fn version() {
+        assert_eq!(
+            parse(&["--version"]).unwrap_err(),
+            CliError::VersionRequested
+        );
+        assert_eq!(
+            parse(&["-V", "prog.tl"]).unwrap_err(),
+            CliError::VersionRequested
+        );
+
+        let version = toylang::version();
+        let parts: Vec<&str> = version.split('.').collect();
+        assert_eq!(parts.len(), 3, "{}", version);
+        for part in parts {
+            assert!(
+                !part.is_empty() && part.chars().all(|ch| ch.is_ascii_digit()),
+                "{}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert_eq!(parse(&[]).unwrap_err(), CliError::MissingInput);
+        assert_eq!(
+            parse(&["--help", "prog.tl"]).unwrap_err(),
+            CliError::HelpRequested
+        );
+        assert_eq!(
+            parse(&["prog.tl", "-o"]).unwrap_err(),
+            CliError::MissingValue(String::from("-o"))
+        );
+        assert_eq!(
+            parse(&["--fast", "prog.tl"]).unwrap_err(),
+            CliError::UnknownFlag(String::from("--fast"))
+        );
+        assert_eq!(
+            parse(&["-", "a.tl", "-"]).unwrap_err(),
+            CliError::UnexpectedArgument(String::from("-"))
+        );
+    }
+}
@@ -1,24 +1,41 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
     fs::File,
     io::Write,
-    process::Command,
+    process::{Command, ExitStatus},
     ptr::NonNull,
 };
 
 use crate::{
-    parser::{Identifier, IntLiteral, LExp, RExp, Stmt, Term},
-    CompileError,
+    lexer::Location,
+    optimize::OptLevel,
+    parser::{
+        Declaration, Identifier, IntLiteral, Intrinsic, LExp, PrintFormat, RExp, Signedness, Stmt,
+        Term, Type,
+    },
+    CompileError, CompileWarning,
 };
 
-use super::string_decorator::StringDecorator;
+use super::{
+    gas::{self, Syntax},
+    operand::{Operand, Reg, Size},
+    stack_tracker::StackTracker,
+    string_decorator::StringDecorator,
+};
 
 #[derive(Debug)]
 pub struct Symbol {
     pub decorated_lexeme: String,
-    pub size_bytes: usize,
     pub rbp_offset: usize,
     pub initialized: bool,
+    /// True if the symbol is initialized on at least one path through the code so far,
+    /// while `initialized` requires every path to have initialized it.
+    pub maybe_initialized: bool,
+    pub mutable: bool,
+    pub ty: Type,
+    /// Where the name of the symbol is in its declaration.
+    pub declared_at: Location,
 }
 
 struct SymbolBuilder {
@@ -26,6 +43,9 @@ struct SymbolBuilder {
     size_bytes: Option<usize>,
     rbp_offset: Option<usize>,
     initialized: Option<bool>,
+    mutable: Option<bool>,
+    ty: Option<Type>,
+    declared_at: Option<Location>,
 }
 
 impl SymbolBuilder {
@@ -35,6 +55,9 @@ impl SymbolBuilder {
             size_bytes: None,
             rbp_offset: None,
             initialized: None,
+            mutable: None,
+            ty: None,
+            declared_at: None,
         };
     }
     pub fn decorated_lexeme(&mut self, decorated_lexeme: String) -> &mut Self {
@@ -53,28 +76,73 @@ impl SymbolBuilder {
         self.initialized = Some(initialized);
         return self;
     }
-    pub fn build(&self) -> Symbol {
-        let self_decorated_lexeme = unsafe {
-            let ptr = &self.decorated_lexeme as *const Option<String> as *mut Option<String>;
-            ptr.as_mut().unwrap()
-        };
-        let decorated_lexeme = std::mem::take(self_decorated_lexeme);
+    pub fn mutable(&mut self, mutable: bool) -> &mut Self {
+        self.mutable = Some(mutable);
+        return self;
+    }
+    pub fn ty(&mut self, ty: Type) -> &mut Self {
+        self.ty = Some(ty);
+        return self;
+    }
+    pub fn declared_at(&mut self, declared_at: Location) -> &mut Self {
+        self.declared_at = Some(declared_at);
+        return self;
+    }
+    pub fn build(&mut self) -> Symbol {
+        let decorated_lexeme = std::mem::take(&mut self.decorated_lexeme);
         return Symbol {
             decorated_lexeme: decorated_lexeme.unwrap(),
-            size_bytes: self.size_bytes.unwrap(),
             rbp_offset: self.rbp_offset.unwrap(),
             initialized: self.initialized.unwrap(),
+            maybe_initialized: self.initialized.unwrap(),
+            mutable: self.mutable.unwrap(),
+            ty: self.ty.unwrap(),
+            declared_at: self.declared_at.unwrap(),
         };
     }
 }
 
 pub type SymTable = HashMap<String, Symbol>;
 
+/// Takes the code generated so far out of `Asm::text`, see `Asm::gen_streaming`.
+type Flush<'a> = &'a mut dyn FnMut(&mut Asm);
+
+/// Lends out `flush` for one call, so that it can be passed on more than once.
+fn reborrow<'a>(flush: &'a mut Option<Flush>) -> Option<Flush<'a>> {
+    return flush.as_mut().map(|flush| &mut **flush as Flush);
+}
+
+/// Exit code of a program that divided by zero while the division guard was enabled.
+pub const DIV_BY_ZERO_EXIT_CODE: i64 = 136;
+const DIV_BY_ZERO_LABEL: &str = "div_by_zero";
+
+/// The tail that the print routines share, which writes their buffer to stdout.
+const PRINT_WRITE_LABEL: &str = "print_write";
+/// The size of the frame of a print routine: the text it writes is built in the 32 bytes below
+/// `rbp`, followed by the start of the text and the number of bytes `WriteFile` wrote. Below
+/// those go the shadow space and the fifth argument of `WriteFile`.
+const PRINT_FRAME_SIZE: i64 = 96;
+/// `STD_OUTPUT_HANDLE`, for `GetStdHandle`.
+const STD_OUTPUT_HANDLE: i64 = -11;
+
+/// Where a scope opened with `Env::push_scope` started, so that `Env::pop_scope` can go back.
+struct Scope {
+    rbp_offset: usize,
+    shadow_counts: HashMap<String, u32>,
+    /// The decorated names of the symbols declared in the scope.
+    declared: Vec<String>,
+}
+
 pub struct Env {
     prev: Option<NonNull<Env>>,
     symtable: SymTable,
     shadow_counts: HashMap<String, u32>,
     current_rbp_offset: usize,
+    /// The most stack space the variables of this scope and the scopes inside of it take up
+    /// at once.
+    frame_size: usize,
+    /// The scopes opened inside of this environment, innermost last.
+    scopes: Vec<Scope>,
 }
 
 impl Env {
@@ -84,16 +152,42 @@ impl Env {
             symtable: HashMap::new(),
             shadow_counts: HashMap::new(),
             current_rbp_offset: 0,
+            frame_size: 0,
+            scopes: Vec::new(),
         }
     }
 
-    fn with_tail(tail: &Env) -> Self {
+    fn with_tail(tail: &mut Env) -> Self {
         Self {
+            current_rbp_offset: tail.current_rbp_offset,
+            frame_size: tail.current_rbp_offset,
             prev: Some(NonNull::from(tail)),
             symtable: HashMap::new(),
             shadow_counts: HashMap::new(),
-            current_rbp_offset: tail.current_rbp_offset,
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Opens a scope inside of this environment. Unlike a new environment from `with_tail`,
+    /// its symbols go into this one until `pop_scope` removes them again.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Scope {
+            rbp_offset: self.current_rbp_offset,
+            shadow_counts: self.shadow_counts.clone(),
+            declared: Vec::new(),
+        });
+    }
+
+    /// Closes the innermost scope opened with `push_scope`. Its symbols go out of scope and the
+    /// stack space they took up is handed back, so the next scope reuses it. The frame keeps
+    /// its size.
+    pub fn pop_scope(&mut self) {
+        let scope = self.scopes.pop().expect("[Env.pop_scope] No scope to pop");
+        for name in scope.declared.iter() {
+            self.symtable.remove(name);
         }
+        self.shadow_counts = scope.shadow_counts;
+        self.current_rbp_offset = scope.rbp_offset;
     }
 
     fn get_shadow_count_mut(&mut self, lexeme: &str) -> &mut u32 {
@@ -127,11 +221,48 @@ impl Env {
         }
     }
 
+    fn get_symbol_mut(&mut self, lexeme: &str) -> Option<&mut Symbol> {
+        let shadow_count = self.get_shadow_count(lexeme);
+        let decorated_lexeme = format!("{}_{}", lexeme, shadow_count);
+        if self.symtable.contains_key(&decorated_lexeme) {
+            return self.symtable.get_mut(&decorated_lexeme);
+        }
+        let env = unsafe { self.prev?.as_mut() };
+        return env.get_symbol_mut(lexeme);
+    }
+
+    /// The symbol of an enclosing scope that declaring `lexeme` in this scope would shadow.
+    /// Redeclaring a name in the scope it was declared in doesn't count.
+    fn shadowed_symbol(&self, lexeme: &str) -> Option<&Symbol> {
+        let shadow_count = self.get_shadow_count(lexeme);
+        if let Some(scope) = self.scopes.last() {
+            if shadow_count > scope.shadow_counts.get(lexeme).copied().unwrap_or(0) {
+                return None;
+            }
+            if shadow_count > 0 {
+                return self.get_symbol(lexeme);
+            }
+        }
+        if shadow_count > 0 {
+            return None;
+        }
+        let env = unsafe { self.prev?.as_ref() };
+        return env.get_symbol(lexeme);
+    }
+
+    /// Adds the symbol under the name `{lexeme}_{count}`, the count being how often `lexeme`
+    /// was declared in this scope. Counts have no `_` in them, so the name splits back into the
+    /// lexeme and the count at its last `_`, and no two symbols end up with the same name: `x`
+    /// declared for the first time is `x_1`, while `x_1` is `x_1_1`.
     fn register_symbol(&mut self, lexeme: &str, symbol_builder: &mut SymbolBuilder) {
         let shadow_count = self.get_shadow_count_mut(lexeme);
         *shadow_count += 1;
         let decorated_lexeme = format!("{}_{}", lexeme, shadow_count);
-        self.current_rbp_offset += 8;
+        self.current_rbp_offset += symbol_builder.size_bytes.unwrap();
+        self.extend_frame(self.current_rbp_offset);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.declared.push(decorated_lexeme.clone());
+        }
         self.symtable.insert(
             decorated_lexeme.clone(),
             symbol_builder
@@ -141,120 +272,290 @@ impl Env {
         );
     }
 
-    fn declare(&mut self, ident: &Identifier) {
+    fn extend_frame(&mut self, rbp_offset: usize) {
+        self.frame_size = self.frame_size.max(rbp_offset);
+        if let Some(mut prev) = self.prev {
+            unsafe { prev.as_mut() }.extend_frame(rbp_offset);
+        }
+    }
+
+    /// The symbols declared in this scope, by their decorated names, in no particular order.
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, &Symbol)> {
+        return self.symtable.iter().map(|(name, sym)| (name.as_str(), sym));
+    }
+
+    /// The symbols of this scope and of every scope around it, innermost scope first.
+    /// Shadowed symbols are included.
+    pub fn all_symbols(&self) -> impl Iterator<Item = (&str, &Symbol)> {
+        let mut symbols: Vec<(&str, &Symbol)> = self.symbols().collect();
+        if let Some(prev) = self.prev {
+            symbols.extend(unsafe { prev.as_ref() }.all_symbols());
+        }
+        return symbols.into_iter();
+    }
+
+    /// Finds the visible symbol stored at `rbp_offset`. Offsets are unique among the symbols
+    /// visible from one environment, even where a name is shadowed.
+    fn get_symbol_at_mut(&mut self, rbp_offset: usize) -> Option<&mut Symbol> {
+        let symtable = unsafe { NonNull::from(&mut self.symtable).as_mut() };
+        if let Some(sym) = symtable
+            .values_mut()
+            .find(|sym| sym.rbp_offset == rbp_offset)
+        {
+            return Some(sym);
+        }
+        let env = unsafe { self.prev?.as_mut() };
+        return env.get_symbol_at_mut(rbp_offset);
+    }
+
+    /// The offsets of the visible symbols that are not initialized yet, along with whether
+    /// they may have been initialized already.
+    fn pending_initializations(&self) -> Vec<(usize, bool)> {
+        let mut pending: Vec<(usize, bool)> = self
+            .symtable
+            .values()
+            .filter(|sym| !sym.initialized)
+            .map(|sym| (sym.rbp_offset, sym.maybe_initialized))
+            .collect();
+        if let Some(prev) = self.prev {
+            pending.extend(unsafe { prev.as_ref() }.pending_initializations());
+        }
+        return pending;
+    }
+
+    /// Reports which of the `pending` symbols a branch initialized, and resets them to how they
+    /// were before the branch so that the next branch starts from the same state.
+    fn take_initializations(&mut self, pending: &[(usize, bool)]) -> Vec<bool> {
+        let mut initialized = Vec::new();
+        for (rbp_offset, maybe_initialized) in pending.iter() {
+            let sym = self.get_symbol_at_mut(*rbp_offset).unwrap();
+            initialized.push(sym.initialized);
+            sym.initialized = false;
+            sym.maybe_initialized = *maybe_initialized;
+        }
+        return initialized;
+    }
+
+    /// Joins the results of `take_initializations` for every branch of an if statement.
+    /// A symbol is initialized afterwards only if all the branches initialized it.
+    fn merge_initializations(&mut self, pending: &[(usize, bool)], branches: &[Vec<bool>]) {
+        for (i, (rbp_offset, maybe_initialized)) in pending.iter().enumerate() {
+            let sym = self.get_symbol_at_mut(*rbp_offset).unwrap();
+            sym.initialized = branches.iter().all(|branch| branch[i]);
+            sym.maybe_initialized = *maybe_initialized || branches.iter().any(|branch| branch[i]);
+        }
+    }
+
+    fn declare(&mut self, decl: &Declaration, ty: Type) {
         self.register_symbol(
-            &ident.lexeme,
-            SymbolBuilder::new().size_bytes(8).initialized(false),
+            &decl.ident.lexeme,
+            SymbolBuilder::new()
+                .size_bytes(ty.size_bytes())
+                .initialized(false)
+                .mutable(decl.mutable)
+                .ty(ty)
+                .declared_at(decl.ident.start),
         );
     }
-    fn initialize(&mut self, ident: &Identifier) {
+    fn initialize(&mut self, decl: &Declaration, ty: Type) {
         self.register_symbol(
-            &ident.lexeme,
-            SymbolBuilder::new().size_bytes(8).initialized(true),
+            &decl.ident.lexeme,
+            SymbolBuilder::new()
+                .size_bytes(ty.size_bytes())
+                .initialized(true)
+                .mutable(decl.mutable)
+                .ty(ty)
+                .declared_at(decl.ident.start),
         );
     }
 }
 
 #[derive(Debug)]
 pub struct Asm {
-    link_files: HashSet<String>,
+    link_files: BTreeSet<String>,
     label_decorator: StringDecorator,
     externals: Vec<String>,
     text: String,
+
+    /// When false, comments and blank separator lines are left out of the output.
+    pub emit_comments: bool,
+    /// The syntax the code is written out in, which also decides the assembler `compile`
+    /// runs.
+    pub syntax: Syntax,
+    /// Comments longer than this many characters are cut short and end in `...`. Expressions
+    /// are only displayed up to the cut, so deeply nested ones don't make codegen quadratic.
+    pub max_comment_len: usize,
+    /// When true, every division checks its divisor and exits with
+    /// `DIV_BY_ZERO_EXIT_CODE` instead of trapping on zero.
+    pub div_by_zero_guard: bool,
+    uses_div_by_zero_handler: bool,
+    /// The formats of the print routines that the code calls, which are emitted after it.
+    print_routines: Vec<PrintFormat>,
+    /// Warnings about the program, in the order they were found.
+    pub warnings: Vec<CompileWarning>,
+    /// From `O1` on, `gen` finishes with a peephole pass over the generated code and reuses
+    /// values that are still in a register instead of loading them again. The AST
+    /// passes are run separately by `optimize::optimize`.
+    pub opt_level: OptLevel,
+    /// When true, a declaration that shadows a variable of an enclosing scope is reported as a
+    /// `CompileWarning::Shadowing`.
+    pub warn_shadowing: bool,
+    /// When true, the variables still in scope at the end of each frame are listed in
+    /// `symbol_dump`.
+    pub dump_symbols: bool,
+    /// One line with the label of each frame, followed by a line for each of its variables
+    /// with its type and stack slot, e.g. `    x_1: i64 [rbp-8]`.
+    pub symbol_dump: String,
+    /// How deep the stack is at the end of the code generated so far.
+    stack: StackTracker,
+    /// Whether a function body is being generated, which is where `return` returns from.
+    in_function: bool,
 }
 
 impl Default for Asm {
     fn default() -> Self {
         return Self {
-            link_files: HashSet::from(["C:/windows/system32/kernel32.dll".into()]),
+            link_files: BTreeSet::from(["C:/windows/system32/kernel32.dll".into()]),
             label_decorator: Default::default(),
             externals: vec!["ExitProcess".into()],
             text: Default::default(),
+            emit_comments: true,
+            syntax: Syntax::Nasm,
+            max_comment_len: 80,
+            div_by_zero_guard: true,
+            uses_div_by_zero_handler: false,
+            print_routines: Vec::new(),
+            warnings: Vec::new(),
+            opt_level: OptLevel::O0,
+            warn_shadowing: false,
+            dump_symbols: false,
+            symbol_dump: String::new(),
+            stack: Default::default(),
+            in_function: false,
         };
     }
 }
 
 impl Asm {
+    /// Generates `stmt`, checking in debug builds that its code leaves the stack as deep as it
+    /// found it.
     fn gen_stmt(&mut self, stmt: &Stmt, env: &mut Env) -> Result<(), CompileError> {
+        let depth = self.stack.depth();
+        self.gen_stmt_code(stmt, env)?;
+        debug_assert_eq!(
+            self.stack.depth(),
+            depth,
+            "[Asm.gen_stmt] The code of {} leaves the stack unbalanced",
+            stmt
+        );
+        return Ok(());
+    }
+    fn gen_stmt_code(&mut self, stmt: &Stmt, env: &mut Env) -> Result<(), CompileError> {
         match stmt {
-            Stmt::Declare(ident) => {
-                env.declare(ident);
-                let sym = env.get_symbol(&ident.lexeme).expect(&format!(
-                    "[AsmGen.gen] Identifier {:?} was not declared properly.",
-                    ident
-                ));
+            Stmt::Declare(decl) => {
+                self.check_shadowing(decl, env);
+                // Without an annotation or an initializer to infer from, a binding is an `i64`.
+                env.declare(decl, decl.ty.unwrap_or(Type::I64));
+                let Some(sym) = env.get_symbol(&decl.ident.lexeme) else {
+                    return Err(CompileError::Internal {
+                        message: format!("[Asm.gen_stmt] `{}` was not declared", decl.ident),
+                        location: Some(decl.ident.start),
+                    });
+                };
                 let lexeme = &sym.decorated_lexeme;
                 self.stmt("");
-                self.comment(format!("let {}", lexeme));
-                self.stmt(format!("sub rsp, {}", sym.size_bytes));
-            }
-            Stmt::Initialize(l_ident, rexp) => {
-                self.stmt("");
-                self.comment(format!("let {} = {}", l_ident, rexp));
-                self.stmt("");
-
-                self.rexp(rexp, env)?;
-
-                env.initialize(l_ident);
-                let l_sym = env.get_symbol(&l_ident.lexeme).expect(&format!(
-                    "[AsmGen.gen] Identifier {:?} was not initialized properly.",
-                    l_ident
-                ));
-                let lexeme = &l_sym.decorated_lexeme;
-
-                self.stmt("");
-                self.comment(&format!("let {} = {}", lexeme, rexp));
-
-                self.stmt("pop rax");
-                self.stmt(&format!("sub rsp, {}", l_sym.size_bytes));
-                self.stmt(&format!("mov qword [rbp-{}], rax", l_sym.rbp_offset));
+                self.comment(format_args!("let {}", lexeme));
             }
+            Stmt::Initialize(decl, rexp) => self.initialize(decl, decl.ty, rexp, env)?,
             Stmt::Assign(lexp, rexp) => {
-                let LExp::Ident(l_ident) = lexp;
+                let l_ident = match lexp {
+                    LExp::Ident(ident) => ident,
+                    LExp::Field(_, _) => return Err(CompileError::NotImplemented(lexp.start())),
+                };
                 let l_sym = env.get_symbol(&l_ident.lexeme);
-                let l_sym = match l_sym {
-                    Some(sym) => sym,
+                let (lexeme, ty) = match l_sym {
+                    // An immutable binding declared without a value may still be assigned once.
+                    Some(sym) if !sym.mutable && sym.maybe_initialized => {
+                        return Err(CompileError::AssignToImmutable(l_ident.clone()))
+                    }
+                    Some(sym) => (sym.decorated_lexeme.clone(), sym.ty),
                     None => return Err(CompileError::UndeclaredIdent(l_ident.clone())),
                 };
-                let lexeme = &l_sym.decorated_lexeme;
                 self.stmt("");
-                self.comment(format!("{} = {}", lexeme, rexp));
-                self.rexp(rexp, env)?;
+                self.comment(format_args!("{} = {}", lexeme, rexp));
+                if let Some(found) = self.rexp(rexp, env)? {
+                    if found != ty {
+                        return Err(CompileError::TypeMismatch(rexp.start(), ty, found));
+                    }
+                }
+
+                // The right hand side is evaluated first so that `x = x` still reads
+                // an uninitialized `x` as an error.
+                let l_sym = env.get_symbol_mut(&l_ident.lexeme).unwrap();
+                l_sym.initialized = true;
+                l_sym.maybe_initialized = true;
 
                 self.stmt("");
-                self.comment(format!("{} = {}", lexeme, rexp));
-                self.stmt("pop rax");
-                self.stmt(&format!("mov qword [rbp-{}], rax", l_sym.rbp_offset));
+                self.comment(format_args!("{} = {}", lexeme, rexp));
+                self.ins("pop", &[Reg::Rax.into()]);
+                self.store(l_sym);
             }
             Stmt::RExp(rexp) => {
-                self.comment(format!("{}", rexp));
+                self.comment(format_args!("{}", rexp));
+                self.rexp(rexp, env)?;
+                self.stmt("");
+                self.comment(format_args!("discard {}", rexp));
+                self.ins("add", &[Reg::Rsp.into(), 8.into()]);
+            }
+            Stmt::Return(rexp) if self.in_function => {
+                self.rexp(rexp, env)?;
+                self.stmt("");
+                self.comment(format_args!("return {}", rexp));
+                self.ins("pop", &[Reg::Rax.into()]);
+                self.ins("mov", &[Reg::Rsp.into(), Reg::Rbp.into()]);
+                self.ins("pop", &[Reg::Rbp.into()]);
+                self.ins("ret", &[]);
+            }
+            // A `return` outside of a function exits the program.
+            Stmt::Exit(rexp) | Stmt::Return(rexp) => {
                 self.rexp(rexp, env)?;
+                self.stmt("");
+                self.comment(format_args!("exit {}", rexp));
+                self.ins("pop", &[Reg::Rax.into()]);
+                self.ins("mov", &[Reg::Rcx.into(), Reg::Rax.into()]);
+                self.ins("call", &[Operand::Label(String::from("ExitProcess"))]);
             }
-            Stmt::Exit(rexp) => {
+            Stmt::Print(rexp, format) => {
                 self.rexp(rexp, env)?;
                 self.stmt("");
-                self.comment(format!("exit {}", rexp));
-                self.stmt("pop rax");
-                self.stmt("mov rcx, rax");
-                self.stmt("call ExitProcess");
+                self.comment(format_args!("{} {}", format.keyword(), rexp));
+                self.ins("pop", &[Reg::Rcx.into()]);
+                self.ins("call", &[Operand::Label(String::from(format.keyword()))]);
+                if !self.print_routines.contains(format) {
+                    self.print_routines.push(*format);
+                }
             }
-            Stmt::Block(block) => self.gen_block(block, Some(env))?,
+            Stmt::Block(block) => self.gen_block(block, env)?,
             Stmt::If(rexp, if_block, else_block) => {
+                if rexp.eval_const().is_some() {
+                    self.warnings
+                        .push(CompileWarning::ConstantCondition(rexp.span()));
+                }
                 if else_block.is_none() {
                     let end_if_label = self
                         .label_decorator
                         .decorate_and_increment(String::from("end_if"));
 
-                    self.rexp(rexp, env)?;
-
-                    self.comment(format!("{} == 0", rexp));
-                    self.stmt("pop rax");
-                    self.stmt("test rax, rax");
-                    self.stmt(format!("jz {}", end_if_label));
+                    self.jump_unless(rexp, &end_if_label, env)?;
 
+                    let pending = env.pending_initializations();
                     self.comment("if");
-                    self.gen_block(if_block, Some(env))?;
+                    self.gen_block(if_block, env)?;
                     self.label(end_if_label);
+
+                    // Skipping the block initializes nothing.
+                    let if_branch = env.take_initializations(&pending);
+                    env.merge_initializations(&pending, &[if_branch, vec![false; pending.len()]]);
                 } else {
                     let else_stmt = else_block.as_ref().unwrap().as_ref();
 
@@ -265,22 +566,19 @@ impl Asm {
                         .label_decorator
                         .decorate_and_increment(String::from("else_end"));
 
-                    self.rexp(rexp, env)?;
-
-                    self.comment(format!("{} == 0", rexp));
-                    self.stmt("pop rax");
-                    self.stmt("test rax, rax");
-                    self.stmt(format!("jz {}", else_start_label));
+                    self.jump_unless(rexp, &else_start_label, env)?;
 
+                    let pending = env.pending_initializations();
                     self.comment("if");
-                    self.gen_block(if_block, Some(env))?;
-                    self.stmt(format!("jmp {}", else_end_label));
+                    self.gen_block(if_block, env)?;
+                    self.ins("jmp", &[Operand::Label(else_end_label.clone())]);
+                    let if_branch = env.take_initializations(&pending);
 
                     self.label(else_start_label);
                     match else_stmt {
                         Stmt::Block(block) => {
                             self.comment("else {");
-                            self.gen_block(block, Some(env))?;
+                            self.gen_block(block, env)?;
                             self.comment("}");
                         }
                         else_if if else_stmt.is_if() => {
@@ -288,48 +586,632 @@ impl Asm {
                             self.gen_stmt(else_if, env)?;
                             self.comment("}");
                         }
-                        else_stmt => panic!(
-                            "[Display for Stmt] else_block in if contains: {:?}",
-                            else_stmt
-                        ),
+                        else_stmt => {
+                            return Err(CompileError::Internal {
+                                message: format!(
+                                    "[Asm.gen_stmt] `else` is followed by: {:?}",
+                                    else_stmt
+                                ),
+                                location: Some(rexp.start()),
+                            })
+                        }
                     }
 
                     self.label(else_end_label);
+
+                    let else_branch = env.take_initializations(&pending);
+                    env.merge_initializations(&pending, &[if_branch, else_branch]);
                 }
             }
-            _ => panic!("[Assembly Generation] Not implemented for Stmt: {}", stmt),
+            // Function bodies are generated separately by `gen`, after the top-level code.
+            Stmt::FnDef(_, _) => (),
         }
         return Ok(());
     }
-    fn gen_block(
+    /// Warns if `decl` is about to shadow a variable of an enclosing scope and the warning is
+    /// enabled.
+    fn check_shadowing(&mut self, decl: &Declaration, env: &Env) {
+        if !self.warn_shadowing {
+            return;
+        }
+        if let Some(shadowed) = env.shadowed_symbol(&decl.ident.lexeme) {
+            self.warnings.push(CompileWarning::Shadowing(
+                decl.ident.clone(),
+                shadowed.declared_at,
+            ));
+        }
+    }
+    /// Allocates the variable of `decl` and stores `rexp` in it. Its type is inferred from
+    /// `rexp` unless it is `ty`.
+    fn initialize(
         &mut self,
-        stmts: &[Stmt],
-        previous_env: Option<&Env>,
+        decl: &Declaration,
+        ty: Option<Type>,
+        rexp: &RExp,
+        env: &mut Env,
     ) -> Result<(), CompileError> {
-        let mut new_env = match previous_env {
-            None => Env::new(),
-            Some(previous_env) => Env::with_tail(previous_env),
+        self.stmt("");
+        self.comment(format_args!("let {} = {}", decl, rexp));
+        self.stmt("");
+
+        let ty = match (ty, self.rexp(rexp, env)?) {
+            (Some(expected), Some(found)) if expected != found => {
+                return Err(CompileError::TypeMismatch(rexp.start(), expected, found))
+            }
+            (ty, inferred) => ty.or(inferred).unwrap_or(Type::I64),
         };
-        self.comment("{");
-        for stmt in stmts.iter() {
-            self.gen_stmt(stmt, &mut new_env)?;
+
+        // Only defined after its initializer, so `let x = x` reads an outer `x` if there is one.
+        self.check_shadowing(decl, env);
+        env.initialize(decl, ty);
+        let Some(l_sym) = env.get_symbol(&decl.ident.lexeme) else {
+            return Err(CompileError::Internal {
+                message: format!("[Asm.initialize] `{}` was not declared", decl.ident),
+                location: Some(decl.ident.start),
+            });
+        };
+        let lexeme = &l_sym.decorated_lexeme;
+
+        self.stmt("");
+        self.comment(format_args!("let {} = {}", lexeme, rexp));
+
+        self.ins("pop", &[Reg::Rax.into()]);
+        self.store(l_sym);
+        return Ok(());
+    }
+
+    /// Generates the statements of a block, followed by `tail` for a block expression.
+    ///
+    /// A declaration doesn't allocate its variable if nothing uses it. If the variable is
+    /// assigned before anything else uses it, it is allocated by that assignment instead.
+    ///
+    /// `flush` is called before each statement, with the code of the ones before it.
+    fn gen_stmts(
+        &mut self,
+        stmts: &[Stmt],
+        tail: Option<&RExp>,
+        env: &mut Env,
+        mut flush: Option<Flush>,
+    ) -> Result<(), CompileError> {
+        let mut deferred: HashMap<usize, &Declaration> = HashMap::new();
+        for (i, stmt) in stmts.iter().enumerate() {
+            if let Some(flush) = flush.as_deref_mut() {
+                flush(self);
+            }
+            let decl = match (stmt, deferred.remove(&i)) {
+                (Stmt::Assign(_, rexp), Some(decl)) => {
+                    // Without an annotation, a declaration is an `i64` whatever it's assigned.
+                    self.initialize(decl, Some(decl.ty.unwrap_or(Type::I64)), rexp, env)?;
+                    continue;
+                }
+                (Stmt::Declare(decl), _) => decl,
+                _ => {
+                    self.gen_stmt(stmt, env)?;
+                    continue;
+                }
+            };
+            let lexeme = &decl.ident.lexeme;
+            let rest = &stmts[i + 1..];
+            // Declaring the name again ends the scope, though not before its initializer.
+            let scope_len = rest
+                .iter()
+                .position(|stmt| stmt.declares(lexeme))
+                .map_or(rest.len(), |pos| pos + 1);
+            let read_by_tail =
+                scope_len == rest.len() && tail.is_some_and(|tail| tail.reads(lexeme));
+            let first_use = rest[..scope_len]
+                .iter()
+                .position(|stmt| stmt.mentions(lexeme));
+            match first_use.map(|pos| (i + 1 + pos, &rest[pos])) {
+                None if !read_by_tail => {
+                    self.stmt("");
+                    self.comment(format_args!("let {} is never used", decl));
+                }
+                Some((pos, Stmt::Assign(LExp::Ident(ident), rexp)))
+                    if ident.lexeme == *lexeme && !rexp.reads(lexeme) =>
+                {
+                    deferred.insert(pos, decl);
+                }
+                _ => self.gen_stmt(stmt, env)?,
+            }
+        }
+        if let Some(tail) = tail {
+            self.rexp(tail, env)?;
         }
+        return Ok(());
+    }
+
+    fn gen_block(&mut self, stmts: &[Stmt], env: &mut Env) -> Result<(), CompileError> {
+        env.push_scope();
+        self.comment("{");
+        let res = self.gen_stmts(stmts, None, env, None);
+        env.pop_scope();
+        res?;
         self.comment("}");
         return Ok(());
     }
+
+    /// Generates the body of the program or of a function, which has a frame of its own.
+    ///
+    /// Every variable of a function has its own place in the frame, so the whole frame is
+    /// allocated up front. Its size is only known once the body is generated though. Without
+    /// `flush`, the allocation is put in front of the body afterwards. With it, the body is
+    /// generated twice: once to find the size, with everything it changed put back afterwards,
+    /// and once for real.
+    fn gen_frame(
+        &mut self,
+        frame: &str,
+        stmts: &[Stmt],
+        flush: Option<Flush>,
+    ) -> Result<(), CompileError> {
+        let Some(flush) = flush else {
+            let frame_start = self.text.len();
+            let frame_size = self.frame_body(frame, stmts, None)?;
+            if frame_size > 0 {
+                let body = self.text.split_off(frame_start);
+                self.ins("sub", &[Reg::Rsp.into(), (frame_size as i64).into()]);
+                self.text.push_str(&body);
+            }
+            return Ok(());
+        };
+        flush(self);
+        let label_decorator = self.label_decorator.clone();
+        let warning_count = self.warnings.len();
+        let symbol_dump_len = self.symbol_dump.len();
+        let frame_size = self.frame_body(frame, stmts, Some(&mut |asm| asm.text.clear()))?;
+        self.text.clear();
+        self.label_decorator = label_decorator;
+        self.warnings.truncate(warning_count);
+        self.symbol_dump.truncate(symbol_dump_len);
+
+        if frame_size > 0 {
+            self.ins("sub", &[Reg::Rsp.into(), (frame_size as i64).into()]);
+        }
+        self.frame_body(frame, stmts, Some(flush))?;
+        return Ok(());
+    }
+
+    /// Generates `stmts` in a new frame and returns the size it has to be allocated with.
+    fn frame_body(
+        &mut self,
+        frame: &str,
+        stmts: &[Stmt],
+        flush: Option<Flush>,
+    ) -> Result<usize, CompileError> {
+        let mut env = Env::new();
+        self.comment("{");
+        self.gen_stmts(stmts, None, &mut env, flush)?;
+        self.comment("}");
+        if self.dump_symbols {
+            self.dump_frame_symbols(frame, &env);
+        }
+        return Ok(env.frame_size.next_multiple_of(16));
+    }
+
+    /// Adds the symbols of `env` to `symbol_dump`, in the order of their stack slots.
+    fn dump_frame_symbols(&mut self, frame: &str, env: &Env) {
+        let mut symbols: Vec<(&str, &Symbol)> = env.all_symbols().collect();
+        symbols.sort_by_key(|(_, sym)| sym.rbp_offset);
+        self.symbol_dump.push_str(&format!("{}:\n", frame));
+        for (name, sym) in symbols {
+            self.symbol_dump.push_str(&format!(
+                "    {}: {} [rbp-{}]\n",
+                name, sym.ty, sym.rbp_offset
+            ));
+        }
+    }
+    /// Clears everything generated so far, so that the next `gen` starts from scratch as if on a
+    /// new `Asm`. Options like `emit_comments` are kept, as is the memory of `text`.
+    pub fn reset(&mut self) {
+        let defaults = Self::default();
+        self.link_files = defaults.link_files;
+        self.label_decorator = defaults.label_decorator;
+        self.externals = defaults.externals;
+        self.text.clear();
+        self.uses_div_by_zero_handler = defaults.uses_div_by_zero_handler;
+        self.print_routines.clear();
+        self.warnings.clear();
+        self.symbol_dump.clear();
+        self.stack = defaults.stack;
+        self.in_function = defaults.in_function;
+    }
     pub fn gen(&mut self, stmts: &[Stmt]) -> Result<(), CompileError> {
+        self.declare_externals(stmts);
+        self.gen_program(stmts, None)?;
+        if self.opt_level >= OptLevel::O1 {
+            let balance = cfg!(debug_assertions).then(|| self.stack_balance());
+            self.peephole();
+            self.reuse_loads();
+            debug_assert_eq!(
+                balance,
+                Some(self.stack_balance()),
+                "[Asm.gen] Optimizing changed how deep the code leaves the stack"
+            );
+        }
+        return Ok(());
+    }
+
+    /// How deep the code generated so far leaves the stack, see `StackTracker::verify`.
+    fn stack_balance(&self) -> Result<(), i64> {
+        let instructions = self
+            .text
+            .lines()
+            .filter(|line| line.starts_with(' '))
+            .map(|line| line.trim())
+            .filter(|instruction| !instruction.is_empty() && !instruction.starts_with(';'));
+        return StackTracker::verify(instructions);
+    }
+
+    /// Like `gen` followed by `write_to`, but the code is written to `w` as it's generated,
+    /// instead of all of it being kept in `text`. Only about one top-level statement is held
+    /// at a time, so the memory used doesn't grow with the size of the program.
+    ///
+    /// The output is the same as with `gen` at `O0`. The passes of `O1` need all of the code at
+    /// once, so `opt_level` isn't looked at. Every frame is generated twice, see `gen_frame`.
+    pub fn gen_streaming<W: Write>(
+        &mut self,
+        stmts: &[Stmt],
+        w: &mut W,
+    ) -> Result<(), StreamError> {
+        self.declare_externals(stmts);
+        self.write_header(w).map_err(StreamError::Io)?;
+        // After a failed write, the rest of the code is still generated but thrown away.
+        let mut write_error = None;
+        let mut flush = |asm: &mut Asm| {
+            if write_error.is_none() {
+                write_error = asm.write_text(w).err();
+            }
+            asm.text.clear();
+        };
+        let res = self.gen_program(stmts, Some(&mut flush));
+        flush(self);
+        if let Some(err) = write_error {
+            return Err(StreamError::Io(err));
+        }
+        return res.map_err(StreamError::Compile);
+    }
+
+    fn gen_program(
+        &mut self,
+        stmts: &[Stmt],
+        mut flush: Option<Flush>,
+    ) -> Result<(), CompileError> {
         self.label("_start");
-        self.stmt("mov rbp, rsp");
+        self.ins("mov", &[Reg::Rbp.into(), Reg::Rsp.into()]);
+
+        self.gen_frame("_start", stmts, reborrow(&mut flush))?;
+
+        let has_main = stmts
+            .iter()
+            .any(|stmt| matches!(stmt, Stmt::FnDef(ident, _) if ident.lexeme == "main"));
+        self.stmt("");
+        if has_main {
+            self.comment("exit main()");
+            self.ins("call", &[Operand::Label(String::from("fn_main"))]);
+            self.ins("mov", &[Reg::Rcx.into(), Reg::Rax.into()]);
+        } else {
+            self.comment("exit 0");
+            self.ins("xor", &[Reg::Rcx.into(), Reg::Rcx.into()]);
+        }
+        self.ins("call", &[Operand::Label(String::from("ExitProcess"))]);
+
+        for stmt in stmts.iter() {
+            if let Stmt::FnDef(ident, body) = stmt {
+                self.gen_fn(ident, body, reborrow(&mut flush))?;
+            }
+        }
+
+        // The runtime routines go last, since function bodies may call them as well.
+        if self.uses_div_by_zero_handler {
+            self.stmt("");
+            self.label(DIV_BY_ZERO_LABEL);
+            self.comment(format_args!("exit {}", DIV_BY_ZERO_EXIT_CODE));
+            self.ins("mov", &[Reg::Rcx.into(), DIV_BY_ZERO_EXIT_CODE.into()]);
+            self.ins("call", &[Operand::Label(String::from("ExitProcess"))]);
+        }
+
+        for format in self.print_routines.clone() {
+            self.print_routine(format);
+        }
+        if !self.print_routines.is_empty() {
+            self.print_write_routine();
+        }
+        return Ok(());
+    }
+
+    /// Declares the functions that the runtime routines used by `stmts` call. The header with the
+    /// `extern` directive is written before the code when streaming, so this can't wait until
+    /// the routines are emitted.
+    fn declare_externals(&mut self, stmts: &[Stmt]) {
+        if stmts.iter().any(Stmt::prints) {
+            self.add_external("GetStdHandle");
+            self.add_external("WriteFile");
+        }
+    }
+
+    /// Emits the routine that prints `rcx` in `format`. The text is built backwards into the
+    /// buffer below `rbp`, with `r10` pointing at its start, and written by the shared tail at
+    /// `PRINT_WRITE_LABEL`. A print may run with any number of temporaries pushed, e.g. in a
+    /// block expression, so the routine aligns the stack for the calls itself.
+    fn print_routine(&mut self, format: PrintFormat) {
+        let keyword = format.keyword();
+        let buffer = Operand::Mem {
+            size: Some(Size::Byte),
+            base: Reg::R10,
+            offset: 0,
+        };
+        self.stmt("");
+        self.label(keyword);
+        self.comment(format_args!("{} rcx", keyword));
+        self.ins("push", &[Reg::Rbp.into()]);
+        self.ins("mov", &[Reg::Rbp.into(), Reg::Rsp.into()]);
+        self.ins("sub", &[Reg::Rsp.into(), PRINT_FRAME_SIZE.into()]);
+        self.ins("and", &[Reg::Rsp.into(), (-16).into()]);
+        self.ins("mov", &[Reg::R10.into(), Reg::Rbp.into()]);
+        if format != PrintFormat::Char {
+            self.ins("dec", &[Reg::R10.into()]);
+            self.ins("mov", &[buffer.clone(), (b'\n' as i64).into()]);
+        }
+        match format {
+            PrintFormat::Decimal => {
+                let digit_label = format!("{}_digit", keyword);
+                self.ins("mov", &[Reg::Rax.into(), Reg::Rcx.into()]);
+                self.ins("test", &[Reg::Rax.into(), Reg::Rax.into()]);
+                self.ins("jns", &[Operand::Label(digit_label.clone())]);
+                // Unsigned, the magnitude of `i64::MIN` is what negating it gives.
+                self.ins("neg", &[Reg::Rax.into()]);
+                self.label(&digit_label);
+                self.ins("xor", &[Reg::Rdx.into(), Reg::Rdx.into()]);
+                self.ins("mov", &[Reg::R8.into(), 10.into()]);
+                self.ins("div", &[Reg::R8.into()]);
+                self.ins("mov", &[Reg::R9.into(), Reg::Rax.into()]);
+                self.ins(
+                    "lea",
+                    &[
+                        Reg::Rax.into(),
+                        Operand::Mem {
+                            size: None,
+                            base: Reg::Rdx,
+                            offset: b'0' as i64,
+                        },
+                    ],
+                );
+                self.ins("dec", &[Reg::R10.into()]);
+                self.ins("mov", &[buffer.clone(), Reg::Al.into()]);
+                self.ins("mov", &[Reg::Rax.into(), Reg::R9.into()]);
+                self.ins("test", &[Reg::Rax.into(), Reg::Rax.into()]);
+                self.ins("jnz", &[Operand::Label(digit_label)]);
+                self.ins("test", &[Reg::Rcx.into(), Reg::Rcx.into()]);
+                self.ins("jns", &[Operand::Label(String::from(PRINT_WRITE_LABEL))]);
+                self.ins("dec", &[Reg::R10.into()]);
+                self.ins("mov", &[buffer, (b'-' as i64).into()]);
+            }
+            // Negative values are written as their two's complement.
+            PrintFormat::Hex => {
+                let digit_label = format!("{}_digit", keyword);
+                let store_label = format!("{}_store", keyword);
+                self.label(&digit_label);
+                self.ins("mov", &[Reg::Rax.into(), Reg::Rcx.into()]);
+                self.ins("and", &[Reg::Rax.into(), 15.into()]);
+                self.ins("add", &[Reg::Rax.into(), (b'0' as i64).into()]);
+                self.ins("cmp", &[Reg::Rax.into(), (b'9' as i64).into()]);
+                self.ins("jbe", &[Operand::Label(store_label.clone())]);
+                self.ins("add", &[Reg::Rax.into(), ((b'a' - b'9' - 1) as i64).into()]);
+                self.label(&store_label);
+                self.ins("dec", &[Reg::R10.into()]);
+                self.ins("mov", &[buffer, Reg::Al.into()]);
+                self.ins("shr", &[Reg::Rcx.into(), 4.into()]);
+                self.ins("jnz", &[Operand::Label(digit_label)]);
+            }
+            PrintFormat::Char => {
+                self.ins("dec", &[Reg::R10.into()]);
+                self.ins("mov", &[Reg::Rax.into(), Reg::Rcx.into()]);
+                self.ins("mov", &[buffer, Reg::Al.into()]);
+            }
+        }
+        self.ins("jmp", &[Operand::Label(String::from(PRINT_WRITE_LABEL))]);
+    }
+
+    /// Emits the tail of the print routines, which writes the text from `r10` up to `rbp` to
+    /// stdout and returns from the routine.
+    fn print_write_routine(&mut self) {
+        let start = Operand::Mem {
+            size: Some(Size::Qword),
+            base: Reg::Rbp,
+            offset: -40,
+        };
+        self.stmt("");
+        self.label(PRINT_WRITE_LABEL);
+        self.comment("WriteFile(GetStdHandle(STD_OUTPUT_HANDLE), r10, rbp - r10, &written, 0)");
+        self.ins("mov", &[start.clone(), Reg::R10.into()]);
+        self.ins("mov", &[Reg::Rcx.into(), STD_OUTPUT_HANDLE.into()]);
+        self.ins("call", &[Operand::Label(String::from("GetStdHandle"))]);
+        self.ins("mov", &[Reg::Rcx.into(), Reg::Rax.into()]);
+        self.ins("mov", &[Reg::Rdx.into(), start]);
+        self.ins("mov", &[Reg::R8.into(), Reg::Rbp.into()]);
+        self.ins("sub", &[Reg::R8.into(), Reg::Rdx.into()]);
+        self.ins(
+            "lea",
+            &[
+                Reg::R9.into(),
+                Operand::Mem {
+                    size: None,
+                    base: Reg::Rbp,
+                    offset: -48,
+                },
+            ],
+        );
+        self.ins(
+            "mov",
+            &[
+                Operand::Mem {
+                    size: Some(Size::Qword),
+                    base: Reg::Rsp,
+                    offset: 32,
+                },
+                0.into(),
+            ],
+        );
+        self.ins("call", &[Operand::Label(String::from("WriteFile"))]);
+        self.ins("mov", &[Reg::Rsp.into(), Reg::Rbp.into()]);
+        self.ins("pop", &[Reg::Rbp.into()]);
+        self.ins("ret", &[]);
+    }
+
+    /// Replaces a `push` that is directly followed by a `pop` with a `mov`, or with nothing if
+    /// both name the same register. Comments and blank lines in between are kept, labels aren't
+    /// looked past since they may be jumped to.
+    fn peephole(&mut self) {
+        let is_instruction = |line: &str| {
+            let line = line.trim();
+            return !line.is_empty() && !line.starts_with(';');
+        };
+        let mut lines: Vec<Option<String>> = self.text.lines().map(|l| Some(l.into())).collect();
+        for i in 0..lines.len() {
+            let Some(push) = lines[i]
+                .as_ref()
+                .and_then(|l| l.trim().strip_prefix("push "))
+            else {
+                continue;
+            };
+            let src = String::from(push);
+            let next =
+                (i + 1..lines.len()).find(|j| lines[*j].as_deref().map_or(false, is_instruction));
+            let Some(j) = next else {
+                continue;
+            };
+            let Some(dst) = lines[j].as_ref().unwrap().trim().strip_prefix("pop ") else {
+                continue;
+            };
+            let dst = String::from(dst);
+            // Labels aren't indented, instructions are.
+            if lines[i..j].iter().flatten().any(|l| !l.starts_with(' ')) {
+                continue;
+            }
+            lines[j] = None;
+            lines[i] = match src == dst {
+                true => None,
+                false => Some(format!("    mov {}, {}", dst, src)),
+            };
+        }
+        self.text = lines.into_iter().flatten().map(|l| l + "\n").collect();
+    }
+
+    /// Drops a load of a stack slot into a register that already holds its value, or turns it
+    /// into a `mov` from a register that does. What the registers hold is only known within a
+    /// basic block, so it's forgotten at labels, jumps and calls. Any write to memory forgets
+    /// every slot, since slots of different sizes may overlap.
+    fn reuse_loads(&mut self) {
+        // Sub-registers are written through the register they're part of.
+        let full_register = |reg: &str| -> Option<&'static str> {
+            match reg {
+                "rax" | "eax" | "al" => return Some("rax"),
+                "rbx" => return Some("rbx"),
+                "rcx" => return Some("rcx"),
+                "rdx" => return Some("rdx"),
+                "rsp" => return Some("rsp"),
+                "rbp" => return Some("rbp"),
+                _ => return None,
+            }
+        };
+        // The load each register holds the result of, e.g. `mov qword [rbp-8]`.
+        let mut held: HashMap<&str, String> = HashMap::new();
+        let mut lines = Vec::new();
+        for line in self.text.lines() {
+            let instruction = line.trim();
+            // Labels aren't indented, instructions are.
+            if !line.starts_with(' ') {
+                held.clear();
+            }
+            if !line.starts_with(' ') || instruction.is_empty() || instruction.starts_with(';') {
+                lines.push(String::from(line));
+                continue;
+            }
+            let (mnemonic, operands) = instruction.split_once(' ').unwrap_or((instruction, ""));
+            let operands: Vec<&str> = operands.split(", ").collect();
+            let dst = operands.first().and_then(|dst| full_register(dst));
+            match (mnemonic, operands.as_slice(), dst) {
+                ("mov" | "movsxd", [_, src], Some(dst)) if src.contains('[') => {
+                    let load = format!("{} {}", mnemonic, src);
+                    let holder = held.iter().find(|(_, held)| **held == load);
+                    match holder.map(|(reg, _)| *reg) {
+                        Some(reg) if reg == dst => (),
+                        Some(reg) => lines.push(format!("    mov {}, {}", dst, reg)),
+                        None => lines.push(String::from(line)),
+                    }
+                    held.insert(dst, load);
+                    continue;
+                }
+                // The slots are addressed through `rbp`, which `rsp` has no bearing on.
+                (_, _, Some("rsp")) => (),
+                (_, _, Some("rbp")) => held.clear(),
+                // After a store, the register holds what loading the slot would.
+                ("mov", [slot, src], None) if slot.starts_with("qword [") => {
+                    held.clear();
+                    if let Some(src) = full_register(src).filter(|reg| reg == src) {
+                        held.insert(src, format!("mov {}", slot));
+                    }
+                }
+                ("mov", [_, src], Some(dst)) => match held.get(src).cloned() {
+                    Some(load) => _ = held.insert(dst, load),
+                    None => _ = held.remove(dst),
+                },
+                ("push" | "cmp" | "test", _, _) => (),
+                ("mul" | "imul" | "div" | "idiv", [_], _) => {
+                    held.remove("rax");
+                    held.remove("rdx");
+                }
+                ("cqo", _, _) => _ = held.remove("rdx"),
+                (mnemonic, _, _) if mnemonic.starts_with('j') => held.clear(),
+                (_, _, Some(dst)) => _ = held.remove(dst),
+                // Stores, calls and anything else that isn't known to leave the slots alone.
+                _ => held.clear(),
+            }
+            lines.push(String::from(line));
+        }
+        self.text = lines.into_iter().map(|l| l + "\n").collect();
+    }
+
+    fn gen_fn(
+        &mut self,
+        ident: &Identifier,
+        body: &[Stmt],
+        flush: Option<Flush>,
+    ) -> Result<(), CompileError> {
+        let label = format!("fn_{}", ident.lexeme);
+        self.stmt("");
+        self.label(&label);
+        self.ins("push", &[Reg::Rbp.into()]);
+        self.ins("mov", &[Reg::Rbp.into(), Reg::Rsp.into()]);
 
-        self.gen_block(stmts, None)?;
+        self.in_function = true;
+        let res = self.gen_frame(&label, body, flush);
+        self.in_function = false;
+        res?;
 
         self.stmt("");
-        self.comment("exit 0");
-        self.stmt("xor rcx, rcx");
-        self.stmt("call ExitProcess");
+        self.comment("return 0");
+        self.ins("xor", &[Reg::Rax.into(), Reg::Rax.into()]);
+        self.ins("mov", &[Reg::Rsp.into(), Reg::Rbp.into()]);
+        self.ins("pop", &[Reg::Rbp.into()]);
+        self.ins("ret", &[]);
         return Ok(());
     }
+    /// Emits one instruction, e.g. `mov rax, 5` for `ins("mov", &[Reg::Rax.into(), 5.into()])`.
+    fn ins(&mut self, mnemonic: &str, operands: &[Operand]) {
+        let operands: Vec<String> = operands.iter().map(|op| op.to_string()).collect();
+        let instruction = match operands.is_empty() {
+            true => String::from(mnemonic),
+            false => format!("{} {}", mnemonic, operands.join(", ")),
+        };
+        self.stack.step(&instruction);
+        self.stmt(instruction);
+    }
+
     fn stmt<'a>(&mut self, stmt: impl AsRef<str>) {
+        if !self.emit_comments && stmt.as_ref().is_empty() {
+            return;
+        }
         self.text.push_str("    ");
         self.text.push_str(stmt.as_ref());
         self.text.push('\n');
@@ -340,171 +1222,2083 @@ impl Asm {
         self.text.push_str(":\n");
     }
 
-    fn comment(&mut self, comment: impl AsRef<str>) {
+    fn comment(&mut self, comment: impl Display) {
+        if !self.emit_comments {
+            return;
+        }
         self.text.push_str("    ; ");
-        self.text.push_str(comment.as_ref());
+        let mut writer = CommentWriter {
+            text: &mut self.text,
+            remaining: self.max_comment_len,
+            truncated: false,
+        };
+        // The only error is the one `CommentWriter` returns to stop formatting at the budget.
+        let _ = std::fmt::Write::write_fmt(&mut writer, format_args!("{}", comment));
+        if writer.truncated {
+            for _ in 0..ELLIPSIS.len().min(self.max_comment_len) {
+                self.text.pop();
+            }
+            self.text.push_str(ELLIPSIS);
+        }
         self.text.push('\n');
     }
 
+    /// Writes `{filename}.asm`, or `{filename}.s` for GAS.
     pub fn write_to_file(&self, filename: impl AsRef<str>) -> std::io::Result<()> {
         let filename = filename.as_ref();
-        let mut outfile = File::create(format!("{filename}.asm"))?;
-        outfile.write_all("default rel\nglobal _start\n".as_bytes())?;
+        let extension = self.syntax.extension();
+        let mut outfile = File::create(format!("{filename}.{extension}"))?;
+        return self.write_to(&mut outfile);
+    }
 
-        outfile.write_all("extern ".as_bytes())?;
-        for ext in self.externals.iter() {
-            outfile.write_all(ext.as_bytes())?;
-            outfile.write_all(", ".as_bytes())?;
+    /// Writes the complete assembly file, headers included, to `w`.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        self.write_header(w)?;
+        self.write_text(w)?;
+        return Ok(());
+    }
+
+    /// The complete assembly file, as `write_to` would write it.
+    pub fn into_asm(self) -> String {
+        let mut buf = Vec::new();
+        self.write_to(&mut buf)
+            .expect("[Asm.into_asm] Writing to a Vec can't fail!");
+        return String::from_utf8(buf).expect("[Asm.into_asm] The assembly isn't valid UTF-8!");
+    }
+
+    /// Writes the code generated so far in `syntax`.
+    fn write_text<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        match self.syntax {
+            Syntax::Nasm => w.write_all(self.text.as_bytes())?,
+            Syntax::Gas => w.write_all(gas::translate(&self.text).as_bytes())?,
+        }
+        return Ok(());
+    }
+
+    /// Writes what goes in front of the code.
+    fn write_header<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        if self.syntax == Syntax::Gas {
+            // Symbols that aren't defined are external anyway.
+            w.write_all(".globl _start\n.text\n".as_bytes())?;
+            return Ok(());
         }
-        outfile.write_all("\n".as_bytes())?;
+        w.write_all("default rel\nglobal _start\n".as_bytes())?;
 
-        outfile.write_all("section .text\n".as_bytes())?;
-        outfile.write_all(self.text.as_bytes())?;
+        // An empty `extern` directive is a syntax error in NASM.
+        if !self.externals.is_empty() {
+            w.write_all(format!("extern {}\n", self.externals.join(", ")).as_bytes())?;
+        }
 
+        w.write_all("section .text\n".as_bytes())?;
         return Ok(());
     }
 
-    pub fn compile(&self, filename: impl AsRef<str>) -> std::io::Result<()> {
+    /// Links the program against `path` as well, e.g. a DLL whose functions it calls.
+    pub fn add_link_file(&mut self, path: impl Into<String>) {
+        self.link_files.insert(path.into());
+    }
+
+    /// Declares `symbol` as `extern` so that the program can call it. Symbols already declared
+    /// are left as they are.
+    pub fn add_external(&mut self, symbol: impl Into<String>) {
+        let symbol = symbol.into();
+        if !self.externals.contains(&symbol) {
+            self.externals.push(symbol);
+        }
+    }
+
+    /// Writes the assembly file and builds it into `{filename}.exe`. Only failing to write the
+    /// file or to start a tool is an error, a tool that fails is recorded in the report.
+    pub fn compile(&self, filename: impl AsRef<str>) -> std::io::Result<BuildReport> {
+        self.write_to_file(filename.as_ref())?;
+        return self.build(filename);
+    }
+    /// Like `compile`, but for code that was already written to the assembly file, e.g. by
+    /// `gen_streaming`.
+    pub fn build(&self, filename: impl AsRef<str>) -> std::io::Result<BuildReport> {
         let filename = filename.as_ref();
-        self.write_to_file(filename)?;
-        Command::new("nasm")
-            .args([
-                "-f",
-                "win64",
-                &format!("{filename}.asm"),
-                "-o",
-                &format!("{filename}.obj"),
-            ])
-            .output()?;
+        let mut report = BuildReport {
+            asm_path: format!("{filename}.{}", self.syntax.extension()),
+            obj_path: format!("{filename}.obj"),
+            exe_path: format!("{filename}.exe"),
+            assembler_stderr: String::new(),
+            gcc_stderr: String::new(),
+            success: false,
+        };
+
+        let (status, stderr) = capture(&mut self.assemble_command(filename))?;
+        report.assembler_stderr = stderr;
+        if !status.success() {
+            return Ok(report);
+        }
+        let (status, stderr) = self.link(filename)?;
+        report.gcc_stderr = stderr;
+        report.success = status.success();
+        return Ok(report);
+    }
+
+    /// Assembles `{stem}.asm` into `{stem}.obj` with nasm, or `{stem}.s` with `as` for GAS.
+    pub fn assemble(&self, stem: impl AsRef<str>) -> std::io::Result<()> {
+        return run(&mut self.assemble_command(stem));
+    }
+
+    fn assemble_command(&self, stem: impl AsRef<str>) -> Command {
+        let stem = stem.as_ref();
+        let extension = self.syntax.extension();
+        let mut command = Command::new(self.syntax.assembler());
+        if self.syntax == Syntax::Nasm {
+            command.args(["-f", "win64"]);
+        }
+        command.args([&format!("{stem}.{extension}"), "-o", &format!("{stem}.obj")]);
+        return command;
+    }
+
+    /// Links `{stem}.obj` against the link files into `{stem}.exe` with gcc, returning gcc's exit
+    /// status and stderr.
+    fn link(&self, stem: impl AsRef<str>) -> std::io::Result<(ExitStatus, String)> {
+        return capture(&mut self.link_command(stem));
+    }
 
+    fn link_command(&self, stem: impl AsRef<str>) -> Command {
+        let stem = stem.as_ref();
         let mut gcc_args = vec![
             "-g".into(),
             "-nostdlib".into(),
             "-o".into(),
-            format!("{filename}.exe"),
-            format!("{filename}.obj"),
+            format!("{stem}.exe"),
+            format!("{stem}.obj"),
         ];
         gcc_args.extend(self.link_files.iter().map(|l| l.clone()));
 
-        Command::new("gcc").args(gcc_args).output()?;
-        return Ok(());
+        let mut command = Command::new("gcc");
+        command.args(gcc_args);
+        return command;
     }
 
-    fn term(&mut self, term: &Term, env: &Env) -> Result<(), CompileError> {
+    /// Pushes the value of `term` and returns its type, see `rexp`.
+    fn term(&mut self, term: &Term, env: &mut Env) -> Result<Option<Type>, CompileError> {
         match term {
-            Term::LExp(LExp::Ident(ident)) => self.ident(ident, env),
-            Term::IntLit(intlit) => self.intlit(intlit),
+            Term::LExp(LExp::Ident(ident)) => return Ok(Some(self.ident(ident, env)?)),
+            Term::LExp(lexp @ LExp::Field(_, _)) => {
+                return Err(CompileError::NotImplemented(lexp.start()))
+            }
+            Term::IntLit(intlit) => {
+                self.intlit(intlit)?;
+                return Ok(None);
+            }
             Term::Neg(inner_term) => {
-                self.term(inner_term, env)?;
-                self.stmt("pop rax");
+                let ty = self.term(inner_term, env)?;
+                self.ins("pop", &[Reg::Rax.into()]);
                 self.stmt("");
-                self.comment(format!("{}", term));
-                self.stmt("neg rax");
-                self.stmt("push rax");
-                return Ok(());
+                self.comment(Shallow::Term(term));
+                self.ins("neg", &[Reg::Rax.into()]);
+                self.wrap(ty);
+                self.ins("push", &[Reg::Rax.into()]);
+                return Ok(ty);
+            }
+            Term::Not(inner_term) => {
+                self.term(inner_term, env)?;
+                self.ins("pop", &[Reg::Rax.into()]);
+                self.stmt("");
+                self.comment(Shallow::Term(term));
+                self.ins("test", &[Reg::Rax.into(), Reg::Rax.into()]);
+                self.ins("sete", &[Reg::Al.into()]);
+                self.ins("and", &[Reg::Rax.into(), 255.into()]);
+                self.ins("push", &[Reg::Rax.into()]);
+                return Ok(None);
             }
-            Term::Bracketed(rexp) => self.rexp(rexp, env),
+            Term::Bracketed(rexp) => return self.rexp(rexp, env),
+            Term::Group(_) => return Err(CompileError::NotImplemented(term.start())),
+            Term::Call(ident, args) => {
+                self.call(term, ident, args, env)?;
+                return Ok(Some(Type::I64));
+            }
+        }
+    }
 
-            _ => panic!("[Assembly Generation] Not implemented for term: {}", term),
+    /// Pushes the result of calling `ident` with `args`, which has to be an `Intrinsic`.
+    fn call(
+        &mut self,
+        term: &Term,
+        ident: &Identifier,
+        args: &[RExp],
+        env: &mut Env,
+    ) -> Result<(), CompileError> {
+        let intrinsic = Intrinsic::resolve(ident, args)?;
+        for arg in args.iter() {
+            if let Some(found @ Type::I32) = self.rexp(arg, env)? {
+                return Err(CompileError::TypeMismatch(arg.start(), Type::I64, found));
+            }
         }
+
+        self.stmt("");
+        self.comment(Shallow::Term(term));
+        match intrinsic {
+            Intrinsic::SatAdd | Intrinsic::SatSub => {
+                self.ins("pop", &[Reg::Rbx.into()]);
+                self.ins("pop", &[Reg::Rax.into()]);
+                // Either operation only overflows past the bound on the side of the left
+                // operand's sign, so rcx gets `i64::MAX` if it's positive and `i64::MIN` if not.
+                self.ins("mov", &[Reg::Rcx.into(), Reg::Rax.into()]);
+                self.ins("sar", &[Reg::Rcx.into(), 63.into()]);
+                self.ins("btc", &[Reg::Rcx.into(), 63.into()]);
+                self.ins("not", &[Reg::Rcx.into()]);
+                let mnemonic = match intrinsic {
+                    Intrinsic::SatAdd => "add",
+                    Intrinsic::SatSub => "sub",
+                };
+                self.ins(mnemonic, &[Reg::Rax.into(), Reg::Rbx.into()]);
+                self.ins("cmovo", &[Reg::Rax.into(), Reg::Rcx.into()]);
+            }
+        }
+        self.ins("push", &[Reg::Rax.into()]);
+        return Ok(());
     }
 
-    fn ident(&mut self, ident: &Identifier, env: &Env) -> Result<(), CompileError> {
+    /// Pushes the value of `ident` and returns its type.
+    fn ident(&mut self, ident: &Identifier, env: &Env) -> Result<Type, CompileError> {
         let sym = env.get_symbol(&ident.lexeme);
         let sym = match sym {
             Some(sym) => sym,
             None => return Err(CompileError::UndeclaredIdent(ident.clone())),
         };
+        // Reading a symbol that was never assigned would load whatever happens to be on the stack.
+        if !sym.initialized {
+            return Err(CompileError::UninitializedIdent(ident.clone()));
+        }
         let lexeme = &sym.decorated_lexeme;
 
         self.stmt("");
         self.comment(lexeme);
-        self.stmt(format!("push qword [rbp-{}]", sym.rbp_offset));
-        return Ok(());
+        match sym.ty {
+            // Going through a register lets `reuse_loads` find the value there again.
+            Type::I64 if self.opt_level >= OptLevel::O1 => {
+                self.ins(
+                    "mov",
+                    &[Reg::Rax.into(), Operand::stack(Size::Qword, sym.rbp_offset)],
+                );
+                self.ins("push", &[Reg::Rax.into()]);
+            }
+            Type::I64 => self.ins("push", &[Operand::stack(Size::Qword, sym.rbp_offset)]),
+            Type::I32 => {
+                self.ins(
+                    "movsxd",
+                    &[Reg::Rax.into(), Operand::stack(Size::Dword, sym.rbp_offset)],
+                );
+                self.ins("push", &[Reg::Rax.into()]);
+            }
+        }
+        return Ok(sym.ty);
+    }
+
+    /// Stores `rax` into the stack slot of `sym`, truncating it to the symbol's width.
+    fn store(&mut self, sym: &Symbol) {
+        let src = match sym.ty {
+            Type::I64 => Reg::Rax,
+            Type::I32 => Reg::Eax,
+        };
+        let dst = Operand::stack(Size::from(sym.ty), sym.rbp_offset);
+        self.ins("mov", &[dst, src.into()]);
     }
 
     fn intlit(&mut self, intlit: &IntLiteral) -> Result<(), CompileError> {
         self.stmt("");
         self.comment(&intlit.lexeme);
-        self.stmt(format!("mov rax, {}", intlit.lexeme));
-        self.stmt("push rax");
+        self.ins("mov", &[Reg::Rax.into(), intlit.wrapping_value().into()]);
+        self.ins("push", &[Reg::Rax.into()]);
         return Ok(());
     }
 
+    /// Pushes the result of `bin_exp`, which `asm_gen` computes into `rax`, and returns its
+    /// type. Comparisons have no type of their own.
     fn binary_operator<F>(
         &mut self,
         bin_exp: &RExp,
         lhs: &RExp,
         rhs: &RExp,
-        env: &Env,
+        env: &mut Env,
         asm_gen: &mut F,
-    ) -> Result<(), CompileError>
+    ) -> Result<Option<Type>, CompileError>
     where
-        F: FnMut(&mut Self) -> (),
+        F: FnMut(&mut Self, Signedness),
     {
-        self.rexp(lhs, env)?;
-        self.rexp(rhs, env)?;
+        let ty = self.operands(bin_exp, lhs, rhs, env)?;
+        // Operands without a type of their own, like literals, are signed.
+        asm_gen(self, ty.map_or(Signedness::Signed, |ty| ty.signedness()));
+
+        let arithmetic = matches!(
+            bin_exp,
+            RExp::Add(_, _) | RExp::Sub(_, _) | RExp::Mul(_, _) | RExp::Div(_, _)
+        );
+        let ty = ty.filter(|_| arithmetic);
+        self.wrap(ty);
+        self.ins("push", &[Reg::Rax.into()]);
+        return Ok(ty);
+    }
+
+    /// Sign extends `eax` into `rax` if `ty` is `i32`. Values are kept sign extended to 64
+    /// bits, so this makes arithmetic on `i32`s wrap at 32 bits.
+    fn wrap(&mut self, ty: Option<Type>) {
+        if ty == Some(Type::I32) {
+            self.ins("movsxd", &[Reg::Rax.into(), Reg::Eax.into()]);
+        }
+    }
+
+    /// Evaluates the operands of `bin_exp`, leaving `lhs` in `rax` and `rhs` in `rbx`.
+    /// Returns their type, which is that of either operand that has one. They must not differ.
+    fn operands(
+        &mut self,
+        bin_exp: &RExp,
+        lhs: &RExp,
+        rhs: &RExp,
+        env: &mut Env,
+    ) -> Result<Option<Type>, CompileError> {
+        let ty = match (self.rexp(lhs, env)?, self.rexp(rhs, env)?) {
+            (Some(l_ty), Some(r_ty)) if l_ty != r_ty => {
+                return Err(CompileError::TypeMismatch(rhs.start(), l_ty, r_ty))
+            }
+            (l_ty, r_ty) => l_ty.or(r_ty),
+        };
 
         self.stmt("");
-        self.comment(&format!("{}", bin_exp));
+        self.comment(Shallow::RExp(bin_exp));
 
-        self.stmt("pop rbx");
-        self.stmt("pop rax");
+        self.ins("pop", &[Reg::Rbx.into()]);
+        self.ins("pop", &[Reg::Rax.into()]);
+        return Ok(ty);
+    }
 
-        asm_gen(self);
+    /// Jumps to `label` if `condition` is false.
+    ///
+    /// Comparisons branch on the flags set by `cmp` directly instead of materializing
+    /// a 0 or 1 first.
+    fn jump_unless(
+        &mut self,
+        condition: &RExp,
+        label: &str,
+        env: &mut Env,
+    ) -> Result<(), CompileError> {
+        let (lhs, rhs, jump) = match condition {
+            RExp::Equal(lhs, rhs) => (lhs, rhs, "jne"),
+            RExp::NotEqual(lhs, rhs) => (lhs, rhs, "je"),
+            RExp::Less(lhs, rhs) => (lhs, rhs, "jge"),
+            RExp::LessEqual(lhs, rhs) => (lhs, rhs, "jg"),
+            RExp::Greater(lhs, rhs) => (lhs, rhs, "jle"),
+            RExp::GreaterEqual(lhs, rhs) => (lhs, rhs, "jl"),
+            _ => {
+                self.rexp(condition, env)?;
 
-        self.stmt("push rax");
+                self.comment(format_args!("{} == 0", condition));
+                self.ins("pop", &[Reg::Rax.into()]);
+                self.ins("test", &[Reg::Rax.into(), Reg::Rax.into()]);
+                self.ins("jz", &[Operand::Label(String::from(label))]);
+                return Ok(());
+            }
+        };
+        self.operands(condition, lhs, rhs, env)?;
+        self.ins("cmp", &[Reg::Rax.into(), Reg::Rbx.into()]);
+        self.ins(jump, &[Operand::Label(String::from(label))]);
         return Ok(());
     }
 
-    fn rexp(&mut self, rexp: &RExp, env: &Env) -> Result<(), CompileError> {
+    /// Pushes the value of `lhs && rhs` or `lhs || rhs`. Once one side decides the result, the
+    /// rest isn't evaluated.
+    fn logical_operator(
+        &mut self,
+        rexp: &RExp,
+        lhs: &RExp,
+        rhs: &RExp,
+        env: &mut Env,
+    ) -> Result<(), CompileError> {
+        // `&&` is decided by a zero and `||` by anything else.
+        let (name, jump, decided) = match rexp {
+            RExp::And(_, _) => ("and", "jz", 0),
+            RExp::Or(_, _) => ("or", "jnz", 1),
+            _ => {
+                return Err(CompileError::Internal {
+                    message: format!("[Asm.logical_operator] Not a logical operator: {}", rexp),
+                    location: Some(rexp.start()),
+                })
+            }
+        };
+        let decided_label = self
+            .label_decorator
+            .decorate_and_increment(format!("{}_decided", name));
+        let end_label = self
+            .label_decorator
+            .decorate_and_increment(format!("{}_end", name));
+
+        let decide = |asm: &mut Self| {
+            asm.stmt("");
+            asm.comment(Shallow::RExp(rexp));
+            asm.ins("pop", &[Reg::Rax.into()]);
+            asm.ins("test", &[Reg::Rax.into(), Reg::Rax.into()]);
+            asm.ins(jump, &[Operand::Label(decided_label.clone())]);
+        };
+        self.rexp(lhs, env)?;
+        decide(self);
+        // Like the block of an `if`, `rhs` may be skipped, so it can't initialize anything.
+        let pending = env.pending_initializations();
+        self.rexp(rhs, env)?;
+        let rhs_branch = env.take_initializations(&pending);
+        env.merge_initializations(&pending, &[rhs_branch, vec![false; pending.len()]]);
+        decide(self);
+
+        self.ins("mov", &[Reg::Rax.into(), (1 - decided).into()]);
+        self.ins("jmp", &[Operand::Label(end_label.clone())]);
+        self.label(decided_label);
+        self.ins("mov", &[Reg::Rax.into(), decided.into()]);
+        self.label(end_label);
+        self.ins("push", &[Reg::Rax.into()]);
+        return Ok(());
+    }
+
+    /// Generates the statements of a block expression in a scope of their own and leaves the
+    /// value of `tail` on the stack.
+    fn block_rexp(
+        &mut self,
+        block: &[Stmt],
+        tail: &RExp,
+        env: &mut Env,
+    ) -> Result<(), CompileError> {
+        let mut block_env = Env::with_tail(env);
+        self.comment("{");
+        self.gen_stmts(block, Some(tail), &mut block_env, None)?;
+        self.comment("}");
+        return Ok(());
+    }
+
+    /// Pushes the value of `rexp` and returns its type, which is inferred from the symbols it
+    /// reads. Integer literals and comparisons have no type of their own and return `None`.
+    fn rexp(&mut self, rexp: &RExp, env: &mut Env) -> Result<Option<Type>, CompileError> {
         match rexp {
-            RExp::Add(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm| {
-                asm.stmt("add rax, rbx");
+            RExp::Add(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm, _| {
+                asm.ins("add", &[Reg::Rax.into(), Reg::Rbx.into()]);
             }),
             RExp::Term(term) => self.term(term, env),
-            RExp::Sub(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm| {
-                asm.stmt("sub rax, rbx");
-            }),
-            RExp::Mul(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm| {
-                asm.stmt("mul rbx");
-            }),
-            RExp::Div(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm| {
-                asm.stmt("xor rdx, rdx");
-                asm.stmt("div rbx");
-            }),
-            RExp::Equal(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm| {
-                asm.stmt("cmp rax, rbx");
-                asm.stmt("sete al");
-                asm.stmt("and rax, 255");
-            }),
-            RExp::NotEqual(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm| {
-                asm.stmt("cmp rax, rbx");
-                asm.stmt("setne al");
-                asm.stmt("and rax, 255");
+            RExp::Cast(inner, ty) => {
+                self.rexp(inner, env)?;
+                // Values are kept sign extended to 64 bits, so only narrowing needs any work.
+                if *ty == Type::I32 {
+                    self.stmt("");
+                    self.comment(Shallow::RExp(rexp));
+                    self.ins("pop", &[Reg::Rax.into()]);
+                    self.ins("movsxd", &[Reg::Rax.into(), Reg::Eax.into()]);
+                    self.ins("push", &[Reg::Rax.into()]);
+                }
+                return Ok(Some(*ty));
+            }
+            RExp::Sub(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm, _| {
+                asm.ins("sub", &[Reg::Rax.into(), Reg::Rbx.into()]);
             }),
-            RExp::Less(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm| {
-                asm.stmt("cmp rax, rbx");
-                asm.stmt("setl al");
-                asm.stmt("and rax, 255");
+            RExp::Mul(lhs, rhs) => {
+                self.binary_operator(rexp, lhs, rhs, env, &mut |asm, Signedness::Signed| {
+                    asm.ins("imul", &[Reg::Rbx.into()])
+                })
+            }
+            RExp::Div(lhs, rhs) => {
+                self.binary_operator(rexp, lhs, rhs, env, &mut |asm, Signedness::Signed| {
+                    if asm.div_by_zero_guard {
+                        asm.uses_div_by_zero_handler = true;
+                        asm.ins("test", &[Reg::Rbx.into(), Reg::Rbx.into()]);
+                        asm.ins("jz", &[Operand::Label(String::from(DIV_BY_ZERO_LABEL))]);
+                    }
+                    asm.ins("cqo", &[]);
+                    asm.ins("idiv", &[Reg::Rbx.into()]);
+                })
+            }
+            RExp::Equal(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm, _| {
+                asm.ins("cmp", &[Reg::Rax.into(), Reg::Rbx.into()]);
+                asm.ins("sete", &[Reg::Al.into()]);
+                asm.ins("and", &[Reg::Rax.into(), 255.into()]);
             }),
-            RExp::LessEqual(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm| {
-                asm.stmt("cmp rax, rbx");
-                asm.stmt("setle al");
-                asm.stmt("and rax, 255");
+            RExp::NotEqual(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm, _| {
+                asm.ins("cmp", &[Reg::Rax.into(), Reg::Rbx.into()]);
+                asm.ins("setne", &[Reg::Al.into()]);
+                asm.ins("and", &[Reg::Rax.into(), 255.into()]);
             }),
-            RExp::Greater(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm| {
-                asm.stmt("cmp rax, rbx");
-                asm.stmt("setg al");
-                asm.stmt("and rax, 255");
+            RExp::Less(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm, _| {
+                asm.ins("cmp", &[Reg::Rax.into(), Reg::Rbx.into()]);
+                asm.ins("setl", &[Reg::Al.into()]);
+                asm.ins("and", &[Reg::Rax.into(), 255.into()]);
             }),
-            RExp::GreaterEqual(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm| {
-                asm.stmt("cmp rax, rbx");
-                asm.stmt("setge al");
-                asm.stmt("and rax, 255");
+            RExp::LessEqual(lhs, rhs) => {
+                self.binary_operator(rexp, lhs, rhs, env, &mut |asm, _| {
+                    asm.ins("cmp", &[Reg::Rax.into(), Reg::Rbx.into()]);
+                    asm.ins("setle", &[Reg::Al.into()]);
+                    asm.ins("and", &[Reg::Rax.into(), 255.into()]);
+                })
+            }
+            RExp::Greater(lhs, rhs) => self.binary_operator(rexp, lhs, rhs, env, &mut |asm, _| {
+                asm.ins("cmp", &[Reg::Rax.into(), Reg::Rbx.into()]);
+                asm.ins("setg", &[Reg::Al.into()]);
+                asm.ins("and", &[Reg::Rax.into(), 255.into()]);
             }),
-            _ => panic!("[Assembly Generation] Not implemented for RExp: {}", rexp),
+            RExp::GreaterEqual(lhs, rhs) => {
+                self.binary_operator(rexp, lhs, rhs, env, &mut |asm, _| {
+                    asm.ins("cmp", &[Reg::Rax.into(), Reg::Rbx.into()]);
+                    asm.ins("setge", &[Reg::Al.into()]);
+                    asm.ins("and", &[Reg::Rax.into(), 255.into()]);
+                })
+            }
+            RExp::And(lhs, rhs) | RExp::Or(lhs, rhs) => {
+                self.logical_operator(rexp, lhs, rhs, env)?;
+                return Ok(None);
+            }
+            // The value may read variables that only exist while the block is generated. Like
+            // a literal's, its type is left to whatever it is stored into.
+            RExp::Block(block, tail) => {
+                self.block_rexp(block, tail, env)?;
+                return Ok(None);
+            }
         }
         // return Ok(());
     }
 }
+
+/// Why `Asm::gen_streaming` failed.
+#[derive(Debug)]
+pub enum StreamError {
+    Compile(CompileError),
+    Io(std::io::Error),
+}
+
+/// What `Asm::compile` did, for tools that want to show more than whether it worked.
+#[derive(Debug)]
+pub struct BuildReport {
+    pub asm_path: String,
+    pub obj_path: String,
+    pub exe_path: String,
+    /// What the assembler printed, which may hold warnings even if it succeeded.
+    pub assembler_stderr: String,
+    /// What gcc printed. Empty if the assembler failed, since gcc isn't run then.
+    pub gcc_stderr: String,
+    /// True if the executable was built.
+    pub success: bool,
+}
+
+/// Runs `command` to completion, turning a failing exit status into an error that carries
+/// the command's stderr.
+fn run(command: &mut Command) -> std::io::Result<()> {
+    let (status, stderr) = capture(command)?;
+    if status.success() {
+        return Ok(());
+    }
+    return Err(std::io::Error::other(format!(
+        "`{}` failed with {}:\n{}",
+        command.get_program().to_string_lossy(),
+        status,
+        stderr
+    )));
+}
+
+/// Runs `command` to completion, returning its exit status along with its stderr.
+fn capture(command: &mut Command) -> std::io::Result<(ExitStatus, String)> {
+    let output = command.output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr)
+        .trim_end()
+        .to_string();
+    return Ok((output.status, stderr));
+}
+
+/// Displays an expression for the comment above the instructions of its outermost operation.
+/// Operands other than names and literals are left out as `(..)`, since they have comments of
+/// their own and displaying them again at every level would make the comments quadratic.
+enum Shallow<'a> {
+    RExp(&'a RExp),
+    Term(&'a Term),
+}
+
+impl Shallow<'_> {
+    fn term(f: &mut std::fmt::Formatter<'_>, term: &Term) -> std::fmt::Result {
+        match term {
+            Term::IntLit(_) | Term::LExp(_) => write!(f, "{}", term),
+            Term::Neg(_)
+            | Term::Not(_)
+            | Term::Bracketed(_)
+            | Term::Group(_)
+            | Term::Call(_, _) => write!(f, "(..)"),
+        }
+    }
+
+    fn operand(f: &mut std::fmt::Formatter<'_>, rexp: &RExp) -> std::fmt::Result {
+        match rexp {
+            RExp::Term(term) => Self::term(f, term),
+            _ => write!(f, "(..)"),
+        }
+    }
+}
+
+impl Display for Shallow<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rexp = match self {
+            Self::Term(Term::Neg(term)) => {
+                write!(f, "-")?;
+                return Self::term(f, term);
+            }
+            Self::Term(Term::Not(term)) => {
+                write!(f, "!")?;
+                return Self::term(f, term);
+            }
+            Self::Term(Term::Bracketed(rexp)) => rexp.as_ref(),
+            Self::Term(Term::Call(ident, args)) => {
+                write!(f, "{}(", ident)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Self::operand(f, arg)?;
+                }
+                return write!(f, ")");
+            }
+            Self::Term(term) => return Self::term(f, term),
+            Self::RExp(rexp) => *rexp,
+        };
+        match rexp {
+            RExp::Term(term) => return Shallow::Term(term).fmt(f),
+            RExp::Cast(inner, ty) => {
+                Self::operand(f, inner)?;
+                return write!(f, " as {}", ty);
+            }
+            RExp::Block(_, _) => return write!(f, "{{ .. }}"),
+            RExp::Add(lhs, rhs)
+            | RExp::Sub(lhs, rhs)
+            | RExp::Mul(lhs, rhs)
+            | RExp::Div(lhs, rhs)
+            | RExp::Equal(lhs, rhs)
+            | RExp::NotEqual(lhs, rhs)
+            | RExp::Less(lhs, rhs)
+            | RExp::LessEqual(lhs, rhs)
+            | RExp::Greater(lhs, rhs)
+            | RExp::GreaterEqual(lhs, rhs)
+            | RExp::And(lhs, rhs)
+            | RExp::Or(lhs, rhs) => {
+                Self::operand(f, lhs)?;
+                write!(f, " {} ", rexp.operator().unwrap())?;
+                return Self::operand(f, rhs);
+            }
+        }
+    }
+}
+
+const ELLIPSIS: &str = "...";
+
+/// Appends to `text` until `remaining` characters have been written, then fails so that
+/// whatever is being formatted stops early.
+struct CommentWriter<'a> {
+    text: &'a mut String,
+    remaining: usize,
+    truncated: bool,
+}
+
+impl std::fmt::Write for CommentWriter<'_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        for ch in s.chars() {
+            if self.remaining == 0 {
+                self.truncated = true;
+                return Err(std::fmt::Error);
+            }
+            self.text.push(ch);
+            self.remaining -= 1;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lexer::Location,
+        parser::Parser,
+        test_utils::{
+            assert_exit_code, assert_exit_code_at, assert_golden, assert_output, nasm_available,
+            temp_stem, toolchain_available,
+        },
+    };
+
+    fn gen_with(source: &str, mut asm: Asm) -> Result<Asm, CompileError> {
+        let mut parser = Parser::new(String::from(source));
+        parser.parse_program()?;
+        asm.gen(&parser.program.stmts)?;
+        return Ok(asm);
+    }
+
+    fn gen(source: &str) -> Result<Asm, CompileError> {
+        return gen_with(source, Asm::default());
+    }
+
+    #[test]
+    fn main_return_value_is_exit_code() {
+        let source = "fn main() {\n    return 7\n}\n";
+        let asm = gen(source).unwrap();
+        assert!(asm
+            .text
+            .contains("call fn_main\n    mov rcx, rax\n    call ExitProcess"));
+        assert!(asm
+            .text
+            .contains("fn_main:\n    push rbp\n    mov rbp, rsp"));
+
+        assert_exit_code(source, 7);
+    }
+
+    #[test]
+    fn int_literals_wrap_like_nasm() {
+        // Negating the wrapped literal gives `i64::MIN` back.
+        let asm = gen("exit -9223372036854775808\n").unwrap();
+        assert!(
+            asm.text.contains("mov rax, -9223372036854775808\n"),
+            "{}",
+            asm.text
+        );
+    }
+
+    #[test]
+    fn top_level_return_is_exit() {
+        let returned = gen("return 5\n").unwrap();
+        let exited = gen("exit 5\n").unwrap();
+        assert_eq!(returned.text, exited.text);
+        assert_exit_code("return 5\n", 5);
+
+        // Inside of a function, `return` only leaves the function.
+        let asm = gen("fn main() {\n    if 1 {\n        return 3\n    }\n}\nreturn 4\n").unwrap();
+        let main = &asm.text[asm.text.find("fn_main:").unwrap()..];
+        assert!(main.contains("pop rax\n    mov rsp, rbp"), "{}", main);
+        assert_eq!(asm.text.matches("call ExitProcess").count(), 2);
+    }
+
+    #[test]
+    fn block_expressions() {
+        let cases = [
+            ("let x = { let t = 1\n t + 1 }\nexit x\n", 2),
+            (
+                "let a = 3\nexit a * { let b = a + 1\n let c = b * 2\n c - a }\n",
+                15,
+            ),
+            ("let mut a = 1\nlet b = { a = 4\n a }\nexit a + b\n", 8),
+        ];
+        for (source, expected) in cases {
+            let asm = gen(source).unwrap();
+            assert_exit_code(source, expected);
+            // The variables of the block get slots in the frame, so the copy of `a` pushed as
+            // the left operand below the frame is left alone.
+            if source.contains("a * {") {
+                assert!(asm.text.contains("sub rsp, 32\n"), "{}", asm.text);
+                assert!(asm.text.contains("mov qword [rbp-16], rax"), "{}", asm.text);
+                assert!(asm.text.contains("mov qword [rbp-24], rax"), "{}", asm.text);
+            }
+        }
+
+        let res = gen("let x = { let t = 1\n t }\nexit t\n");
+        assert!(matches!(res, Err(CompileError::UndeclaredIdent(ident)) if ident.lexeme == "t"));
+    }
+
+    #[test]
+    fn read_before_initialization_is_rejected() {
+        let res = gen("let x\nexit x\n");
+        assert!(matches!(res, Err(CompileError::UninitializedIdent(ident)) if ident.lexeme == "x"));
+
+        let res = gen("let x\n{\n    exit x + 1\n}\n");
+        assert!(matches!(res, Err(CompileError::UninitializedIdent(_))));
+    }
+
+    #[test]
+    fn if_let_binding_is_scoped_to_the_branches() {
+        let source = "let a = 3\nif let x = a - 3 {\n    exit 1\n} else {\n    exit x + 7\n}\n";
+        gen(source).unwrap();
+        assert_exit_code(source, 7);
+        assert_exit_code("if let x = 5 exit x\n", 5);
+
+        let res = gen("if let x = 1 {\n    exit x\n}\nexit x\n");
+        assert!(matches!(res, Err(CompileError::UndeclaredIdent(ident)) if ident.lexeme == "x"));
+    }
+
+    #[test]
+    fn top_level_blocks_are_checked() {
+        let res = gen("{\n    exit x\n}\n");
+        assert!(matches!(res, Err(CompileError::UndeclaredIdent(ident))
+            if ident.lexeme == "x" && (ident.start.row, ident.start.col) == (2, 10)));
+        // The block is a scope of its own.
+        let res = gen("{\n    let x = 1\n}\nexit x\n");
+        assert!(matches!(res, Err(CompileError::UndeclaredIdent(ident)) if ident.start.row == 4));
+        gen("let x = 1\n{\n    exit x\n}\n").unwrap();
+    }
+
+    #[test]
+    fn signed_arithmetic_instructions() {
+        let asm = gen("let x: i32 = 3\nexit x * 4 / 2\n").unwrap();
+        assert!(asm.text.contains("imul rbx\n"));
+        assert!(asm.text.contains("cqo\n    idiv rbx\n"));
+        assert!(!asm.text.contains(" mul rbx\n"));
+    }
+
+    #[test]
+    fn inconsistent_ast_is_an_internal_error() {
+        let mut parser = Parser::new(String::from("if 1 {\n}\nexit 2\n"));
+        parser.parse_program().unwrap();
+        let mut stmts = core::mem::take(&mut parser.program.stmts);
+        let exit = stmts.pop().unwrap();
+        let Some(Stmt::If(rexp, if_block, None)) = stmts.pop() else {
+            panic!("Expected an `if` without `else`");
+        };
+        // The parser only ever puts a block or another `if` after `else`.
+        let stmt = Stmt::If(rexp, if_block, Some(Box::new(exit)));
+        assert!(stmt.to_string().contains("<error>"));
+        let res = Asm::default().gen(&[stmt]);
+        assert!(
+            matches!(res, Err(CompileError::Internal { location: Some(location), .. })
+            if (location.row, location.col) == (1, 4))
+        );
+    }
+
+    #[test]
+    fn self_reference_reads_before_initialization() {
+        let res = gen("let x = x + 1\n");
+        assert!(matches!(res, Err(CompileError::UndeclaredIdent(ident)) if ident.lexeme == "x"));
+        let res = gen("let x\nx = x\n");
+        assert!(matches!(res, Err(CompileError::UninitializedIdent(ident)) if ident.lexeme == "x"));
+        let res = gen("let mut x\nx = x + 1\nexit x\n");
+        assert!(matches!(res, Err(CompileError::UninitializedIdent(_))));
+
+        // The right hand side sees the binding that's being shadowed.
+        gen("let x = 1\n{\n    let x = x + 1\n    exit x\n}\n").unwrap();
+    }
+
+    #[test]
+    fn read_after_assignment_is_accepted() {
+        gen("let x\nx = 3\nexit x\n").unwrap();
+        gen("let x\n{\n    x = 3\n}\nexit x\n").unwrap();
+    }
+
+    #[test]
+    fn comments_can_be_disabled() {
+        let source =
+            "let a = 1\nlet b = a * (2 + 3)\nif b > 4 {\n    exit b\n} else {\n    exit 0\n}\n";
+        let commented = gen(source).unwrap();
+        let compact = gen_with(
+            source,
+            Asm {
+                emit_comments: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert!(commented
+            .text
+            .lines()
+            .any(|line| line.trim_start().starts_with(';')));
+        assert!(!compact.text.contains(';'));
+        assert!(compact.text.lines().all(|line| !line.trim().is_empty()));
+
+        let instructions: Vec<&str> = commented
+            .text
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with(';'))
+            .collect();
+        assert_eq!(instructions, compact.text.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn division_checks_for_zero_divisor() {
+        let source = "let d = 0\nexit 5 / d\n";
+        let asm = gen(source).unwrap();
+        assert!(asm
+            .text
+            .contains("test rbx, rbx\n    jz div_by_zero\n    cqo\n    idiv rbx\n"));
+        assert!(asm.text.contains(&format!(
+            "div_by_zero:\n    ; exit {0}\n    mov rcx, {0}\n    call ExitProcess\n",
+            DIV_BY_ZERO_EXIT_CODE
+        )));
+
+        assert_exit_code(source, DIV_BY_ZERO_EXIT_CODE);
+    }
+
+    #[test]
+    fn division_guard_can_be_disabled() {
+        let unguarded = Asm {
+            div_by_zero_guard: false,
+            ..Default::default()
+        };
+        let asm = gen_with("exit 5 / 1\n", unguarded).unwrap();
+        assert!(!asm.text.contains(DIV_BY_ZERO_LABEL));
+
+        let asm = gen("exit 5 * 1\n").unwrap();
+        assert!(!asm.text.contains(DIV_BY_ZERO_LABEL));
+    }
+
+    #[test]
+    fn comparisons_are_signed() {
+        let cases = [
+            ("-1 < 0", "setl", 1),
+            ("0 < -1", "setl", 0),
+            ("-5 <= -5", "setle", 1),
+            ("-4 <= -5", "setle", 0),
+            ("-5 > -10", "setg", 1),
+            ("-10 > -5", "setg", 0),
+            ("0 >= -1", "setge", 1),
+            ("-1 >= 0", "setge", 0),
+            ("-3 == -3", "sete", 1),
+            ("-3 == 3", "sete", 0),
+            ("-3 != 3", "setne", 1),
+            ("-3 != -3", "setne", 0),
+        ];
+        for (rexp, setcc, expected) in cases {
+            let source = format!("let a = {}\nexit a\n", rexp);
+            let asm = gen(&source).unwrap();
+            assert!(
+                asm.text.contains(&format!("    {} al\n", setcc)),
+                "{}",
+                rexp
+            );
+
+            assert_exit_code(&source, expected);
+        }
+    }
+
+    #[test]
+    fn comments_are_cut_at_the_budget() {
+        let depth = 50;
+        let source = format!("exit {}1{}\n", "(1 + ".repeat(depth), ")".repeat(depth));
+        let asm = gen(&source).unwrap();
+        let comments: Vec<&str> = asm
+            .text
+            .lines()
+            .filter_map(|line| line.strip_prefix("    ; "))
+            .collect();
+        assert!(comments.iter().all(|comment| comment.len() <= 80));
+        assert!(comments.iter().any(|comment| comment.ends_with("...")));
+        assert!(comments.contains(&"1 + 1"));
+
+        let asm = gen_with(
+            &source,
+            Asm {
+                max_comment_len: 10,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(asm.text.contains("    ; exit ((...\n"));
+    }
+
+    #[test]
+    fn operation_comments_are_shallow() {
+        let asm = gen("let a: i32 = 1\nexit -(a * 2 + (a as i32)) - -a\n").unwrap();
+        let comments: Vec<&str> = asm
+            .text
+            .lines()
+            .filter_map(|line| line.strip_prefix("    ; "))
+            .collect();
+        for comment in [
+            "a * 2",
+            "a as i32",
+            "(..) + (..)",
+            "-(..)",
+            "-a",
+            "(..) - (..)",
+        ] {
+            assert!(comments.contains(&comment), "{}", comment);
+        }
+
+        // Every node of a balanced tree is commented once, so with the whole expression shown
+        // at every level, its text would be repeated once per level.
+        fn tree(depth: usize) -> String {
+            match depth {
+                0 => String::from("a"),
+                _ => format!("({} + {})", tree(depth - 1), tree(depth - 1)),
+            }
+        }
+        let source = format!("let a = 1\nexit {}\n", tree(10));
+        let asm = gen_with(
+            &source,
+            Asm {
+                max_comment_len: usize::MAX,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let comment_len: usize = asm
+            .text
+            .lines()
+            .filter_map(|line| line.strip_prefix("    ; "))
+            .map(str::len)
+            .sum();
+        assert!(comment_len < 4 * source.len(), "{}", comment_len);
+    }
+
+    #[test]
+    fn comparison_conditions_branch_on_flags() {
+        let asm = gen("let a = 1\nlet b = 2\nif a < b {\n}\n").unwrap();
+        assert!(asm.text.contains("    cmp rax, rbx\n    jge end_if_0\n"));
+        assert!(!asm.text.contains("setl"));
+        assert!(!asm.text.contains("and rax, 255"));
+
+        let cases = [
+            ("-1 < 0", 1),
+            ("0 < -1", 2),
+            ("-5 <= -5", 1),
+            ("-5 > -10", 1),
+            ("-1 >= 0", 2),
+            ("-3 == -3", 1),
+            ("-3 != -3", 2),
+        ];
+        for (rexp, expected) in cases {
+            let source = format!("if {} {{\n    exit 1\n}} else {{\n    exit 2\n}}\n", rexp);
+            assert_exit_code(&source, expected);
+        }
+    }
+
+    #[test]
+    fn symbol_tables_can_be_listed() {
+        let stmts = |source: &str| {
+            let mut parser = Parser::new(String::from(source));
+            parser.parse_program().unwrap();
+            return parser.program.stmts;
+        };
+        let names = |symbols: Vec<(&str, &Symbol)>| {
+            let mut names: Vec<(String, usize)> = symbols
+                .into_iter()
+                .map(|(name, sym)| (String::from(name), sym.rbp_offset))
+                .collect();
+            names.sort();
+            return names;
+        };
+        let mut asm = Asm::default();
+        let mut env = Env::new();
+        for stmt in stmts(
+            "let a
+let b: i32 = 1
+let a = 2
+",
+        )
+        .iter()
+        {
+            asm.gen_stmt(stmt, &mut env).unwrap();
+        }
+        let mut inner = Env::with_tail(&mut env);
+        for stmt in stmts(
+            "let c = a
+",
+        )
+        .iter()
+        {
+            asm.gen_stmt(stmt, &mut inner).unwrap();
+        }
+
+        assert_eq!(
+            names(inner.symbols().collect()),
+            [(String::from("c_1"), 28)]
+        );
+        assert_eq!(
+            names(inner.all_symbols().collect()),
+            [
+                (String::from("a_1"), 8),
+                (String::from("a_2"), 20),
+                (String::from("b_1"), 12),
+                (String::from("c_1"), 28),
+            ]
+        );
+    }
+
+    #[test]
+    fn symbol_dump() {
+        let source = "let a = 1\nlet b: i32 = 2\n{\n    let c = 3\n}\nfn main() {\n    let a = 4\n    return a\n}\n";
+        let mut asm = Asm::default();
+        asm.dump_symbols = true;
+        let asm = gen_with(source, asm).unwrap();
+        assert_eq!(
+            asm.symbol_dump,
+            "_start:\n    a_1: i64 [rbp-8]\n    b_1: i32 [rbp-12]\nfn_main:\n    a_1: i64 [rbp-8]\n"
+        );
+        assert_eq!(gen(source).unwrap().symbol_dump, "");
+    }
+
+    #[test]
+    fn sibling_scopes_reuse_offsets() {
+        let stmts = |source: &str| {
+            let mut parser = Parser::new(String::from(source));
+            parser.parse_program().unwrap();
+            return parser.program.stmts;
+        };
+        let mut asm = Asm::default();
+        asm.warn_shadowing = true;
+        let mut env = Env::new();
+        let gen = |source: &str, asm: &mut Asm, env: &mut Env| {
+            for stmt in stmts(source).iter() {
+                asm.gen_stmt(stmt, env).unwrap();
+            }
+        };
+        gen("let a = 1\n", &mut asm, &mut env);
+
+        env.push_scope();
+        gen("let b = 2\nlet a = 3\n", &mut asm, &mut env);
+        assert_eq!(env.get_symbol("a").unwrap().rbp_offset, 24);
+        assert_eq!(env.get_symbol("b").unwrap().rbp_offset, 16);
+        assert!(matches!(
+            &asm.warnings[..],
+            [CompileWarning::Shadowing(_, _)]
+        ));
+        env.pop_scope();
+        assert_eq!(env.get_symbol("a").unwrap().rbp_offset, 8);
+        assert!(env.get_symbol("b").is_none());
+
+        env.push_scope();
+        gen("let c: i32 = 4\n", &mut asm, &mut env);
+        assert_eq!(env.get_symbol("c").unwrap().rbp_offset, 12);
+        env.pop_scope();
+
+        gen("let d = 5\n", &mut asm, &mut env);
+        assert_eq!(env.get_symbol("d").unwrap().rbp_offset, 16);
+        assert_eq!(env.frame_size, 24);
+        let mut names: Vec<&str> = env.symbols().map(|(name, _)| name).collect();
+        names.sort();
+        assert_eq!(names, ["a_1", "d_1"]);
+    }
+
+    #[test]
+    fn decorated_names_dont_collide() {
+        let source = "let x = 1\nlet x = 2\nlet x_1 = 3\nlet x_2 = 4\n";
+        let mut parser = Parser::new(String::from(source));
+        parser.parse_program().unwrap();
+        let mut asm = Asm::default();
+        let mut env = Env::new();
+        for stmt in parser.program.stmts.iter() {
+            asm.gen_stmt(stmt, &mut env).unwrap();
+        }
+        let mut names: Vec<(&str, usize)> = env
+            .symbols()
+            .map(|(name, sym)| (name, sym.rbp_offset))
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [("x_1", 8), ("x_1_1", 24), ("x_2", 16), ("x_2_1", 32)]
+        );
+        assert_eq!(env.get_symbol("x").unwrap().rbp_offset, 16);
+        assert_eq!(env.get_symbol("x_1").unwrap().rbp_offset, 24);
+
+        let source = format!("{}exit x * 100 + x_1 * 10 + x_2\n", source);
+        assert_exit_code(&source, 234);
+    }
+
+    #[test]
+    fn golden_output() {
+        for name in ["exit", "arithmetic", "if_else"] {
+            let path = format!("{}/tests/golden/{}.tl", env!("CARGO_MANIFEST_DIR"), name);
+            let source = std::fs::read_to_string(path).unwrap();
+            let asm = gen(&source).unwrap();
+            let mut buf = Vec::new();
+            asm.write_to(&mut buf).unwrap();
+            assert_golden(&format!("{}.asm", name), &String::from_utf8(buf).unwrap());
+        }
+    }
+
+    #[test]
+    fn streaming_matches_buffered() {
+        let mut sources = vec![String::from(
+            "let a = 1 + 2\nlet mut b: i32\nb = 3\nlet c = { let t = a\nt / 2 }\nif a > c {\n    let d = 1\n    exit d && b\n}\nfn main() {\n    let e = 4\n    {\n        let f = e\n        return f\n    }\n}\nfn f() {\n}\nexit c\n",
+        )];
+        for name in ["exit", "arithmetic", "if_else"] {
+            let path = format!("{}/tests/golden/{}.tl", env!("CARGO_MANIFEST_DIR"), name);
+            sources.push(std::fs::read_to_string(path).unwrap());
+        }
+        for source in sources {
+            let mut parser = Parser::new(source.clone());
+            parser.parse_program().unwrap();
+            let buffered = gen(&source).unwrap();
+            let mut expected = Vec::new();
+            buffered.write_to(&mut expected).unwrap();
+
+            let mut asm = Asm::default();
+            let mut streamed = Vec::new();
+            asm.gen_streaming(&parser.program.stmts, &mut streamed)
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(streamed).unwrap(),
+                String::from_utf8(expected).unwrap()
+            );
+            assert!(asm.text.is_empty());
+            assert_eq!(asm.warnings, buffered.warnings);
+        }
+
+        // Remembers how much was written at once, and in all.
+        #[derive(Default)]
+        struct Sizes {
+            largest: usize,
+            total: usize,
+        }
+        impl Write for Sizes {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.largest = self.largest.max(buf.len());
+                self.total += buf.len();
+                return Ok(buf.len());
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                return Ok(());
+            }
+        }
+        let source = format!("let a = 1\n{}", "exit a + 1\n".repeat(1000));
+        let mut parser = Parser::new(source);
+        parser.parse_program().unwrap();
+        let mut sizes = Sizes::default();
+        Asm::default()
+            .gen_streaming(&parser.program.stmts, &mut sizes)
+            .unwrap();
+        assert!(sizes.largest < 1000, "{}", sizes.largest);
+        assert!(sizes.total > 100_000, "{}", sizes.total);
+
+        let mut parser = Parser::new(String::from("exit x\n"));
+        parser.parse_program().unwrap();
+        let res = Asm::default().gen_streaming(&parser.program.stmts, &mut Vec::new());
+        assert!(matches!(
+            res,
+            Err(StreamError::Compile(CompileError::UndeclaredIdent(_)))
+        ));
+    }
+
+    #[test]
+    fn every_statement() {
+        let source = "\
+fn main() {
+    let a
+    a = 4
+    let mut b = a * 2
+    a + b
+    {
+        let c = 1
+        b = b + c
+    }
+    if b > 10 {
+        return 1
+    } else if b == 9 {
+        b = b - 1
+    } else {
+        return 2
+    }
+    return b
+}
+let x = 1
+if x == 0 {
+    exit 5
+}
+";
+        let asm = gen(source).unwrap();
+        assert!(asm.text.contains("fn_main:"));
+        assert_exit_code(source, 8);
+        assert_exit_code(&source.replace("let x = 1", "let x = 0"), 5);
+    }
+
+    #[test]
+    fn unary_and_binary_minus() {
+        let cases = [
+            ("exit -5 + 6\n", 1),
+            ("let a = 8\nexit a - 5\n", 3),
+            ("let a = 8\nexit a - -5\n", 13),
+            ("let a = -8\nexit -a - 5\n", 3),
+            ("exit 5 - - - 5\n", 0),
+        ];
+        for (source, expected) in cases {
+            gen(source).unwrap();
+            assert_exit_code(source, expected);
+        }
+    }
+
+    #[test]
+    fn nested_brackets() {
+        let cases = [
+            ("exit ((1 + 2))\n", 3),
+            ("exit ((((5))))\n", 5),
+            ("exit (1 +\n    (2\n    * 3)\n)\n", 7),
+        ];
+        for (source, expected) in cases {
+            gen(source).unwrap();
+            assert_exit_code(source, expected);
+        }
+    }
+
+    #[test]
+    fn reassigning_bindings() {
+        gen("let mut a = 1\na = 2\na = 3\nexit a\n").unwrap();
+        gen("let mut a\na = 2\na = 3\nexit a\n").unwrap();
+        gen("let a\na = 2\nexit a\n").unwrap();
+
+        let res = gen("let a = 1\na = 2\n");
+        assert!(matches!(res, Err(CompileError::AssignToImmutable(ident)) if ident.lexeme == "a"));
+        let res = gen("let a\na = 1\n{\n    a = 2\n}\n");
+        assert!(matches!(res, Err(CompileError::AssignToImmutable(_))));
+    }
+
+    #[test]
+    fn assemble_without_linking() {
+        if !nasm_available() {
+            return;
+        }
+        let stem = temp_stem();
+        let asm = gen("exit 3\n").unwrap();
+        asm.write_to_file(&stem).unwrap();
+        asm.assemble(&stem).unwrap();
+        assert!(std::path::Path::new(&format!("{stem}.obj")).exists());
+        assert!(!std::path::Path::new(&format!("{stem}.exe")).exists());
+        if toolchain_available() {
+            let (status, stderr) = asm.link(&stem).unwrap();
+            assert!(status.success(), "{}", stderr);
+            assert!(std::path::Path::new(&format!("{stem}.exe")).exists());
+        }
+
+        std::fs::write(format!("{stem}.asm"), "not assembly\n").unwrap();
+        let err = asm.assemble(&stem).unwrap_err();
+        assert!(err.to_string().contains("`nasm` failed"));
+    }
+
+    #[test]
+    fn build_report() {
+        if !nasm_available() {
+            return;
+        }
+        let stem = temp_stem();
+        let mut asm = gen("exit 3\n").unwrap();
+        if toolchain_available() {
+            let report = asm.compile(&stem).unwrap();
+            assert!(report.success, "{:?}", report);
+            assert_eq!(report.asm_path, format!("{stem}.asm"));
+            assert_eq!(report.obj_path, format!("{stem}.obj"));
+            assert_eq!(report.exe_path, format!("{stem}.exe"));
+            assert!(std::path::Path::new(&report.exe_path).exists());
+            assert_eq!(report.assembler_stderr, "");
+        }
+
+        asm.text = String::from("not assembly\n");
+        let report = asm.compile(&stem).unwrap();
+        assert!(!report.success);
+        assert!(!report.assembler_stderr.is_empty());
+        assert_eq!(report.gcc_stderr, "");
+    }
+
+    #[test]
+    fn peephole_removes_push_pop_pairs() {
+        let source = "let a = 1\nlet b = a + 2\nexit b\n";
+        let mut asm = Asm::default();
+        asm.opt_level = OptLevel::O1;
+        let asm = gen_with(source, asm).unwrap();
+        assert!(!asm.text.contains("push rax\n    pop rax\n"));
+        assert!(asm.text.contains("mov rax, 2\n    mov rbx, rax\n"));
+        // `b` is still in `rax` after it's stored, so it isn't loaded again.
+        assert!(asm.text.contains("mov qword [rbp-16], rax\n"));
+        assert!(!asm.text.contains("mov rax, qword [rbp-16]\n"));
+
+        let unoptimized = gen(source).unwrap();
+        let pushes = |asm: &Asm| asm.text.matches("push ").count();
+        assert!(pushes(&asm) < pushes(&unoptimized));
+    }
+
+    #[test]
+    fn loads_are_reused() {
+        let optimized = |source| {
+            let mut asm = Asm::default();
+            asm.opt_level = OptLevel::O1;
+            return gen_with(source, asm).unwrap();
+        };
+        let loads = |asm: &Asm| {
+            let lines = asm.text.lines();
+            return lines
+                .filter(|line| line.ends_with(", qword [rbp-8]"))
+                .count();
+        };
+        let source = "let x = 5\nexit x + x\n";
+        assert_eq!(loads(&optimized(source)), 0);
+        // Without the store right before, `x` is loaded once and then copied from `rbx`.
+        let source = "let x = 5\nlet y = 1\nexit x + x + x\n";
+        let asm = optimized(source);
+        assert_eq!(loads(&asm), 1);
+        assert!(asm.text.contains("mov rax, rbx\n"));
+        assert_eq!(gen(source).unwrap().text.matches("[rbp-8]").count(), 4);
+
+        // What's in the registers is forgotten at a label, since it may be jumped to.
+        let source = "let x = 5\nlet y = 1\nif y {\n    exit x\n}\nexit x\n";
+        assert_eq!(loads(&optimized(source)), 2);
+    }
+
+    #[test]
+    fn write_to_buffer() {
+        let asm = gen("exit 3\n").unwrap();
+        let mut buf = Vec::new();
+        asm.write_to(&mut buf).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "default rel");
+        assert_eq!(lines[1], "global _start");
+        assert!(lines[2].starts_with("extern ExitProcess"));
+        assert_eq!(lines[3], "section .text");
+        assert!(output.ends_with(&asm.text));
+    }
+
+    #[test]
+    fn into_asm() {
+        let asm = gen("exit 3\n").unwrap();
+        let text = asm.text.clone();
+        let output = asm.into_asm();
+        assert!(output.starts_with("default rel\n"), "{}", output);
+        assert!(output.ends_with(&text));
+        assert!(output.contains("\n_start:\n"), "{}", output);
+        assert!(output.contains("    mov rax, 3\n"), "{}", output);
+        assert!(output.contains("    call ExitProcess\n"), "{}", output);
+
+        let mut asm = Asm::default();
+        asm.syntax = Syntax::Gas;
+        let mut parser = Parser::new(String::from("exit 3\n"));
+        parser.parse_program().unwrap();
+        asm.gen(&parser.program.stmts).unwrap();
+        let output = asm.into_asm();
+        assert!(output.starts_with(".globl _start\n"), "{}", output);
+        assert!(output.contains("    mov $3, %rax\n"), "{}", output);
+    }
+
+    #[test]
+    fn gas_syntax() {
+        let mut parser = Parser::new(String::from("let a = 5\nexit a\n"));
+        parser.parse_program().unwrap();
+        let mut asm = Asm::default();
+        asm.syntax = Syntax::Gas;
+        asm.gen(&parser.program.stmts).unwrap();
+        // The code is generated the same way and only translated when it is written out.
+        assert!(asm.text.contains("mov rax, 5"), "{}", asm.text);
+
+        let mut buf = Vec::new();
+        asm.write_to(&mut buf).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[..2], [".globl _start", ".text"]);
+        assert!(output.contains("    mov $5, %rax\n"), "{}", output);
+        assert!(output.contains("    movq %rax, -8(%rbp)\n"), "{}", output);
+        assert!(output.contains("    # exit a\n"), "{}", output);
+        assert!(!output.contains(" rax"), "{}", output);
+
+        let mut streamed = Vec::new();
+        let mut streaming = Asm::default();
+        streaming.syntax = Syntax::Gas;
+        streaming
+            .gen_streaming(&parser.program.stmts, &mut streamed)
+            .unwrap();
+        assert_eq!(String::from_utf8(streamed).unwrap(), output);
+    }
+
+    #[test]
+    fn link_files_and_externals_can_be_added() {
+        let mut asm = gen("exit 3\n").unwrap();
+        asm.add_link_file("C:/windows/system32/user32.dll");
+        asm.add_link_file("C:/windows/system32/advapi32.dll");
+        asm.add_link_file("C:/windows/system32/user32.dll");
+        let args: Vec<String> = asm
+            .link_command("prog")
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            args,
+            [
+                "-g",
+                "-nostdlib",
+                "-o",
+                "prog.exe",
+                "prog.obj",
+                "C:/windows/system32/advapi32.dll",
+                "C:/windows/system32/kernel32.dll",
+                "C:/windows/system32/user32.dll",
+            ]
+        );
+
+        asm.add_external("WriteFile");
+        asm.add_external("ExitProcess");
+        let mut buf = Vec::new();
+        asm.write_to(&mut buf).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        assert!(output.contains("extern ExitProcess, WriteFile\n"));
+    }
+
+    #[test]
+    fn output_is_deterministic() {
+        let mut asm = gen("exit 3\n").unwrap();
+        asm.externals.push("WriteFile".into());
+        asm.link_files
+            .insert("C:/windows/system32/advapi32.dll".into());
+        asm.link_files
+            .insert("C:/windows/system32/user32.dll".into());
+
+        let mut buf = Vec::new();
+        asm.write_to(&mut buf).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        let extern_line = output
+            .lines()
+            .find(|line| line.starts_with("extern"))
+            .unwrap();
+        assert_eq!(extern_line, "extern ExitProcess, WriteFile");
+
+        let link_files: Vec<&String> = asm.link_files.iter().collect();
+        assert_eq!(
+            link_files,
+            [
+                "C:/windows/system32/advapi32.dll",
+                "C:/windows/system32/kernel32.dll",
+                "C:/windows/system32/user32.dll",
+            ]
+        );
+    }
+
+    #[test]
+    fn extern_directive() {
+        let extern_line = |externals: &[&str]| {
+            let mut asm = Asm::default();
+            asm.externals = externals.iter().map(|ext| String::from(*ext)).collect();
+            let mut buf = Vec::new();
+            asm.write_to(&mut buf).unwrap();
+            let output = String::from_utf8(buf).unwrap();
+            return output
+                .lines()
+                .find(|line| line.starts_with("extern"))
+                .map(String::from);
+        };
+        assert_eq!(extern_line(&[]), None);
+        assert_eq!(extern_line(&["ExitProcess"]).unwrap(), "extern ExitProcess");
+        assert_eq!(
+            extern_line(&["ExitProcess", "WriteFile"]).unwrap(),
+            "extern ExitProcess, WriteFile"
+        );
+    }
+
+    #[test]
+    fn expression_statements_are_discarded() {
+        let source = "let a = 1\nif a {\n    a + 1\n    a * 2\n}\nexit a\n";
+        let asm = gen_with(
+            source,
+            Asm {
+                emit_comments: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // Every value pushed while evaluating the block is popped again before it ends.
+        let if_body = asm.text.split("jz end_if").nth(1).unwrap();
+        let if_body = if_body.split("end_if").next().unwrap();
+        let pushes = if_body.matches("push ").count() as i64;
+        let pops = if_body.matches("pop ").count() as i64;
+        let discards = if_body.matches("add rsp, 8").count() as i64;
+        assert_eq!(discards, 2);
+        assert_eq!(pushes - pops - discards, 0);
+
+        assert_exit_code(source, 1);
+    }
+
+    #[test]
+    fn declare_then_assign_shares_a_slot() {
+        let sources = [
+            "let x\nx = 5\nexit x\n",
+            "let x\n{\n    x = 5\n}\nexit x\n",
+            "let x\nif 1 {\n    x = 5\n} else {\n    x = 6\n}\nexit x\n",
+        ];
+        for source in sources {
+            let asm = gen(source).unwrap();
+            assert_eq!(asm.text.matches("sub rsp").count(), 1, "{:?}", source);
+            assert!(asm.text.contains("sub rsp, 16\n"), "{:?}", source);
+            assert!(
+                asm.text
+                    .lines()
+                    .filter(|line| line.contains("[rbp-"))
+                    .all(|line| line.contains("[rbp-8]")),
+                "{:?}",
+                source
+            );
+            assert!(asm.text.contains("mov qword [rbp-8], rax\n"));
+            assert!(asm.text.contains("push qword [rbp-8]\n"));
+
+            assert_exit_code(source, 5);
+        }
+    }
+
+    #[test]
+    fn declarations_allocate_on_first_use() {
+        // The offsets of the slots, in the order they're first stored to.
+        let slots = |source: &str| {
+            let asm = gen(source).unwrap();
+            let mut slots = Vec::new();
+            for line in asm.text.lines() {
+                if !line.contains("], ") {
+                    continue;
+                }
+                let slot = line.split_once("[rbp-").unwrap().1;
+                let slot: usize = slot.split_once(']').unwrap().0.parse().unwrap();
+                if !slots.contains(&slot) {
+                    slots.push(slot);
+                }
+            }
+            return slots;
+        };
+        assert_eq!(slots("let x\nexit 0\n"), []);
+        assert_eq!(slots("let x: i32\n{\n    let y\n}\nexit 0\n"), []);
+        assert_eq!(slots("let x\nx = 5\nexit x\n"), [8]);
+        assert_eq!(slots("let x: i32\nlet y = 1\nx = 2\nexit y\n"), [8, 12]);
+        // Used before it's assigned, or assigned in a nested scope, a variable is allocated
+        // where it is declared.
+        assert_eq!(slots("let x\nif 1 {\n    x = 5\n}\n"), [8]);
+        assert_eq!(
+            slots("let mut x\nlet y = 1\nx = 5\nx = x + y\nexit x\n"),
+            [8, 16]
+        );
+        assert_eq!(slots("exit { let x\nx = 5\nx }\n"), [8]);
+        assert_eq!(slots("exit { let x\n0 }\n"), []);
+
+        let asm = gen("let x: i32\nlet y = 1\nx = 2\nexit (x as i64) + y\n").unwrap();
+        assert!(asm.text.contains("mov qword [rbp-8], rax\n"));
+        assert!(asm.text.contains("mov dword [rbp-12], eax\n"));
+
+        let res = gen("let x\nx = 1 as i32\n");
+        assert!(matches!(
+            res,
+            Err(CompileError::TypeMismatch(_, Type::I64, Type::I32))
+        ));
+        let res = gen("let x\nx = 1\nx = 2\n");
+        assert!(matches!(res, Err(CompileError::AssignToImmutable(_))));
+    }
+
+    #[test]
+    fn let_always_allocates_a_new_slot() {
+        let slots = |source: &str| {
+            let asm = gen(source).unwrap();
+            let mut slots: Vec<String> = asm
+                .text
+                .lines()
+                .filter_map(|line| Some(line.split_once("[rbp-")?.1.split_once(']')?.0.into()))
+                .collect();
+            slots.dedup();
+            return slots;
+        };
+        // A fresh binding initialized in place.
+        assert_eq!(slots("let x = 5\nexit x\n"), ["8"]);
+        // A declaration that a later `let` shadows is never used, so it gets no slot.
+        assert_eq!(slots("let x\nlet x = 5\nexit x\n"), ["8"]);
+        assert_eq!(
+            gen("let x\nlet x = 5\nexit x\n")
+                .unwrap()
+                .text
+                .matches("sub rsp, 16")
+                .count(),
+            1
+        );
+        // Assigning to a declaration fills in the declared slot.
+        assert_eq!(slots("let x\nx = 5\nexit x\n"), ["8"]);
+        assert_eq!(slots("let x = 1\nlet x = x + 1\nexit x\n"), ["8", "16"]);
+    }
+
+    #[test]
+    fn frame_is_allocated_once() {
+        let source = "{\n    let a = 1\n    let b = 2\n    let c = 3\n    exit a + b + c\n}\n";
+        let asm = gen(source).unwrap();
+        assert!(asm
+            .text
+            .contains("_start:\n    mov rbp, rsp\n    sub rsp, 32\n"));
+        assert_eq!(asm.text.matches("sub rsp").count(), 1, "{}", asm.text);
+        assert_exit_code(source, 6);
+
+        // Functions have frames of their own.
+        let source = "let a = 1\nfn main() {\n    let b: i32 = 2\n    return b as i64\n}\n";
+        let asm = gen(source).unwrap();
+        assert!(asm
+            .text
+            .contains("_start:\n    mov rbp, rsp\n    sub rsp, 16\n"));
+        assert!(asm
+            .text
+            .contains("fn_main:\n    push rbp\n    mov rbp, rsp\n    sub rsp, 16\n"));
+        assert!(asm.text.contains("mov dword [rbp-4], eax\n"));
+        assert_exit_code(source, 2);
+
+        assert!(!gen("exit 1\n").unwrap().text.contains("sub rsp"));
+    }
+
+    #[test]
+    fn sibling_scopes_reuse_stack_space() {
+        let source = "let a = 7\n{\n    let b = 1\n    let c = 2\n}\n{\n    let d = 3\n}\nlet e = 4\nexit a + e\n";
+        let asm = gen_with(
+            source,
+            Asm {
+                emit_comments: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let stores: Vec<&str> = asm
+            .text
+            .lines()
+            .filter(|line| line.contains("mov qword"))
+            .collect();
+        assert_eq!(
+            stores,
+            [
+                "    mov qword [rbp-8], rax",
+                "    mov qword [rbp-16], rax",
+                "    mov qword [rbp-24], rax",
+                "    mov qword [rbp-16], rax",
+                "    mov qword [rbp-16], rax",
+            ]
+        );
+        // The frame only needs to fit the variables that are live at the same time.
+        assert!(asm
+            .text
+            .contains("_start:\n    mov rbp, rsp\n    sub rsp, 32\n"));
+        assert_eq!(asm.text.matches("sub rsp").count(), 1);
+        assert_eq!(asm.text.matches("add rsp").count(), 0);
+        assert!(asm.text.contains("push qword [rbp-8]\n"));
+
+        assert_exit_code(source, 11);
+    }
+
+    #[test]
+    fn constant_conditions_are_warned_about() {
+        let asm = gen("if 0 {\n    exit 1\n}\nlet a = 1\nif a {\n    exit 2\n} else if 2 > 1 {\n    exit 3\n}\n").unwrap();
+        let starts: Vec<Location> = asm
+            .warnings
+            .iter()
+            .filter_map(|warning| match warning {
+                CompileWarning::ConstantCondition(span) => Some(span.start),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                Location {
+                    row: 1,
+                    col: 4,
+                    offset: 3
+                },
+                Location {
+                    row: 7,
+                    col: 11,
+                    offset: 58
+                }
+            ]
+        );
+    }
+
+    // `gen_stmt` only checks the statements in debug builds.
+    #[test]
+    #[cfg(debug_assertions)]
+    fn statements_leave_the_stack_balanced() {
+        let source = "let a = 1 + 2 * 3\nlet mut b: i32 = 4\nb = b + 1\na - 1\nlet c = { let t = a\nt * sat_add(t, 1) }\nif let d = c / 2 {\n    b = d as i32\n} else if a {\n    exit 2\n}\nfn main() {\n    if 1 {\n        return 3\n    }\n    let e = 4\n    return e + 1\n}\nexit c + (b as i64)\n";
+        let mut asm = Asm::default();
+        asm.div_by_zero_guard = true;
+        gen_with(source, asm).unwrap();
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let first = "let a = 4\nif a > 3 {\n    exit a / 2\n}\nexit 0 or a\n";
+        let second = "if 0 {\n    exit 1\n}\nfn main() {\n    return 2 and 3\n}\n";
+        let configured = || {
+            let mut asm = Asm::default();
+            asm.emit_comments = false;
+            asm.div_by_zero_guard = true;
+            return asm;
+        };
+
+        let mut reused = gen_with(first, configured()).unwrap();
+        reused.reset();
+        assert!(reused.text.is_empty());
+        assert!(!reused.emit_comments);
+        let reused = gen_with(second, reused).unwrap();
+        let fresh = gen_with(second, configured()).unwrap();
+        assert_eq!(reused.text, fresh.text);
+        assert_eq!(reused.warnings, fresh.warnings);
+        assert_eq!(reused.externals, fresh.externals);
+        assert_eq!(reused.link_files, fresh.link_files);
+    }
+
+    #[test]
+    fn logical_operators() {
+        let cases = [
+            ("exit not 0\n", 1),
+            ("exit 1 and 0\n", 0),
+            ("let a = 5\nexit !a || a && a - 5\n", 0),
+            ("let a = 0\nexit a and { exit 3\n1 }\n", 0),
+            ("let a = 2\nexit a or { exit 3\n1 }\n", 1),
+            ("let a = 2\nexit (a - 2 or a) + (a and a - 1) * 2\n", 3),
+        ];
+        for (source, expected) in cases {
+            gen(source).unwrap();
+            assert_exit_code(source, expected);
+        }
+        let asm = gen("let a = 0\nexit a and { exit 3\n1 }\n").unwrap();
+        assert!(asm.text.contains("jz and_decided_0\n"), "{}", asm.text);
+
+        // The right hand side may be skipped, so assigning there doesn't initialize.
+        let res = gen("let x\nlet a = 1\nlet b = a and { x = 1\n1 }\nexit x\n");
+        assert!(
+            matches!(res, Err(CompileError::UninitializedIdent(ident)) if ident.start.row == 5)
+        );
+    }
+
+    #[test]
+    fn shadowing_is_warned_about_when_enabled() {
+        let source = "let a = 1\n{\n    let a = 2\n    let a = 3\n}\n{\n    let b\n    b = 4\n}\n{\n    let b = 5\n}\nexit { let a = 6\na }\n";
+        assert!(gen(source).unwrap().warnings.is_empty());
+
+        let mut asm = Asm::default();
+        asm.warn_shadowing = true;
+        let asm = gen_with(source, asm).unwrap();
+        let shadowing: Vec<((usize, usize), (usize, usize))> = asm
+            .warnings
+            .iter()
+            .map(|warning| match warning {
+                CompileWarning::Shadowing(ident, original) => (
+                    (ident.start.row, ident.start.col),
+                    (original.row, original.col),
+                ),
+                warning => panic!("Unexpected warning: {:?}", warning),
+            })
+            .collect();
+        // The second `a` of the first block only shadows one from its own scope, and the `b`s
+        // are in sibling scopes.
+        assert_eq!(shadowing, [((3, 9), (1, 5)), ((13, 12), (1, 5))]);
+
+        let mut asm = Asm::default();
+        asm.warn_shadowing = true;
+        let asm = gen_with(
+            "let a = 1\nfn main() {\n    let a = 2\n    return a\n}\n",
+            asm,
+        )
+        .unwrap();
+        assert!(asm.warnings.is_empty(), "{:?}", asm.warnings);
+    }
+
+    #[test]
+    fn initialization_is_tracked_through_branches() {
+        gen("let x\nif 1 {\n    x = 5\n} else if 0 {\n    x = 6\n} else {\n    x = 7\n}\nexit x\n")
+            .unwrap();
+
+        let res = gen("let x\nif 1 {\n    x = 5\n}\nexit x\n");
+        assert!(matches!(res, Err(CompileError::UninitializedIdent(_))));
+        let res = gen("let x\nif 1 {\n    x = 5\n}\nx = 6\n");
+        assert!(matches!(res, Err(CompileError::AssignToImmutable(_))));
+
+        gen("let mut x\nif 1 {\n    x = 5\n}\nx = 6\nexit x\n").unwrap();
+    }
+
+    #[test]
+    fn saturating_intrinsics() {
+        let cases = [
+            (
+                "let max = 9223372036854775807\nexit sat_add(max - 1, 10) == max\n",
+                1,
+            ),
+            (
+                "let min = -9223372036854775807 - 1\nexit sat_sub(min, 1) == min\n",
+                1,
+            ),
+            (
+                "let max = 9223372036854775807\nexit sat_sub(-2, max) < 0\n",
+                1,
+            ),
+            ("let a = 2\nexit sat_add(a, 3) + sat_sub(a, 3)\n", 4),
+        ];
+        for (source, expected) in cases {
+            let asm = gen(source).unwrap();
+            assert!(asm.text.contains("cmovo rax, rcx"), "{}", asm.text);
+            for level in [OptLevel::O0, OptLevel::O2] {
+                assert_exit_code_at(source, level, expected);
+            }
+        }
+
+        let res = gen("exit sat_add(1)\n");
+        assert!(
+            matches!(res, Err(CompileError::ArgumentCount(ident, 2, 1)) if ident.lexeme == "sat_add")
+        );
+        let res = gen("let a: i32 = 1\nexit sat_add(a, 1)\n");
+        assert!(matches!(
+            res,
+            Err(CompileError::TypeMismatch(_, Type::I64, Type::I32))
+        ));
+        let res = gen("let a: i32 = sat_add(1, 1)\n");
+        assert!(matches!(
+            res,
+            Err(CompileError::TypeMismatch(_, Type::I32, Type::I64))
+        ));
+    }
+
+    #[test]
+    fn print_routines() {
+        let asm = gen("let a = 1\nprint a\n").unwrap();
+        assert!(
+            asm.text.contains("    pop rcx\n    call print\n"),
+            "{}",
+            asm.text
+        );
+        assert!(asm.text.contains("\nprint:\n"), "{}", asm.text);
+        assert!(asm.text.contains("\nprint_write:\n"), "{}", asm.text);
+        // Only the routines that are called are emitted.
+        assert!(!asm.text.contains("print_hex"), "{}", asm.text);
+        let mut buf = Vec::new();
+        asm.write_to(&mut buf).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        assert!(output.contains("extern ExitProcess, GetStdHandle, WriteFile\n"));
+
+        let asm = gen("exit 1\n").unwrap();
+        assert!(!asm.text.contains("print_write"));
+        assert_eq!(asm.externals, ["ExitProcess"]);
+
+        // The routines go after the function bodies, which may be the only ones to call them.
+        let asm = gen("fn main() {\n    print_hex 255\n    print_char 65\n}\n").unwrap();
+        let main = asm.text.find("fn_main:").unwrap();
+        assert!(
+            asm.text.find("\nprint_hex:\n").unwrap() > main,
+            "{}",
+            asm.text
+        );
+        assert!(
+            asm.text.find("\nprint_char:\n").unwrap() > main,
+            "{}",
+            asm.text
+        );
+        let asm = gen("fn main() {\n    let a = 0\n    return 1 / a\n}\n").unwrap();
+        assert!(asm.text.contains("\ndiv_by_zero:\n"), "{}", asm.text);
+
+        let cases: [(&str, &[u8]); 6] = [
+            ("print_hex 255\n", b"ff\n"),
+            ("print_char 65\n", b"A"),
+            ("print 1234\nprint (0 - 56)\n", b"1234\n-56\n"),
+            (
+                "print (-9223372036854775807 - 1)\n",
+                b"-9223372036854775808\n",
+            ),
+            ("print_hex (0 - 1)\nprint 0\n", b"ffffffffffffffff\n0\n"),
+            ("exit 1 + { print_char 66\n2 }\n", b"B"),
+        ];
+        for (source, expected) in cases {
+            gen(source).unwrap();
+            assert_output(source, expected);
+        }
+
+        // Where it isn't a print statement, `print` is a name like any other.
+        let source = "let mut print = 2\nprint = print + 1\nexit print\n";
+        gen(source).unwrap();
+        assert_exit_code(source, 3);
+    }
+
+    #[test]
+    fn field_access_is_not_implemented() {
+        let res = gen("let a = 1\na.b = 2\n");
+        assert!(matches!(res, Err(CompileError::NotImplemented(loc)) if loc.row == 2));
+        let res = gen("let a = 1\nexit a.b\n");
+        assert!(matches!(res, Err(CompileError::NotImplemented(_))));
+        let res = gen("exit f(1)\n");
+        assert!(matches!(res, Err(CompileError::NotImplemented(loc)) if loc.col == 6));
+    }
+
+    #[test]
+    fn groups_are_not_implemented() {
+        let res = gen("let a = 1\nexit (a, 2)\n");
+        assert!(matches!(res, Err(CompileError::NotImplemented(loc)) if loc.row == 2));
+    }
+
+    #[test]
+    fn i32_uses_four_byte_slots() {
+        let source = "let a: i32 = 5
+let mut b: i32
+b = a + 1
+let c = 2
+exit b
+";
+        let asm = gen(source).unwrap();
+        assert!(asm.text.contains(
+            "pop rax
+    mov dword [rbp-4], eax
+"
+        ));
+        assert!(asm.text.contains(
+            "sub rsp, 16
+"
+        ));
+        assert!(asm.text.contains(
+            "movsxd rax, dword [rbp-4]
+"
+        ));
+        assert!(asm.text.contains(
+            "mov dword [rbp-8], eax
+"
+        ));
+        assert!(asm.text.contains(
+            "mov qword [rbp-16], rax
+"
+        ));
+
+        assert_exit_code(source, 6);
+    }
+
+    #[test]
+    fn casts_sign_extend() {
+        let source =
+            "let a: i64 = 4294967295\nlet b: i32 = a as i32\nlet c = b as i64\nexit c == -1\n";
+        let asm = gen(source).unwrap();
+        assert!(asm
+            .text
+            .contains("pop rax\n    movsxd rax, eax\n    push rax\n"));
+        assert!(asm.text.contains("mov dword [rbp-12], eax\n"));
+        assert!(asm.text.contains("movsxd rax, dword [rbp-12]\n"));
+        assert!(asm.text.contains("mov qword [rbp-20], rax\n"));
+
+        let res = gen("let a: i64 = 1\nlet b: i32 = a\n");
+        assert!(matches!(res, Err(CompileError::TypeMismatch(..))));
+
+        assert_exit_code(source, 1);
+    }
+
+    #[test]
+    fn i32_arithmetic_wraps() {
+        let source = "let a: i32 = 65536\nexit a * a == 0\n";
+        let asm = gen(source).unwrap();
+        assert!(
+            asm.text
+                .contains("imul rbx\n    movsxd rax, eax\n    push rax\n"),
+            "{}",
+            asm.text
+        );
+        let asm = gen("let a = 65536\nexit a * a\n").unwrap();
+        assert!(!asm.text.contains("movsxd rax, eax"), "{}", asm.text);
+
+        assert_exit_code(source, 1);
+        assert_exit_code("let a: i32 = -2147483647 - 1\nexit -a < 0\n", 1);
+    }
+
+    #[test]
+    fn mismatched_types_are_rejected() {
+        gen("let a: i32 = 1
+let b = a
+let c: i32 = b * 2
+")
+        .unwrap();
+
+        let res = gen("let a: i32 = 1
+let b: i64 = a
+");
+        assert!(matches!(
+            res,
+            Err(CompileError::TypeMismatch(_, Type::I64, Type::I32))
+        ));
+        let res = gen("let a: i32 = 1
+let mut b: i64
+b = a + 1
+");
+        assert!(matches!(
+            res,
+            Err(CompileError::TypeMismatch(_, Type::I64, Type::I32))
+        ));
+        let res = gen("let a: i32 = 1
+let b: i64 = 2
+exit a < b
+");
+        assert!(matches!(
+            res,
+            Err(CompileError::TypeMismatch(_, Type::I32, Type::I64))
+        ));
+    }
+}
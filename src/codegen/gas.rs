@@ -0,0 +1,185 @@
+use std::{fmt::Display, str::FromStr};
+
+use super::operand::Reg;
+
+/// The assembler `Asm` writes its code for. The code is generated in NASM syntax either way,
+/// since the passes that run over it read that, and only translated when it is written out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Intel syntax, assembled with nasm.
+    #[default]
+    Nasm,
+    /// AT&T syntax, assembled with the GNU assembler `as`.
+    Gas,
+}
+
+impl Syntax {
+    /// The extension of the assembly file, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Nasm => return "asm",
+            Self::Gas => return "s",
+        }
+    }
+
+    /// The program that assembles the code.
+    pub fn assembler(self) -> &'static str {
+        match self {
+            Self::Nasm => return "nasm",
+            Self::Gas => return "as",
+        }
+    }
+}
+
+impl FromStr for Syntax {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nasm" => Ok(Self::Nasm),
+            "gas" => Ok(Self::Gas),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Syntax {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Nasm => write!(f, "nasm"),
+            Self::Gas => write!(f, "gas"),
+        }
+    }
+}
+
+/// Translates whole lines of code in NASM syntax, the way `Asm` writes it, into AT&T syntax.
+pub fn translate(text: &str) -> String {
+    let mut translated = String::with_capacity(text.len());
+    for line in text.lines() {
+        translated.push_str(&translate_line(line));
+        translated.push('\n');
+    }
+    return translated;
+}
+
+fn translate_line(line: &str) -> String {
+    let instruction = line.trim();
+    // Labels aren't indented, and neither are they any different in AT&T syntax.
+    if !line.starts_with(' ') || instruction.is_empty() {
+        return String::from(line);
+    }
+    let indent = &line[..line.len() - line.trim_start().len()];
+    if let Some(comment) = instruction.strip_prefix(';') {
+        return format!("{}#{}", indent, comment);
+    }
+
+    let (mnemonic, operands) = instruction.split_once(' ').unwrap_or((instruction, ""));
+    let mut suffix = None;
+    let mut translated: Vec<String> = Vec::new();
+    if !operands.is_empty() {
+        for op in operands.split(", ").collect::<Vec<&str>>().iter().rev() {
+            let (op, size) = translate_operand(op);
+            suffix = suffix.or(size);
+            translated.push(op);
+        }
+    }
+    let mnemonic = match mnemonic {
+        "cqo" => String::from("cqto"),
+        // Sign extends a dword into a qword.
+        "movsxd" => String::from("movslq"),
+        _ => match suffix {
+            Some(suffix) => format!("{}{}", mnemonic, suffix),
+            None => String::from(mnemonic),
+        },
+    };
+    if translated.is_empty() {
+        return format!("{}{}", indent, mnemonic);
+    }
+    return format!("{}{} {}", indent, mnemonic, translated.join(", "));
+}
+
+/// Translates one operand, along with the suffix its size puts on the mnemonic, if it's a
+/// memory operand with a size.
+fn translate_operand(op: &str) -> (String, Option<char>) {
+    let (op, suffix) = match op.split_once(' ') {
+        Some(("qword", mem)) => (mem, Some('q')),
+        Some(("dword", mem)) => (mem, Some('l')),
+        Some(("byte", mem)) => (mem, Some('b')),
+        _ => (op, None),
+    };
+    if let Some(address) = op.strip_prefix('[').and_then(|op| op.strip_suffix(']')) {
+        let (base, offset) = match address.find(['+', '-']) {
+            Some(i) => (&address[..i], address[i..].trim_start_matches('+')),
+            None => (address, ""),
+        };
+        return (format!("{}(%{})", offset, base), suffix);
+    }
+    if op.parse::<i64>().is_ok() {
+        return (format!("${}", op), suffix);
+    }
+    if Reg::ALL.iter().any(|reg| reg.to_string() == op) {
+        return (format!("%{}", op), suffix);
+    }
+    // A label, which jumps and calls take as is.
+    return (String::from(op), suffix);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_and_t_syntax() {
+        let nasm = "\
+_start:
+    mov rbp, rsp
+    ; let a_1 = 5
+    mov qword [rbp-8], 5
+    mov rax, qword [rbp-8]
+    movsxd rax, dword [rbp-12]
+    mov rax, 9223372036854775807
+    push rax
+    cqo
+    idiv rbx
+    mov rcx, [rsp+8]
+    mov rdx, [rsp]
+    mov byte [r10], al
+    lea r9, [rbp-48]
+    jz end_if_0
+    call ExitProcess
+
+    ret
+";
+        let gas = "\
+_start:
+    mov %rsp, %rbp
+    # let a_1 = 5
+    movq $5, -8(%rbp)
+    movq -8(%rbp), %rax
+    movslq -12(%rbp), %rax
+    mov $9223372036854775807, %rax
+    push %rax
+    cqto
+    idiv %rbx
+    mov 8(%rsp), %rcx
+    mov (%rsp), %rdx
+    movb %al, (%r10)
+    lea -48(%rbp), %r9
+    jz end_if_0
+    call ExitProcess
+
+    ret
+";
+        assert_eq!(translate(nasm), gas);
+    }
+
+    #[test]
+    fn syntax_names() {
+        for syntax in [Syntax::Nasm, Syntax::Gas] {
+            assert_eq!(syntax.to_string().parse(), Ok(syntax));
+        }
+        assert_eq!("att".parse::<Syntax>(), Err(()));
+        assert_eq!(Syntax::default(), Syntax::Nasm);
+        assert_eq!(Syntax::Gas.extension(), "s");
+    }
+}
@@ -1,4 +1,8 @@
 mod codegen;
+mod gas;
+mod operand;
+mod stack_tracker;
 mod string_decorator;
 
-pub use codegen::{Asm, Env};
+pub use codegen::{Asm, Env, StreamError, DIV_BY_ZERO_EXIT_CODE};
+pub use gas::Syntax;
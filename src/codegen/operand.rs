@@ -0,0 +1,177 @@
+use std::fmt::Display;
+
+use crate::parser::Type;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    Rax,
+    Rbx,
+    Rcx,
+    Rdx,
+    Rsp,
+    Rbp,
+    R8,
+    R9,
+    R10,
+    /// The lower 32 bits of `rax`.
+    Eax,
+    /// The lowest 8 bits of `rax`.
+    Al,
+}
+
+impl Reg {
+    pub const ALL: [Reg; 11] = [
+        Self::Rax,
+        Self::Rbx,
+        Self::Rcx,
+        Self::Rdx,
+        Self::Rsp,
+        Self::Rbp,
+        Self::R8,
+        Self::R9,
+        Self::R10,
+        Self::Eax,
+        Self::Al,
+    ];
+}
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rax => write!(f, "rax"),
+            Self::Rbx => write!(f, "rbx"),
+            Self::Rcx => write!(f, "rcx"),
+            Self::Rdx => write!(f, "rdx"),
+            Self::Rsp => write!(f, "rsp"),
+            Self::Rbp => write!(f, "rbp"),
+            Self::R8 => write!(f, "r8"),
+            Self::R9 => write!(f, "r9"),
+            Self::R10 => write!(f, "r10"),
+            Self::Eax => write!(f, "eax"),
+            Self::Al => write!(f, "al"),
+        }
+    }
+}
+
+/// How many bytes a memory operand refers to, for instructions where NASM can't tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    Byte,
+    Dword,
+    Qword,
+}
+
+impl From<Type> for Size {
+    fn from(ty: Type) -> Self {
+        match ty {
+            Type::I32 => Self::Dword,
+            Type::I64 => Self::Qword,
+        }
+    }
+}
+
+impl Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Byte => write!(f, "byte"),
+            Self::Dword => write!(f, "dword"),
+            Self::Qword => write!(f, "qword"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    /// `[base+offset]`, prefixed with its size if there is one.
+    Mem {
+        size: Option<Size>,
+        base: Reg,
+        offset: i64,
+    },
+    Imm(i64),
+    Label(String),
+}
+
+impl Operand {
+    /// The stack slot `rbp_offset` bytes below `rbp`, which is where variables live.
+    pub fn stack(size: Size, rbp_offset: usize) -> Self {
+        return Self::Mem {
+            size: Some(size),
+            base: Reg::Rbp,
+            offset: -(rbp_offset as i64),
+        };
+    }
+}
+
+impl From<Reg> for Operand {
+    fn from(reg: Reg) -> Self {
+        return Self::Reg(reg);
+    }
+}
+
+impl From<i64> for Operand {
+    fn from(imm: i64) -> Self {
+        return Self::Imm(imm);
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reg(reg) => write!(f, "{}", reg),
+            Self::Mem { size, base, offset } => {
+                if let Some(size) = size {
+                    write!(f, "{} ", size)?;
+                }
+                match offset {
+                    0 => write!(f, "[{}]", base),
+                    1.. => write!(f, "[{}+{}]", base, offset),
+                    _ => write!(f, "[{}-{}]", base, offset.unsigned_abs()),
+                }
+            }
+            Self::Imm(imm) => write!(f, "{}", imm),
+            Self::Label(label) => write!(f, "{}", label),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nasm_syntax() {
+        assert_eq!(Operand::from(Reg::Rax).to_string(), "rax");
+        assert_eq!(Operand::from(Reg::Al).to_string(), "al");
+        assert_eq!(Operand::from(-5).to_string(), "-5");
+        assert_eq!(
+            Operand::Label(String::from("end_if_0")).to_string(),
+            "end_if_0"
+        );
+
+        assert_eq!(
+            Operand::stack(Size::Qword, 16).to_string(),
+            "qword [rbp-16]"
+        );
+        assert_eq!(
+            Operand::stack(Size::from(Type::I32), 4).to_string(),
+            "dword [rbp-4]"
+        );
+        let mem = |offset| Operand::Mem {
+            size: None,
+            base: Reg::Rsp,
+            offset,
+        };
+        assert_eq!(mem(0).to_string(), "[rsp]");
+        assert_eq!(mem(8).to_string(), "[rsp+8]");
+        assert_eq!(mem(-8).to_string(), "[rsp-8]");
+        assert_eq!(mem(i64::MIN).to_string(), "[rsp-9223372036854775808]");
+        let byte = Operand::Mem {
+            size: Some(Size::Byte),
+            base: Reg::R10,
+            offset: 0,
+        };
+        assert_eq!(byte.to_string(), "byte [r10]");
+    }
+}
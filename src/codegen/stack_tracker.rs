@@ -0,0 +1,92 @@
+/// Follows how many bytes the emitted instructions have pushed onto the stack, so that the
+/// generator can check that the code of every statement leaves the stack as it found it.
+#[derive(Debug, Default)]
+pub struct StackTracker {
+    depth: i64,
+    /// The depth before the last `mov rsp, rbp`. The code after the `ret` of an epilogue is
+    /// only reached by jumping past the epilogue, with the stack as deep as it was before it.
+    before_epilogue: Option<i64>,
+}
+
+impl StackTracker {
+    /// How many bytes have been pushed so far.
+    pub fn depth(&self) -> i64 {
+        return self.depth;
+    }
+
+    /// Accounts for one instruction, as it is written out, e.g. `push rax`.
+    pub fn step(&mut self, instruction: &str) {
+        let (mnemonic, operands) = instruction.split_once(' ').unwrap_or((instruction, ""));
+        let operands: Vec<&str> = operands.split(", ").collect();
+        let bytes = |bytes: &str| -> i64 {
+            return bytes.parse().unwrap_or_else(|_| {
+                panic!(
+                    "[StackTracker.step] Expected a number of bytes in `{}`",
+                    instruction
+                )
+            });
+        };
+        match (mnemonic, operands.as_slice()) {
+            ("push", _) => self.depth += 8,
+            ("pop", _) => self.depth -= 8,
+            ("sub", ["rsp", amount]) => self.depth += bytes(amount),
+            ("add", ["rsp", amount]) => self.depth -= bytes(amount),
+            ("mov", ["rsp", "rbp"]) => self.before_epilogue = Some(self.depth),
+            ("ret", _) => {
+                if let Some(depth) = self.before_epilogue.take() {
+                    self.depth = depth;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Checks that `instructions` leave the stack as deep as they found it. Otherwise returns
+    /// how many bytes deeper it is afterwards.
+    pub fn verify<'a>(instructions: impl IntoIterator<Item = &'a str>) -> Result<(), i64> {
+        let mut tracker = Self::default();
+        for instruction in instructions {
+            tracker.step(instruction);
+        }
+        match tracker.depth {
+            0 => return Ok(()),
+            depth => return Err(depth),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balanced_sequences() {
+        let balanced = [
+            "push 1",
+            "push qword [rbp-8]",
+            "pop rbx",
+            "pop rax",
+            "add rax, rbx",
+            "push rax",
+            "add rsp, 8",
+        ];
+        assert_eq!(StackTracker::verify(balanced), Ok(()));
+        // The `ret` only returns from the function, the code after it continues at the depth
+        // from before the epilogue.
+        let returned = ["push 7", "pop rax", "mov rsp, rbp", "pop rbp", "ret"];
+        assert_eq!(StackTracker::verify(returned), Ok(()));
+    }
+
+    #[test]
+    fn unbalanced_sequences() {
+        assert_eq!(
+            StackTracker::verify(["push 1", "push 2", "pop rax"]),
+            Err(8)
+        );
+        assert_eq!(
+            StackTracker::verify(["push 1", "pop rax", "pop rbx"]),
+            Err(-8)
+        );
+        assert_eq!(StackTracker::verify(["sub rsp, 16", "add rsp, 8"]), Err(8));
+    }
+}
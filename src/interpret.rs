@@ -0,0 +1,565 @@
+use std::{collections::HashMap, io::Write};
+
+use crate::{
+    codegen::DIV_BY_ZERO_EXIT_CODE,
+    parser::{Identifier, Intrinsic, LExp, PrintFormat, Program, RExp, Stmt, Term, Type},
+    CompileError,
+};
+
+/// Runs `program` without compiling it and returns its exit code, as a reference for what the
+/// compiled program does.
+///
+/// Arithmetic wraps and comparisons are signed, like in the generated code, and dividing by
+/// zero exits with `DIV_BY_ZERO_EXIT_CODE`. Only the lower 32 bits of the result make it into
+/// the exit code of a compiled program. Errors are reported as they are run into, so a program
+/// that doesn't compile may still run if the offending code is never reached.
+///
+/// What the program prints is written to stdout once it stops.
+pub fn interpret(program: &Program) -> std::io::Result<Result<i64, CompileError>> {
+    let (res, output) = interpret_with_output(program);
+    let mut stdout = std::io::stdout();
+    stdout.write_all(&output)?;
+    stdout.flush()?;
+    return Ok(res);
+}
+
+/// Like `interpret`, but also returns what the program printed before it stopped.
+pub fn interpret_with_output(program: &Program) -> (Result<i64, CompileError>, Vec<u8>) {
+    let main = program.stmts.iter().find_map(|stmt| match stmt {
+        Stmt::FnDef(ident, body) if ident.lexeme == "main" => Some(body),
+        _ => None,
+    });
+    let mut interpreter = Interpreter::default();
+    // Functions can't see the variables around them, which are out of scope by then.
+    let res = interpreter.block(&program.stmts).and_then(|()| match main {
+        Some(body) => match interpreter.block(body) {
+            Ok(()) => return Ok(0),
+            Err(Stop::Return(value)) => return Ok(value),
+            Err(stop) => return Err(stop),
+        },
+        None => return Ok(0),
+    });
+    let res = match res {
+        // A `return` outside of a function exits the program.
+        Ok(code) | Err(Stop::Exit(code)) | Err(Stop::Return(code)) => Ok(code),
+        Err(Stop::Error(err)) => Err(err),
+    };
+    return (res, interpreter.output);
+}
+
+/// Why the statements being run stopped before their end.
+enum Stop {
+    Exit(i64),
+    Return(i64),
+    Error(CompileError),
+}
+
+impl From<CompileError> for Stop {
+    fn from(err: CompileError) -> Self {
+        return Stop::Error(err);
+    }
+}
+
+struct Variable {
+    /// `None` until the variable is given a value.
+    value: Option<i64>,
+    ty: Type,
+    mutable: bool,
+}
+
+#[derive(Default)]
+struct Interpreter {
+    /// The variables of every scope, innermost scope last.
+    scopes: Vec<HashMap<String, Variable>>,
+    /// What the print statements wrote, as the compiled program would write it to stdout.
+    output: Vec<u8>,
+}
+
+impl Interpreter {
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), Stop> {
+        self.scopes.push(HashMap::new());
+        let res = stmts.iter().try_for_each(|stmt| self.stmt(stmt));
+        self.scopes.pop();
+        return res;
+    }
+
+    fn lookup(&mut self, ident: &Identifier) -> Result<&mut Variable, CompileError> {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(var) = scope.get_mut(&ident.lexeme) {
+                return Ok(var);
+            }
+        }
+        return Err(CompileError::UndeclaredIdent(ident.clone()));
+    }
+
+    fn define(&mut self, ident: &Identifier, var: Variable) {
+        let scope = self.scopes.last_mut().unwrap();
+        scope.insert(ident.lexeme.clone(), var);
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), Stop> {
+        match stmt {
+            Stmt::Declare(decl) => {
+                let var = Variable {
+                    value: None,
+                    ty: decl.ty.unwrap_or(Type::I64),
+                    mutable: decl.mutable,
+                };
+                self.define(&decl.ident, var);
+            }
+            Stmt::Initialize(decl, rexp) => {
+                let (value, inferred) = self.typed_rexp(rexp)?;
+                let ty = decl.ty.or(inferred).unwrap_or(Type::I64);
+                let var = Variable {
+                    value: Some(truncate(value, ty)),
+                    ty,
+                    mutable: decl.mutable,
+                };
+                self.define(&decl.ident, var);
+            }
+            Stmt::Assign(lexp, rexp) => {
+                let ident = match lexp {
+                    LExp::Ident(ident) => ident,
+                    LExp::Field(_, _) => {
+                        return Err(CompileError::NotImplemented(lexp.start()).into())
+                    }
+                };
+                let value = self.rexp(rexp)?;
+                let var = self.lookup(ident)?;
+                if !var.mutable && var.value.is_some() {
+                    return Err(CompileError::AssignToImmutable(ident.clone()).into());
+                }
+                var.value = Some(truncate(value, var.ty));
+            }
+            Stmt::RExp(rexp) => {
+                self.rexp(rexp)?;
+            }
+            Stmt::Block(block) => return self.block(block),
+            Stmt::If(rexp, if_block, else_stmt) => {
+                if self.rexp(rexp)? != 0 {
+                    return self.block(if_block);
+                }
+                if let Some(else_stmt) = else_stmt {
+                    return self.stmt(else_stmt);
+                }
+            }
+            Stmt::Exit(rexp) => return Err(Stop::Exit(self.rexp(rexp)?)),
+            Stmt::Return(rexp) => return Err(Stop::Return(self.rexp(rexp)?)),
+            Stmt::Print(rexp, format) => {
+                let value = self.rexp(rexp)?;
+                match format {
+                    PrintFormat::Decimal => self.output.extend(format!("{}\n", value).bytes()),
+                    PrintFormat::Hex => self.output.extend(format!("{:x}\n", value).bytes()),
+                    PrintFormat::Char => self.output.push(value as u8),
+                }
+            }
+            // Only `main` is run, once the top-level statements are done.
+            Stmt::FnDef(_, _) => (),
+        }
+        return Ok(());
+    }
+
+    fn rexp(&mut self, rexp: &RExp) -> Result<i64, Stop> {
+        return Ok(self.typed_rexp(rexp)?.0);
+    }
+
+    /// Evaluates `rexp` along with the type `Asm::rexp` gives it. Arithmetic on `i32`s wraps at
+    /// 32 bits.
+    fn typed_rexp(&mut self, rexp: &RExp) -> Result<(i64, Option<Type>), Stop> {
+        let ((lhs, l_ty), (rhs, r_ty)) = match rexp {
+            RExp::Term(term) => return self.term(term),
+            RExp::Cast(rexp, ty) => return Ok((truncate(self.rexp(rexp)?, *ty), Some(*ty))),
+            RExp::Block(block, tail) => {
+                self.scopes.push(HashMap::new());
+                let res = block
+                    .iter()
+                    .try_for_each(|stmt| self.stmt(stmt))
+                    .and_then(|()| self.rexp(tail));
+                self.scopes.pop();
+                return Ok((res?, None));
+            }
+            // The right hand side is only evaluated if the left one doesn't decide the result.
+            RExp::And(lhs, rhs) => {
+                return Ok(((self.rexp(lhs)? != 0 && self.rexp(rhs)? != 0) as i64, None))
+            }
+            RExp::Or(lhs, rhs) => {
+                return Ok(((self.rexp(lhs)? != 0 || self.rexp(rhs)? != 0) as i64, None))
+            }
+            RExp::Add(lhs, rhs)
+            | RExp::Sub(lhs, rhs)
+            | RExp::Mul(lhs, rhs)
+            | RExp::Div(lhs, rhs)
+            | RExp::Equal(lhs, rhs)
+            | RExp::NotEqual(lhs, rhs)
+            | RExp::Less(lhs, rhs)
+            | RExp::LessEqual(lhs, rhs)
+            | RExp::Greater(lhs, rhs)
+            | RExp::GreaterEqual(lhs, rhs) => (self.typed_rexp(lhs)?, self.typed_rexp(rhs)?),
+        };
+        let value = match rexp {
+            RExp::Add(_, _) => lhs.wrapping_add(rhs),
+            RExp::Sub(_, _) => lhs.wrapping_sub(rhs),
+            RExp::Mul(_, _) => lhs.wrapping_mul(rhs),
+            RExp::Div(_, _) if rhs == 0 => return Err(Stop::Exit(DIV_BY_ZERO_EXIT_CODE)),
+            // The compiled program faults on `i64::MIN / -1` instead of wrapping.
+            RExp::Div(_, _) => lhs.wrapping_div(rhs),
+            RExp::Equal(_, _) => return Ok(((lhs == rhs) as i64, None)),
+            RExp::NotEqual(_, _) => return Ok(((lhs != rhs) as i64, None)),
+            RExp::Less(_, _) => return Ok(((lhs < rhs) as i64, None)),
+            RExp::LessEqual(_, _) => return Ok(((lhs <= rhs) as i64, None)),
+            RExp::Greater(_, _) => return Ok(((lhs > rhs) as i64, None)),
+            RExp::GreaterEqual(_, _) => return Ok(((lhs >= rhs) as i64, None)),
+            RExp::Term(_)
+            | RExp::Cast(_, _)
+            | RExp::Block(_, _)
+            | RExp::And(_, _)
+            | RExp::Or(_, _) => unreachable!(),
+        };
+        let ty = l_ty.or(r_ty);
+        return Ok((ty.map_or(value, |ty| truncate(value, ty)), ty));
+    }
+
+    fn term(&mut self, term: &Term) -> Result<(i64, Option<Type>), Stop> {
+        match term {
+            Term::LExp(LExp::Ident(ident)) => {
+                let var = self.lookup(ident)?;
+                match var.value {
+                    Some(value) => return Ok((value, Some(var.ty))),
+                    None => return Err(CompileError::UninitializedIdent(ident.clone()).into()),
+                }
+            }
+            Term::LExp(lexp @ LExp::Field(_, _)) => {
+                return Err(CompileError::NotImplemented(lexp.start()).into())
+            }
+            Term::IntLit(intlit) => return Ok((intlit.wrapping_value(), None)),
+            Term::Neg(term) => {
+                let (value, ty) = self.term(term)?;
+                let value = value.wrapping_neg();
+                return Ok((ty.map_or(value, |ty| truncate(value, ty)), ty));
+            }
+            Term::Not(term) => return Ok(((self.term(term)?.0 == 0) as i64, None)),
+            Term::Bracketed(rexp) => return self.typed_rexp(rexp),
+            Term::Group(_) => return Err(CompileError::NotImplemented(term.start()).into()),
+            Term::Call(ident, args) => {
+                let intrinsic = Intrinsic::resolve(ident, args)?;
+                let mut values = Vec::new();
+                for arg in args.iter() {
+                    values.push(self.rexp(arg)?);
+                }
+                return Ok((intrinsic.eval(&values), Some(Type::I64)));
+            }
+        }
+    }
+}
+
+/// Keeps the part of `value` that fits in `ty`, sign extended back to an `i64`.
+fn truncate(value: i64, ty: Type) -> i64 {
+    match ty {
+        Type::I32 => return value as i32 as i64,
+        Type::I64 => return value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::Asm,
+        optimize::{optimize, OptLevel},
+        parser::Parser,
+        test_utils::{compile_and_run_at, compile_and_run_with_output},
+    };
+
+    fn run(source: &str) -> Result<i64, CompileError> {
+        let mut parser = Parser::new(String::from(source));
+        parser.parse_program()?;
+        return interpret_with_output(&parser.program).0;
+    }
+
+    #[test]
+    fn agrees_with_compiled_programs() {
+        let cases = [
+            ("exit 3\n", 3),
+            ("let a = 1\n", 0),
+            ("let a = 7\nlet b = (a + 2) * 3 - 10 / a\nexit b\n", 26),
+            ("exit -5 / 2\n", -2),
+            ("exit 1 / 0\n", DIV_BY_ZERO_EXIT_CODE),
+            ("exit 9223372036854775807 + 2\n", i64::MIN + 1),
+            // Negating the minimum wraps around to itself.
+            ("let m = -9223372036854775807 - 1\nexit -m\n", i64::MIN),
+            ("exit -9223372036854775808\n", i64::MIN),
+            ("let m = -9223372036854775807 - 1\nexit -m == m\n", 1),
+            (
+                "exit (-1 < 0) + (2 <= 1) * 2 + (3 != 3) * 4 + (5 == 5) * 8\n",
+                9,
+            ),
+            (
+                "let a = 5\nif a > 3 {\n    exit 1\n} else {\n    exit 2\n}\n",
+                1,
+            ),
+            (
+                "let a = 2\nif a > 3 exit 1 else if a > 1 exit 2 else exit 3\n",
+                2,
+            ),
+            ("let mut a = 1\n{\n    let a = 10\n    exit a\n}\n", 10),
+            ("let mut a = 1\n{\n    a = a + 10\n}\nexit a\n", 11),
+            ("let a = 1\nlet a = a + 1\nexit a\n", 2),
+            (
+                "let x = 1\nlet x = 2\nlet x_1 = 3\nlet x_2 = 4\nexit x * 100 + x_1 * 10 + x_2\n",
+                234,
+            ),
+            (
+                "let x\nif 1 {\n    x = 5\n} else {\n    x = 6\n}\nexit x\n",
+                5,
+            ),
+            (
+                "let a: i32 = 2147483647\nlet b: i32 = a + 1\nexit b < 0\n",
+                1,
+            ),
+            ("exit (4294967297 as i32) + 1\n", 2),
+            ("let a: i32 = 65536\nexit a * a == 0\n", 1),
+            ("let a: i32 = 65536\nexit a * 65536 / 65536\n", 0),
+            ("let a: i32 = -2147483647 - 1\nexit -a < 0\n", 1),
+            ("exit sat_add(9223372036854775807, 10)\n", i64::MAX),
+            ("exit sat_sub(-9223372036854775807, 10)\n", i64::MIN),
+            ("exit { let a = 4\na * a } + 1\n", 17),
+            ("fn main() {\n    return 7\n}\n", 7),
+            ("fn main() {\n    exit 4\n}\nexit 5\n", 5),
+            ("fn main() {\n    let a = 1\n}\n", 0),
+            ("return 5\n", 5),
+            ("exit not 0 and 1 or 0\n", 1),
+            ("let a = 0\nexit a && { exit 3\n1 }\n", 0),
+            ("let a = 2\nexit a || { exit 3\n1 }\n", 1),
+            ("let a = 2\nexit !a or { exit 3\n1 }\n", 3),
+            ("let a = 2\nif a > 1 {\n    return a\n}\nexit 1\n", 2),
+        ];
+        for (source, expected) in cases {
+            let value = run(source).unwrap();
+            assert_eq!(value, expected, "{:?}", source);
+            if let Some(code) = compile_and_run_at(source, OptLevel::O0) {
+                assert_eq!(code, value as i32, "{:?}", source);
+            }
+        }
+    }
+
+    #[test]
+    fn prints() {
+        let mut parser = Parser::new(String::from(
+            "let a = 255\nprint a\nprint_hex a\nprint_char 65 + 256\nprint_hex (0 - 1)\nexit { print (0 - a)\n2 }\nprint 1\n",
+        ));
+        parser.parse_program().unwrap();
+        let (res, output) = interpret_with_output(&parser.program);
+        assert_eq!(res, Ok(2));
+        assert_eq!(output, b"255\nff\nAffffffffffffffff\n-255\n");
+    }
+
+    #[test]
+    fn errors() {
+        let res = run("exit a\n");
+        assert!(matches!(res, Err(CompileError::UndeclaredIdent(ident)) if ident.lexeme == "a"));
+        let res = run("let a\nexit a\n");
+        assert!(matches!(res, Err(CompileError::UninitializedIdent(_))));
+        let res = run("let a = a + 1\n");
+        assert!(matches!(res, Err(CompileError::UndeclaredIdent(_))));
+        let res = run("let mut a\na = a + 1\n");
+        assert!(matches!(res, Err(CompileError::UninitializedIdent(_))));
+        let res = run("{\n    exit a\n}\n");
+        assert!(matches!(res, Err(CompileError::UndeclaredIdent(ident)) if ident.lexeme == "a"));
+        let res = run("{\n    let a = 1\n}\nexit a\n");
+        assert!(matches!(res, Err(CompileError::UndeclaredIdent(_))));
+        let res = run("let a = 1\na = 2\n");
+        assert!(matches!(res, Err(CompileError::AssignToImmutable(_))));
+        let res = run("{\n    let a = 1\n}\nexit a\n");
+        assert!(matches!(res, Err(CompileError::UndeclaredIdent(_))));
+        let res = run("exit f()\n");
+        assert!(matches!(res, Err(CompileError::NotImplemented(_))));
+    }
+
+    /// Checks that the interpreter and the program compiled without and with optimizations
+    /// agree on `source`, both on the exit code and the output, returning the interpreter's
+    /// result. Without a toolchain only the
+    /// code generation is checked.
+    fn differential(source: &str) -> i64 {
+        let mut parser = Parser::new(String::from(source));
+        parser.parse_program().unwrap();
+        let (res, output) = interpret_with_output(&parser.program);
+        let value = res.unwrap_or_else(|err| panic!("{:?}\n{}", err, source));
+        for level in [OptLevel::O0, OptLevel::O2] {
+            let mut parser = Parser::new(String::from(source));
+            parser.parse_program().unwrap();
+            match optimize(&mut parser.program.stmts, level) {
+                Ok(()) => (),
+                // Folding reports constant arithmetic that overflows, which wraps otherwise.
+                Err(CompileError::ConstantOverflow(_)) => continue,
+                Err(err) => panic!("{:?} at {:?}\n{}", err, level, source),
+            }
+            let mut asm = Asm::default();
+            asm.opt_level = level;
+            asm.gen(&parser.program.stmts)
+                .unwrap_or_else(|err| panic!("{:?} at {:?}\n{}", err, level, source));
+            if let Some((code, compiled_output)) = compile_and_run_with_output(source, level) {
+                assert_eq!(code, value as i32, "at {:?}\n{}", level, source);
+                assert_eq!(compiled_output, output, "at {:?}\n{}", level, source);
+            }
+        }
+        return value;
+    }
+
+    #[test]
+    fn differential_corpus() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("corpus");
+        let mut paths: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "tl"))
+            .collect();
+        paths.sort();
+        assert!(paths.len() >= 12, "{}", dir.display());
+
+        for path in paths {
+            let source = std::fs::read_to_string(&path).unwrap();
+            let expected = source
+                .lines()
+                .next()
+                .and_then(|line| line.strip_prefix("// exit code: "))
+                .and_then(|code| code.trim().parse::<i64>().ok())
+                .unwrap_or_else(|| {
+                    panic!("{} should start with `// exit code: N`", path.display())
+                });
+            assert_eq!(differential(&source), expected, "{}", path.display());
+        }
+    }
+
+    /// Generates random programs out of `let`s, assignments, `if`/`else` and arithmetic.
+    ///
+    /// Divisors are always positive literals, so `i64::MIN / -1` can't come up.
+    struct ProgramGen {
+        state: u64,
+        /// The variables in scope and whether they are mutable.
+        vars: Vec<(String, bool)>,
+        next_var: usize,
+        source: String,
+    }
+
+    impl ProgramGen {
+        fn new(seed: u64) -> Self {
+            return Self {
+                state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+                vars: vec![],
+                next_var: 0,
+                source: String::new(),
+            };
+        }
+
+        /// xorshift64
+        fn next(&mut self) -> u64 {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            return self.state;
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            return self.next() % n;
+        }
+
+        fn program(mut self) -> String {
+            let count = 3 + self.below(4);
+            self.stmts(0, count);
+            let exit = self.rexp(3);
+            self.source.push_str(&format!("exit {}\n", exit));
+            return self.source;
+        }
+
+        fn stmts(&mut self, depth: usize, count: u64) {
+            for _ in 0..count {
+                self.stmt(depth);
+            }
+        }
+
+        fn stmt(&mut self, depth: usize) {
+            let indent = "    ".repeat(depth);
+            let mutable: Vec<String> = self
+                .vars
+                .iter()
+                .filter(|(_, mutable)| *mutable)
+                .map(|(name, _)| name.clone())
+                .collect();
+            match self.below(4) {
+                1 if !mutable.is_empty() => {
+                    let name = mutable[self.below(mutable.len() as u64) as usize].clone();
+                    let rexp = self.rexp(3);
+                    self.source
+                        .push_str(&format!("{}{} = {}\n", indent, name, rexp));
+                }
+                2 if depth < 2 => {
+                    let condition = self.rexp(2);
+                    self.source
+                        .push_str(&format!("{}if {} {{\n", indent, condition));
+                    self.block(depth + 1);
+                    self.source.push_str(&format!("{}}} else {{\n", indent));
+                    self.block(depth + 1);
+                    self.source.push_str(&format!("{}}}\n", indent));
+                }
+                3 if depth > 0 && self.below(3) == 0 => {
+                    let rexp = self.rexp(2);
+                    self.source.push_str(&format!("{}exit {}\n", indent, rexp));
+                }
+                _ => {
+                    let mutable = self.below(2) == 0;
+                    let rexp = self.rexp(3);
+                    let name = format!("v{}", self.next_var);
+                    self.next_var += 1;
+                    let keyword = if mutable { "let mut" } else { "let" };
+                    self.source
+                        .push_str(&format!("{}{} {} = {}\n", indent, keyword, name, rexp));
+                    self.vars.push((name, mutable));
+                }
+            }
+        }
+
+        fn block(&mut self, depth: usize) {
+            let outer = self.vars.len();
+            let count = 1 + self.below(3);
+            self.stmts(depth, count);
+            self.vars.truncate(outer);
+        }
+
+        fn rexp(&mut self, depth: usize) -> String {
+            if depth == 0 || self.below(3) == 0 {
+                return self.term();
+            }
+            let lhs = self.rexp(depth - 1);
+            let operators = ["+", "-", "*", "/", "<", "<=", ">", ">=", "==", "!="];
+            let operator = operators[self.below(operators.len() as u64) as usize];
+            let rhs = if operator == "/" {
+                (1 + self.below(9)).to_string()
+            } else {
+                self.rexp(depth - 1)
+            };
+            return format!("({} {} {})", lhs, operator, rhs);
+        }
+
+        fn term(&mut self) -> String {
+            if !self.vars.is_empty() && self.below(2) == 0 {
+                let i = self.below(self.vars.len() as u64) as usize;
+                return self.vars[i].0.clone();
+            }
+            let literal = self.below(41) as i64 - 20;
+            if literal < 0 {
+                return format!("({})", literal);
+            }
+            return literal.to_string();
+        }
+    }
+
+    #[test]
+    fn differential_random_programs() {
+        for seed in 0..200 {
+            let source = ProgramGen::new(seed).program();
+            differential(&source);
+        }
+    }
+}
@@ -1,13 +1,11 @@
-use std::{
-    fmt::{Debug, Display},
-    fs::read_to_string,
-    rc::Rc,
-    vec,
-};
+use alloc::{format, rc::Rc, string::String, vec, vec::Vec};
+use core::fmt::{Debug, Display};
+#[cfg(feature = "std")]
+use std::{fs::read_to_string, io::Read};
 
 const DEBUG_TOKENS: bool = false;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Token {
     pub file: Option<Rc<str>>,
     pub start: Location,
@@ -15,20 +13,74 @@ pub struct Token {
     pub tokentype: TokenType,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// The stretch of source code from `start` up to `end`, e.g. all the tokens of one node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: Option<Rc<str>>,
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    /// The span from the start of `start_tok` to the end of `end_tok`.
+    pub fn join(start_tok: &Token, end_tok: &Token) -> Self {
+        return Self {
+            file: start_tok.file.clone(),
+            start: start_tok.start,
+            end: end_tok.end,
+        };
+    }
+}
+
+impl From<&Token> for Span {
+    fn from(token: &Token) -> Self {
+        return Span::join(token, token);
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// Locations are ordered by row first and column second, i.e. in the order they appear in the
+/// source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Location {
     pub row: usize,
     pub col: usize,
+    /// Index of the character in the source, counting from 0.
+    pub offset: usize,
+}
+
+impl Location {
+    /// The later of two locations, e.g. the end of a span covering both.
+    pub fn max(a: Self, b: Self) -> Self {
+        return Ord::max(a, b);
+    }
+
+    /// The earlier of two locations, e.g. the start of a span covering both.
+    pub fn min(a: Self, b: Self) -> Self {
+        return Ord::min(a, b);
+    }
 }
 
 impl Default for Location {
     fn default() -> Self {
-        return Self { row: 1, col: 1 };
+        return Self {
+            row: 1,
+            col: 1,
+            offset: 0,
+        };
     }
 }
 
 impl Display for Location {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}:{}", self.row, self.col)
     }
 }
@@ -42,14 +94,23 @@ pub enum TokenType {
     IntLiteral(String),
     Illegal(String),
 
+    LineComment(String),
+
     Let,
+    Mut,
     Exit,
     If,
     Else,
+    Fn,
+    Return,
+    As,
 
     NewLine,
 
     Assign,
+    Colon,
+    Comma,
+    Dot,
 
     Plus,
     Minus,
@@ -63,6 +124,10 @@ pub enum TokenType {
     Greater,
     GreaterEqual,
 
+    Not,
+    And,
+    Or,
+
     SCurly,
     ECurly,
 
@@ -76,11 +141,17 @@ const TOKENTYPE_MAPPINGS: &[(&str, TT)] = &[
     ("!=", TT::NotEqual),
     ("<=", TT::LessEqual),
     (">=", TT::GreaterEqual),
+    ("&&", TT::And),
+    ("||", TT::Or),
+    ("!", TT::Not),
     ("+", TT::Plus),
     ("-", TT::Minus),
     ("*", TT::Asterisk),
     ("/", TT::ForwardSlash),
     ("=", TT::Assign),
+    (":", TT::Colon),
+    (",", TT::Comma),
+    (".", TT::Dot),
     ("<", TT::Less),
     (">", TT::Greater),
     ("{", TT::SCurly),
@@ -90,17 +161,88 @@ const TOKENTYPE_MAPPINGS: &[(&str, TT)] = &[
     ("\n", TT::NewLine),
 ];
 
+const KEYWORD_MAPPINGS: &[(&str, TT)] = &[
+    ("else", TT::Else),
+    ("exit", TT::Exit),
+    ("let", TT::Let),
+    ("mut", TT::Mut),
+    ("if", TT::If),
+    ("fn", TT::Fn),
+    ("return", TT::Return),
+    ("as", TT::As),
+    // Aliases of the symbols above.
+    ("not", TT::Not),
+    ("and", TT::And),
+    ("or", TT::Or),
+];
+
 use crate::CompileError;
 
+impl TokenType {
+    /// Comments are only lexed as tokens when `Lexer::preserve_trivia` is set.
+    pub fn is_trivia(&self) -> bool {
+        return matches!(self, TT::LineComment(_));
+    }
+}
+
+/// Displays the source text a token of this type is lexed from. `StartOfFile` and `EndOfFile`
+/// have none, and the word forms of operators like `not` are displayed as their symbol.
+impl Display for TokenType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TT::StartOfFile | TT::EndOfFile => return Ok(()),
+            TT::Ident(text) | TT::IntLiteral(text) | TT::Illegal(text) | TT::LineComment(text) => {
+                return write!(f, "{}", text)
+            }
+            _ => (),
+        }
+        let (text, _) = TOKENTYPE_MAPPINGS
+            .iter()
+            .chain(KEYWORD_MAPPINGS.iter())
+            .find(|(_, tokentype)| tokentype == self)
+            .expect(&format!("[Display for TokenType] No text for {:?}", self));
+        write!(f, "{}", text)
+    }
+}
+
 impl Default for TokenType {
     fn default() -> Self {
         return TT::StartOfFile;
     }
 }
 
+/// Options for how source text is lexed. The defaults lex the way `Lexer::new` does.
+#[derive(Debug, Default, Clone)]
+pub struct LexerConfig {
+    preserve_trivia: bool,
+    collapse_newlines: bool,
+    recover_illegal: bool,
+}
+
+impl LexerConfig {
+    /// See `Lexer::preserve_trivia`.
+    pub fn preserve_trivia(&mut self, preserve_trivia: bool) -> &mut Self {
+        self.preserve_trivia = preserve_trivia;
+        return self;
+    }
+    /// See `Lexer::collapse_newlines`.
+    pub fn collapse_newlines(&mut self, collapse_newlines: bool) -> &mut Self {
+        self.collapse_newlines = collapse_newlines;
+        return self;
+    }
+    /// See `Lexer::recover_illegal`.
+    pub fn recover_illegal(&mut self, recover_illegal: bool) -> &mut Self {
+        self.recover_illegal = recover_illegal;
+        return self;
+    }
+}
+
 #[derive(Debug)]
 pub struct Lexer {
     source: Vec<char>,
+    /// The source as it was given, along with the byte offset where each of its lines starts.
+    text: String,
+    line_starts: Vec<usize>,
     tokens: Vec<Token>,
 
     next_token: Token,
@@ -111,22 +253,63 @@ pub struct Lexer {
 
     pub loc: Location,
     pub emit_newline: bool,
+    /// When true, comments are emitted as `LineComment` tokens instead of
+    /// being skipped along with whitespace.
+    pub preserve_trivia: bool,
+    /// When true, a run of newlines (and the whitespace between them) is lexed as a single
+    /// `NewLine` token.
+    pub collapse_newlines: bool,
+    /// When true, `consume` doesn't fail on an illegal token. It is still emitted as an
+    /// `Illegal` token, and lexing goes on after it.
+    pub recover_illegal: bool,
 }
 
 impl Lexer {
     pub fn new(source: String) -> Self {
+        return Self::with_file(source, None);
+    }
+    pub fn new_with_config(source: String, config: &LexerConfig) -> Self {
+        let mut lexer = Self::with_file(source, None);
+        lexer.configure(config);
+        return lexer;
+    }
+    #[cfg(feature = "std")]
+    pub fn from_file(path: Rc<str>) -> std::io::Result<Self> {
+        let source = read_to_string(path.as_ref())?;
+        return Ok(Self::with_file(source, Some(path)));
+    }
+
+    /// Reads the whole source from `reader` before lexing it. `file` names the source in the
+    /// tokens, e.g. for error messages.
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: Read>(mut reader: R, file: Option<Rc<str>>) -> std::io::Result<Self> {
+        let mut source = String::new();
+        reader.read_to_string(&mut source)?;
+        return Ok(Self::with_file(source, file));
+    }
+
+    fn with_file(source: String, file: Option<Rc<str>>) -> Self {
         let first_token = Token {
-            file: None,
+            file: file.clone(),
             start: Location::default(),
             end: Location::default(),
             tokentype: TT::StartOfFile,
         };
+        let mut line_starts = vec![0];
+        line_starts.extend(
+            source
+                .match_indices('\n')
+                .map(|(i, _)| i + 1)
+                .filter(|start| *start < source.len()),
+        );
         let mut ret = Self {
             source: source.chars().collect(),
+            text: source,
+            line_starts,
             peek_ch: None,
             tokens: vec![first_token],
             next_token: Token {
-                file: None,
+                file,
                 start: Location::default(),
                 end: Location::default(),
                 tokentype: TT::StartOfFile,
@@ -135,39 +318,89 @@ impl Lexer {
             token_cursor: 0,
             loc: Location::default(),
             emit_newline: true,
+            preserve_trivia: false,
+            collapse_newlines: false,
+            recover_illegal: false,
         };
         if ret.source.len() > 0 {
             ret.peek_ch = Some(ret.source[0]);
         }
         return ret;
     }
-    pub fn from_file(path: Rc<str>) -> Self {
-        let source = read_to_string(path.as_ref()).expect("Provided input file does not exist!");
-        let first_token = Token {
-            file: Some(path.clone()),
-            start: Location::default(),
-            end: Location::default(),
-            tokentype: TT::StartOfFile,
+
+    /// Applies `config` to a lexer that hasn't lexed anything yet.
+    pub fn configure(&mut self, config: &LexerConfig) {
+        self.preserve_trivia = config.preserve_trivia;
+        self.collapse_newlines = config.collapse_newlines;
+        self.recover_illegal = config.recover_illegal;
+    }
+
+    /// The text of the 1-based line `row`, without its line ending, e.g. to point at an error.
+    /// Returns `None` past the last line, or if the lexer was created from tokens.
+    pub fn source_line(&self, row: usize) -> Option<&str> {
+        let start = *self.line_starts.get(row.checked_sub(1)?)?;
+        let end = match self.line_starts.get(row) {
+            Some(next_start) => next_start - 1,
+            None => self.text.len(),
         };
-        let mut ret = Self {
-            source: source.chars().collect(),
+        let line = &self.text[start..end];
+        return Some(
+            line.strip_suffix('\n')
+                .unwrap_or(line)
+                .trim_end_matches('\r'),
+        );
+    }
+
+    /// The source that hasn't been lexed yet, i.e. everything after the last token lexed,
+    /// e.g. to show where lexing got stuck. Rewinding doesn't give back any source.
+    pub fn remaining_source(&self) -> String {
+        return self.source[self.ch_cursor..].iter().collect();
+    }
+
+    /// The source text of `token`, e.g. the lexeme of an identifier. Returns `None` if the
+    /// token isn't from this lexer's source, e.g. if the lexer was created from tokens.
+    pub fn token_text(&self, token: &Token) -> Option<String> {
+        let chars = self.source.get(token.start.offset..token.end.offset)?;
+        return Some(chars.iter().collect());
+    }
+
+    /// Creates a lexer that serves an already lexed token stream instead of lexing source text.
+    /// A `StartOfFile` token is added in front if the stream doesn't begin with one, and an
+    /// `EndOfFile` token is produced once the stream runs out.
+    pub fn from_tokens(mut tokens: Vec<Token>) -> Self {
+        let file = tokens.first().and_then(|token| token.file.clone());
+        if tokens.first().map(|token| &token.tokentype) != Some(&TT::StartOfFile) {
+            tokens.insert(
+                0,
+                Token {
+                    file: file.clone(),
+                    start: Location::default(),
+                    end: Location::default(),
+                    tokentype: TT::StartOfFile,
+                },
+            );
+        }
+        let loc = tokens.last().unwrap().end;
+        return Self {
+            source: Vec::new(),
+            text: String::new(),
+            line_starts: Vec::new(),
             peek_ch: None,
-            tokens: vec![first_token],
+            tokens,
             next_token: Token {
-                file: Some(path.clone()),
-                start: Location::default(),
-                end: Location::default(),
+                file,
+                start: loc,
+                end: loc,
                 tokentype: TT::StartOfFile,
             },
             ch_cursor: 0,
             token_cursor: 0,
-            loc: Location::default(),
+            loc,
             emit_newline: true,
+            preserve_trivia: false,
+            collapse_newlines: false,
+            recover_illegal: false,
         };
-        if ret.source.len() > 0 {
-            ret.peek_ch = Some(ret.source[0]);
-        }
-        return ret;
     }
 
     pub fn is_eof(&mut self) -> bool {
@@ -182,10 +415,11 @@ impl Lexer {
         self.next_token.start = self.loc;
     }
 
-    fn set_next_token(&mut self, tokentype: TokenType) {
+    /// Emits the token that started at `prepare_next_token` and ends here, and returns it.
+    fn set_next_token(&mut self, tokentype: TokenType) -> Token {
         if DEBUG_TOKENS {
-            println!("[Lexer] emit_newline: {}", self.emit_newline);
-            println!("[Lexer] lexed: {:?}", tokentype);
+            debug_println!("[Lexer] emit_newline: {}", self.emit_newline);
+            debug_println!("[Lexer] lexed: {:?}", tokentype);
         }
 
         self.next_token.tokentype = tokentype;
@@ -193,6 +427,21 @@ impl Lexer {
 
         self.tokens.push(self.next_token.clone());
         self.token_cursor += 1;
+        return self.next_token.clone();
+    }
+
+    /// Lexes the rest of the source and returns its tokens, up to and including `EndOfFile`.
+    pub fn lex_all(&mut self) -> Result<Vec<Token>, CompileError> {
+        let mut tokens = Vec::new();
+        loop {
+            self.consume()?;
+            let token = self.peek();
+            let is_eof = token.tokentype == TT::EndOfFile;
+            tokens.push(token);
+            if is_eof {
+                return Ok(tokens);
+            }
+        }
     }
 
     pub fn rewind(&mut self) {
@@ -232,6 +481,16 @@ impl Lexer {
             return Ok(());
         };
 
+        if let Some(comment) = self.comment() {
+            self.set_next_token(comment);
+            return Ok(());
+        }
+
+        // Int literals go first, so that no mapping can ever claim a digit or a base prefix.
+        if ch.is_ascii_digit() {
+            return self.int_literal();
+        }
+
         for (string, tokentype) in TOKENTYPE_MAPPINGS.iter() {
             if self.try_consume_str(string) {
                 self.set_next_token(tokentype.clone());
@@ -241,11 +500,9 @@ impl Lexer {
 
         match ch {
             ch if ch.is_ascii_alphabetic() || ch == '_' => self.ident_or_keyword(),
-            ch if ch.is_ascii_digit() => self.int_literal()?,
             ch => {
-                self.set_next_token(TT::Illegal(String::from(ch)));
                 self.consume_ch();
-                return Err(CompileError::IllegalToken(self.peek()));
+                return self.illegal(String::from(ch));
             }
         };
         return Ok(());
@@ -257,6 +514,7 @@ impl Lexer {
         }
 
         self.ch_cursor += 1;
+        self.loc.offset += 1;
 
         if let Some('\n') = self.peek_ch {
             self.loc.row += 1;
@@ -273,21 +531,19 @@ impl Lexer {
     }
 
     fn skip_whitespace(&mut self) {
+        let after_newline = self.tokens.last().map(|token| &token.tokentype) == Some(&TT::NewLine);
+        let skip_newline = !self.emit_newline || (self.collapse_newlines && after_newline);
         loop {
             let mut skipped = false;
             while self.peek_ch.map_or(false, |ch| {
-                (ch != '\n' && ch.is_whitespace()) || (ch == '\n' && !self.emit_newline)
+                (ch != '\n' && ch.is_whitespace()) || (ch == '\n' && skip_newline)
             }) {
                 skipped = true;
                 self.consume_ch();
             }
-            if self.try_consume_str("//") {
+            if !self.preserve_trivia && self.comment().is_some() {
                 skipped = true;
-                while self.peek_ch.map_or(false, |ch| ch != '\n') {
-                    self.consume_ch();
-                }
             }
-            println!();
 
             if !skipped {
                 break;
@@ -295,20 +551,60 @@ impl Lexer {
         }
     }
 
+    /// Consumes a `// ...` comment if one starts at the cursor.
+    /// The returned token holds the comment's full text, delimiters included.
+    fn comment(&mut self) -> Option<TokenType> {
+        let start = self.ch_cursor;
+        if self.try_consume_str("//") {
+            while self.peek_ch.map_or(false, |ch| ch != '\n') {
+                self.consume_ch();
+            }
+            let text = self.source[start..self.ch_cursor].iter().collect();
+            return Some(TT::LineComment(text));
+        }
+        return None;
+    }
+
+    /// Emits an `Illegal` token for `lexeme`, which was just consumed, and reports it unless
+    /// `recover_illegal` is set. The error carries the token as it was emitted, rather than
+    /// whatever token the cursor is on.
+    fn illegal(&mut self, lexeme: String) -> Result<(), CompileError> {
+        let token = self.set_next_token(TT::Illegal(lexeme));
+        if self.recover_illegal {
+            return Ok(());
+        }
+        return Err(CompileError::IllegalToken(token));
+    }
+
+    /// Lexes a decimal literal, or a hexadecimal, binary or octal one if it starts with `0x`,
+    /// `0b` or `0o`. The lexeme is kept as written; `IntLiteral::value` reads it.
+    ///
+    /// A decimal literal can't have leading zeros: C reads `007` as octal while Rust reads it
+    /// as decimal, so rather than pick one, it is a `LeadingZero` error. `0` itself is fine.
     fn int_literal(&mut self) -> Result<(), CompileError> {
         // TODO: Handle 64 bit int literals
         let Some(ch) = self.peek_ch else {
             panic!("[Lexer.int_literal] Called eventhough no characters are left!");
         };
-        assert!(
-            ch.is_ascii_digit(),
-            "[Lexer.ident_or_keyword] Falsely called!"
-        );
+        assert!(ch.is_ascii_digit(), "[Lexer.int_literal] Falsely called!");
 
         let mut lexeme = String::new();
-        while self.peek_ch.map_or(false, |ch| ch.is_ascii_digit()) {
+        let radix = match (ch, self.source.get(self.ch_cursor + 1)) {
+            ('0', Some(&prefix @ ('x' | 'b' | 'o'))) => {
+                lexeme.push(ch);
+                lexeme.push(prefix);
+                self.consume_ch();
+                self.consume_ch();
+                int_literal_radix(prefix)
+            }
+            _ => 10,
+        };
+
+        let mut digits = 0;
+        while self.peek_ch.map_or(false, |ch| ch.is_digit(radix)) {
             lexeme.push(self.peek_ch.unwrap());
             self.consume_ch();
+            digits += 1;
         }
         let mut illegal_lexeme = String::new();
         while self.peek_ch.map_or(false, |ch| ch.is_ascii_alphanumeric()) {
@@ -316,10 +612,17 @@ impl Lexer {
             self.consume_ch();
         }
 
-        if illegal_lexeme.len() > 0 {
+        // A prefix without any digits, like `0x`, is as illegal as a stray suffix.
+        if illegal_lexeme.len() > 0 || digits == 0 {
             lexeme.extend(illegal_lexeme.chars());
-            self.set_next_token(TT::Illegal(lexeme));
-            return Err(CompileError::IllegalToken(self.peek()));
+            return self.illegal(lexeme);
+        }
+        if radix == 10 && lexeme.len() > 1 && lexeme.starts_with('0') {
+            let token = self.set_next_token(TT::Illegal(lexeme));
+            if self.recover_illegal {
+                return Ok(());
+            }
+            return Err(CompileError::LeadingZero(token.start));
         }
 
         self.set_next_token(TT::IntLiteral(lexeme));
@@ -344,16 +647,29 @@ impl Lexer {
             self.consume_ch();
         }
 
-        match lexeme.as_str() {
-            "else" => self.set_next_token(TT::Else),
-            "exit" => self.set_next_token(TT::Exit),
-            "let" => self.set_next_token(TT::Let),
-            "if" => self.set_next_token(TT::If),
-            _ => self.set_next_token(TT::Ident(lexeme)),
+        let keyword = KEYWORD_MAPPINGS
+            .iter()
+            .find(|(keyword, _)| *keyword == lexeme);
+        match keyword {
+            Some((_, tokentype)) => self.set_next_token(tokentype.clone()),
+            None => self.set_next_token(TT::Ident(lexeme)),
         };
     }
 }
 
+/// The radix an int literal's base prefix (the character after its `0`) stands for.
+pub fn int_literal_radix(prefix: char) -> u32 {
+    match prefix {
+        'x' => return 16,
+        'b' => return 2,
+        'o' => return 8,
+        _ => panic!(
+            "[Lexer.int_literal_radix] `{}` isn't a base prefix!",
+            prefix
+        ),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -387,6 +703,119 @@ mod tests {
         }
     }
 
+    #[test]
+    fn recover_from_illegal_tokens() {
+        let source = String::from("let a = 1 @\nexit 2a\n");
+        let mut lexer = Lexer::new(source.clone());
+        lexer.consume().unwrap();
+        let mut res = Ok(());
+        while lexer.peek().tokentype != TT::EndOfFile && res.is_ok() {
+            res = lexer.consume();
+        }
+        assert!(matches!(res, Err(CompileError::IllegalToken(token)) if token.start.row == 1));
+
+        let mut config = LexerConfig::default();
+        config.recover_illegal(true);
+        let mut lexer = Lexer::new_with_config(source, &config);
+        let mut illegal = Vec::new();
+        while lexer.peek().tokentype != TT::EndOfFile {
+            lexer.consume().unwrap();
+            let token = lexer.peek();
+            if let TT::Illegal(lexeme) = token.tokentype {
+                illegal.push((token.start.row, lexeme));
+            }
+        }
+        assert_eq!(illegal, [(1, String::from("@")), (2, String::from("2a"))]);
+    }
+
+    #[test]
+    fn illegal_token_errors_carry_the_illegal_token() {
+        let location = |col, offset| Location {
+            row: 1,
+            col,
+            offset,
+        };
+        let cases = [
+            ("a $b", "$", location(3, 2), location(4, 3)),
+            ("exit 12ab + 1", "12ab", location(6, 5), location(10, 9)),
+        ];
+        for (source, lexeme, start, end) in cases {
+            let mut lexer = Lexer::new(String::from(source));
+            let expected = Token {
+                file: None,
+                start,
+                end,
+                tokentype: TT::Illegal(String::from(lexeme)),
+            };
+            assert_eq!(
+                lexer.lex_all().unwrap_err(),
+                CompileError::IllegalToken(expected.clone()),
+                "{:?}",
+                source
+            );
+            assert_eq!(lexer.peek(), expected, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn int_literal_bases() {
+        let mut config = LexerConfig::default();
+        config.recover_illegal(true);
+        let source = "0 0x10 0b10 0x0 0o7 0x 0b12 0xg 1x 0b";
+        let tokens = Lexer::new_with_config(String::from(source), &config).lex_all();
+
+        use TokenType::*;
+        let expected = [
+            IntLiteral(String::from("0")),
+            IntLiteral(String::from("0x10")),
+            IntLiteral(String::from("0b10")),
+            IntLiteral(String::from("0x0")),
+            IntLiteral(String::from("0o7")),
+            Illegal(String::from("0x")),
+            Illegal(String::from("0b12")),
+            Illegal(String::from("0xg")),
+            Illegal(String::from("1x")),
+            Illegal(String::from("0b")),
+            EndOfFile,
+        ];
+        let tokentypes: Vec<TokenType> = tokens.unwrap().into_iter().map(|t| t.tokentype).collect();
+        assert_eq!(tokentypes, expected);
+    }
+
+    #[test]
+    fn leading_zeros() {
+        let lex = |source: &str| Lexer::new(String::from(source)).lex_all();
+        for source in ["0", "0x0", "10", "0b0"] {
+            assert!(lex(source).is_ok(), "{}", source);
+        }
+        let location = |col, offset| Location {
+            row: 1,
+            col,
+            offset,
+        };
+        assert_eq!(
+            lex("007").unwrap_err(),
+            CompileError::LeadingZero(location(1, 0))
+        );
+        assert_eq!(
+            lex("exit 00").unwrap_err(),
+            CompileError::LeadingZero(location(6, 5))
+        );
+
+        let mut config = LexerConfig::default();
+        config.recover_illegal(true);
+        let tokens = Lexer::new_with_config(String::from("007 7"), &config).lex_all();
+        let tokentypes: Vec<TokenType> = tokens.unwrap().into_iter().map(|t| t.tokentype).collect();
+        assert_eq!(
+            tokentypes,
+            [
+                TT::Illegal(String::from("007")),
+                TT::IntLiteral(String::from("7")),
+                TT::EndOfFile
+            ]
+        );
+    }
+
     #[test]
     fn legal_tokens() {
         let source = String::from(
@@ -396,10 +825,11 @@ b  a1352 _ _ab
 
 325252 1234
 
-let exit if else 
+let mut exit if else fn return as not and or nota
 
-= + - * /
+= : . + - * /
 == != < <= > >=
+! && || !!=
 
 { } {}
 ( ) ()
@@ -424,12 +854,22 @@ let exit if else
             NewLine,
             NewLine,
             Let,
+            Mut,
             Exit,
             If,
             Else,
+            Fn,
+            Return,
+            As,
+            Not,
+            And,
+            Or,
+            Ident(String::from("nota")),
             NewLine,
             NewLine,
             Assign,
+            Colon,
+            Dot,
             Plus,
             Minus,
             Asterisk,
@@ -442,6 +882,12 @@ let exit if else
             Greater,
             GreaterEqual,
             NewLine,
+            Not,
+            And,
+            Or,
+            Not,
+            NotEqual,
+            NewLine,
             NewLine,
             SCurly,
             ECurly,
@@ -466,4 +912,229 @@ let exit if else
             i += 1;
         }
     }
+
+    #[test]
+    fn trivia_tokens() {
+        let source = String::from("let a = 1 // one\n// two\nexit a\n");
+        use TokenType::*;
+        let expected = [
+            StartOfFile,
+            Let,
+            Ident(String::from("a")),
+            Assign,
+            IntLiteral(String::from("1")),
+            LineComment(String::from("// one")),
+            NewLine,
+            LineComment(String::from("// two")),
+            NewLine,
+            Exit,
+            Ident(String::from("a")),
+            NewLine,
+            EndOfFile,
+        ];
+        let mut lexer = Lexer::new(source.clone());
+        lexer.preserve_trivia = true;
+        for tokentype in expected.iter() {
+            assert_eq!(lexer.peek().tokentype, *tokentype);
+            lexer.consume().unwrap();
+        }
+
+        let mut config = LexerConfig::default();
+        config.preserve_trivia(true);
+        let mut lexer = Lexer::new_with_config(source.clone(), &config);
+        for tokentype in expected.iter() {
+            assert_eq!(lexer.peek().tokentype, *tokentype);
+            lexer.consume().unwrap();
+        }
+
+        let mut lexer = Lexer::new(source);
+        while lexer.peek().tokentype != EndOfFile {
+            assert!(!lexer.peek().tokentype.is_trivia());
+            lexer.consume().unwrap();
+        }
+    }
+
+    #[test]
+    fn location_ordering() {
+        let loc = |row, col| Location {
+            row,
+            col,
+            ..Default::default()
+        };
+        assert!(loc(1, 5) < loc(1, 6));
+        assert!(loc(1, 80) < loc(2, 1));
+        assert!(loc(3, 1) > loc(2, 40));
+        assert_eq!(loc(2, 3), loc(2, 3));
+
+        assert_eq!(Location::max(loc(1, 80), loc(2, 1)), loc(2, 1));
+        assert_eq!(Location::max(loc(4, 2), loc(4, 1)), loc(4, 2));
+        assert_eq!(Location::min(loc(1, 80), loc(2, 1)), loc(1, 80));
+        assert_eq!(Location::min(loc(4, 2), loc(4, 1)), loc(4, 1));
+    }
+
+    #[test]
+    fn collapsed_newlines() {
+        let source = String::from("a\n\n  \n\nb // c\n\n\nc\n");
+        use TokenType::*;
+        let lex = |collapse_newlines| {
+            let mut lexer = Lexer::new(source.clone());
+            lexer.collapse_newlines = collapse_newlines;
+            let mut tokentypes = Vec::new();
+            while lexer.peek().tokentype != EndOfFile {
+                lexer.consume().unwrap();
+                tokentypes.push(lexer.peek().tokentype);
+            }
+            return tokentypes;
+        };
+        assert_eq!(
+            lex(true),
+            [
+                Ident(String::from("a")),
+                NewLine,
+                Ident(String::from("b")),
+                NewLine,
+                Ident(String::from("c")),
+                NewLine,
+                EndOfFile,
+            ]
+        );
+        assert_eq!(lex(false).iter().filter(|tt| **tt == NewLine).count(), 8);
+    }
+
+    #[test]
+    fn source_lines() {
+        let lexer = Lexer::new(String::from("let a = 1\r\nlet b = 2\n\nexit a + b\n"));
+        assert_eq!(lexer.source_line(1), Some("let a = 1"));
+        assert_eq!(lexer.source_line(2), Some("let b = 2"));
+        assert_eq!(lexer.source_line(3), Some(""));
+        assert_eq!(lexer.source_line(4), Some("exit a + b"));
+        assert_eq!(lexer.source_line(5), None);
+        assert_eq!(lexer.source_line(0), None);
+
+        let lexer = Lexer::new(String::from("let a = 1\nexit a"));
+        assert_eq!(lexer.source_line(2), Some("exit a"));
+        assert_eq!(lexer.source_line(3), None);
+
+        assert_eq!(Lexer::from_tokens(Vec::new()).source_line(1), None);
+    }
+
+    #[test]
+    fn remaining_source() {
+        let mut lexer = Lexer::new(String::from("let a = 5\nexit a"));
+        assert_eq!(lexer.remaining_source(), "let a = 5\nexit a");
+        for _ in 0..3 {
+            lexer.consume().unwrap();
+        }
+        assert_eq!(lexer.peek().tokentype, TT::Assign);
+        assert_eq!(lexer.remaining_source(), " 5\nexit a");
+
+        lexer.rewind();
+        assert_eq!(lexer.remaining_source(), " 5\nexit a");
+
+        while lexer.peek().tokentype != TT::EndOfFile {
+            lexer.consume().unwrap();
+        }
+        assert_eq!(lexer.remaining_source(), "");
+        assert_eq!(Lexer::from_tokens(Vec::new()).remaining_source(), "");
+    }
+
+    #[test]
+    fn lex_all_displays_the_source() {
+        let source = "let mut a: i32 = b != 12 as i64\nif a >= 1 exit f(a, -2) else return";
+        let mut lexer = Lexer::new(String::from(source));
+        let tokens = lexer.lex_all().unwrap();
+        assert_eq!(tokens.last().unwrap().tokentype, TT::EndOfFile);
+        // Every token is displayed as the text it was lexed from.
+        for token in tokens.iter() {
+            assert_eq!(
+                lexer.token_text(token),
+                Some(token.tokentype.to_string()),
+                "{:?}",
+                token
+            );
+        }
+
+        let mut lexer = Lexer::new(String::from("a $"));
+        assert!(matches!(
+            lexer.lex_all(),
+            Err(CompileError::IllegalToken(_))
+        ));
+    }
+
+    #[test]
+    fn token_offsets() {
+        let source = "let a = 12\n// c\n\nexit a + 3";
+        let chars: Vec<char> = source.chars().collect();
+        let mut lexer = Lexer::new(String::from(source));
+        let mut texts = Vec::new();
+        loop {
+            lexer.consume().unwrap();
+            let token = lexer.peek();
+            if token.tokentype == TT::EndOfFile {
+                assert_eq!(token.start.offset, chars.len());
+                break;
+            }
+            let text: String = chars[token.start.offset..token.end.offset].iter().collect();
+            assert_eq!(lexer.token_text(&token), Some(text.clone()));
+            texts.push(text);
+        }
+        assert_eq!(
+            texts,
+            ["let", "a", "=", "12", "\n", "\n", "\n", "exit", "a", "+", "3"]
+        );
+
+        let token = lexer.peek();
+        assert_eq!(
+            Lexer::from_tokens(vec![token.clone()]).token_text(&token),
+            None
+        );
+    }
+
+    #[test]
+    fn span_join() {
+        let file: Option<Rc<str>> = Some(Rc::from("prog.tl"));
+        let token = |col: usize, len: usize, tokentype: TT| Token {
+            file: file.clone(),
+            start: Location {
+                row: 2,
+                col,
+                offset: col - 1,
+            },
+            end: Location {
+                row: 2,
+                col: col + len,
+                offset: col + len - 1,
+            },
+            tokentype,
+        };
+        let start_tok = token(5, 1, TT::Ident(String::from("a")));
+        let end_tok = token(9, 2, TT::IntLiteral(String::from("12")));
+
+        let span = Span::join(&start_tok, &end_tok);
+        assert_eq!(
+            span,
+            Span {
+                file: file.clone(),
+                start: Location {
+                    row: 2,
+                    col: 5,
+                    offset: 4
+                },
+                end: Location {
+                    row: 2,
+                    col: 11,
+                    offset: 10
+                },
+            }
+        );
+        assert_eq!(span.to_string(), "prog.tl:2:5-2:11");
+        assert_eq!(
+            Span::from(&end_tok).start,
+            Location {
+                row: 2,
+                col: 9,
+                offset: 8
+            }
+        );
+    }
 }
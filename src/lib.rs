@@ -0,0 +1,115 @@
+//! The front end of the compiler: the lexer and the parser.
+//!
+//! It only needs `alloc`, so with the `std` feature turned off it builds for `no_std`
+//! targets. Reading source from files or readers needs `std`, as does everything after
+//! parsing, which lives in the `toylang` binary.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+/// `println!` for debugging output, which has nowhere to go without `std`.
+macro_rules! debug_println {
+    () => {
+        debug_println!("")
+    };
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "std")]
+        std::println!($($arg)*);
+        #[cfg(not(feature = "std"))]
+        let _ = core::format_args!($($arg)*);
+    }};
+}
+
+pub mod lexer;
+pub mod parser;
+
+use alloc::{boxed::Box, string::String};
+use lexer::{Location, Span, Token};
+use parser::{Identifier, RExp, Type};
+
+/// Name of the language this compiler compiles.
+pub const LANG_NAME: &str = "toylang";
+
+/// Version of the compiler.
+pub fn version() -> &'static str {
+    return env!("CARGO_PKG_VERSION");
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CompileError {
+    // Lexer
+    IllegalToken(Token),
+    /// A decimal int literal with leading zeros, like `007`, which some languages read as
+    /// octal. Octal literals are written `0o7`.
+    LeadingZero(Location),
+
+    // Parser
+    UnexpectedToken(Token),
+    RExpOnLHS(RExp),
+    ExpectedExpression(Location),
+    ExpectedIdent(Location),
+    ExpectedType(Location),
+    UnknownType(Identifier),
+    ExpectedEBrace(Location),
+    ExpectedECurly(Location),
+    ExpectedBlock(Location),
+    /// The token found after a statement, where a newline should have been.
+    ExpectedNewline(Token),
+    /// `()`, which isn't a value.
+    EmptyBrackets(Span),
+    /// A statement where it isn't allowed, along with where it can go instead.
+    MisplacedStatement(Span, &'static str),
+    /// Brackets, blocks or unary operators nested deeper than `ParserConfig::max_depth`.
+    NestingTooDeep(Location),
+    /// A name declared at the top level of more than one file, along with where it was
+    /// declared first. Boxed, since two identifiers would make every `CompileError` large.
+    Redefinition(Box<(Identifier, Identifier)>),
+    // This error is only used internally in the parser and is not intended to reach the user.
+    // It is used to signify that the parser couldn't find the terminals
+    // that appear at the start of the requested language construct
+    NotFound,
+
+    // Analyzer
+    UndeclaredIdent(Identifier),
+    UninitializedIdent(Identifier),
+    AssignToImmutable(Identifier),
+    TypeMismatch(Location, Type, Type),
+    /// A call with the wrong number of arguments: the function, then the expected and the
+    /// given number.
+    ArgumentCount(Identifier, usize, usize),
+    NotImplemented(Location),
+
+    // Optimizer
+    ConstantOverflow(Span),
+
+    /// A broken invariant of the compiler itself rather than a mistake in the program, along
+    /// with where in the program it was noticed, if that's known.
+    Internal {
+        message: String,
+        location: Option<Location>,
+    },
+}
+
+/// Something that compiles but is likely a mistake.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CompileWarning {
+    // Analyzer
+    /// An `if` whose condition is a constant, so the same branch is always taken.
+    ConstantCondition(Span),
+    /// A declaration that shadows a variable of an enclosing scope, along with where that
+    /// variable was declared. Only reported when asked for.
+    Shadowing(Identifier, Location),
+}
+
+trait HandleNotFound {
+    fn handle_not_found(self, err: CompileError) -> Self;
+}
+
+impl<T> HandleNotFound for Result<T, CompileError> {
+    fn handle_not_found(self, err: CompileError) -> Self {
+        match self {
+            Err(CompileError::NotFound) => Err(err),
+            res => res,
+        }
+    }
+}
@@ -1,80 +1,183 @@
+mod cli;
 mod codegen;
-mod lexer;
-mod parser;
+mod interpret;
+mod optimize;
+#[cfg(test)]
+mod test_utils;
 
-use crate::codegen::{Asm, Env};
-use lexer::{Location, Token};
-use parser::{Identifier, Parser, RExp};
+use crate::codegen::{Asm, Env, StreamError};
+use cli::{parse_args, CliError, STDIN_INPUT, USAGE};
+use interpret::interpret;
+use lexer::{Lexer, Span};
+use optimize::{optimize, OptLevel};
+use parser::{Parser, Program};
+use toylang::{lexer, parser, version, CompileError, CompileWarning, LANG_NAME};
 
-use std::{process::exit, rc::Rc};
-
-#[derive(Debug)]
-pub enum CompileError {
-    // Lexer
-    IllegalToken(Token),
-
-    // Parser
-    UnexpectedToken(Token),
-    RExpOnLHS(RExp),
-    ExpectedExpression(Location),
-    ExpectedIdent(Location),
-    ExpectedEBrace(Location),
-    ExpectedECurly(Location),
-    ExpectedBlock(Location),
-    ExpectedNewline(Location),
-    // This error is only used internally in the parser and is not intended to reach the user.
-    // It is used to signify that the parser couldn't find the terminals
-    // that appear at the start of the requested language construct
-    NotFound,
-
-    // Analyzer
-    UndeclaredIdent(Identifier),
-    UninitializedIdent(Identifier),
-}
-
-trait HandleNotFound {
-    fn handle_not_found(self, err: CompileError) -> Self;
-}
-
-impl<T> HandleNotFound for Result<T, CompileError> {
-    fn handle_not_found(self, err: CompileError) -> Self {
-        match self {
-            Err(CompileError::NotFound) => Err(err),
-            res => res,
-        }
-    }
-}
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    process::exit,
+    rc::Rc,
+};
 
 fn main() -> std::io::Result<()> {
-    let args: Vec<_> = std::env::args().collect();
-    let path: Rc<str> = Rc::from(args[1].clone());
-    let mut parser = Parser::from_file(path.clone());
-    let res = parser.parse_program();
-    match res {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(CliError::HelpRequested) => {
+            print!("{}", USAGE);
+            return Ok(());
+        }
+        Err(CliError::VersionRequested) => {
+            println!("{} {}", LANG_NAME, version());
+            return Ok(());
+        }
         Err(err) => {
+            eprintln!("Error: {}\n\n{}", err, USAGE);
+            exit(2);
+        }
+    };
+    if args.dump_tokens {
+        for input in args.inputs.iter() {
+            let lexer = match input.as_ref() {
+                STDIN_INPUT => Lexer::from_reader(std::io::stdin(), Some(Rc::from("<stdin>"))),
+                _ => Lexer::from_file(input.clone()),
+            };
+            let mut lexer = lexer.unwrap_or_else(|err| could_not_read(input, err));
+            dump_tokens(&mut lexer);
+        }
+        return Ok(());
+    }
+    let mut program = Program { stmts: Vec::new() };
+    for input in args.inputs.iter() {
+        let parser = match input.as_ref() {
+            STDIN_INPUT => Parser::from_reader(std::io::stdin(), Some(Rc::from("<stdin>"))),
+            _ => Parser::from_file(input.clone()),
+        };
+        let mut parser = parser.unwrap_or_else(|err| could_not_read(input, err));
+        if let Err(err) = parser
+            .parse_program()
+            .and_then(|()| program.append(parser.program))
+        {
             println!("Error: {:?}", err);
             exit(1);
         }
-        _ => {
-            println!(
-                "-------------------[AST]-----------------\n{}",
-                parser.program
-            )
-        }
     }
-    // println!(
-    //     "-------------------[AST]-----------------\n{}",
-    //     parser.program
-    // );
+    println!("-------------------[AST]-----------------\n{}", program);
+    // Errors and warnings are looked for in the program as written, since optimizing can
+    // remove code.
     let mut asm = Asm::default();
-    let res = asm.gen(&parser.program.stmts);
-    match res {
-        Err(err) => {
+    asm.warn_shadowing = args.warn_shadowing;
+    asm.dump_symbols = args.dump_symbols;
+    if let Err(err) = asm.gen(&program.stmts) {
+        println!("Error: {:?}", err);
+        exit(1);
+    }
+    for warning in asm.warnings.iter() {
+        println!("Warning: {:?}", warning);
+    }
+    print!("{}", asm.symbol_dump);
+    if args.interpret {
+        match interpret(&program)? {
+            Ok(code) => exit(code as i32),
+            Err(err) => {
+                println!("Error: {:?}", err);
+                exit(1);
+            }
+        }
+    }
+    if args.opt_level > OptLevel::O0 {
+        if let Err(err) = optimize(&mut program.stmts, args.opt_level) {
             println!("Error: {:?}", err);
             exit(1);
         }
-        _ => (),
     }
-    asm.compile(path)?;
+    asm.reset();
+    asm.warn_shadowing = false;
+    asm.dump_symbols = false;
+    asm.emit_comments = args.emit_comments;
+    asm.opt_level = args.opt_level;
+    asm.syntax = args.syntax;
+    for file in args.link_files.iter() {
+        asm.add_link_file(file.as_ref());
+    }
+    // Nothing is optimized at O0, so the code can go straight into the file.
+    let streamed = args.opt_level == OptLevel::O0;
+    if streamed {
+        let path = format!("{}.{}", args.output, args.syntax.extension());
+        let mut file = BufWriter::new(File::create(&path)?);
+        match asm.gen_streaming(&program.stmts, &mut file) {
+            Err(StreamError::Compile(err)) => {
+                drop(file);
+                _ = std::fs::remove_file(&path);
+                println!("Error: {:?}", err);
+                exit(1);
+            }
+            Err(StreamError::Io(err)) => return Err(err),
+            Ok(()) => file.flush()?,
+        }
+    } else if let Err(err) = asm.gen(&program.stmts) {
+        println!("Error: {:?}", err);
+        exit(1);
+    }
+    if args.emit_asm_only {
+        if !streamed {
+            let path = format!("{}.{}", args.output, args.syntax.extension());
+            std::fs::write(path, asm.into_asm())?;
+        }
+        return Ok(());
+    }
+    if args.assemble_only {
+        if !streamed {
+            asm.write_to_file(args.output.as_ref())?;
+        }
+        if let Err(err) = asm.assemble(args.output) {
+            eprintln!("Error: {}", err);
+            exit(1);
+        }
+        return Ok(());
+    }
+    let report = match streamed {
+        true => asm.build(args.output)?,
+        false => asm.compile(args.output)?,
+    };
+    for stderr in [&report.assembler_stderr, &report.gcc_stderr] {
+        if !stderr.is_empty() {
+            eprintln!("{}", stderr);
+        }
+    }
+    if !report.success {
+        // gcc isn't run if the assembler failed.
+        let step = match report.gcc_stderr.is_empty() {
+            true => format!("assembling `{}` failed", report.asm_path),
+            false => format!("linking `{}` failed", report.obj_path),
+        };
+        eprintln!("Error: could not build `{}`: {}", report.exe_path, step);
+        exit(1);
+    }
     return Ok(());
 }
+
+fn could_not_read(input: &str, err: std::io::Error) -> ! {
+    eprintln!("Error: could not read `{}`: {}", input, err);
+    exit(1);
+}
+
+/// Prints every token of the input on a line of its own: where it is, its type and its text.
+/// Illegal tokens are printed too instead of stopping at the first one.
+fn dump_tokens(lexer: &mut Lexer) {
+    lexer.recover_illegal = true;
+    let tokens = match lexer.lex_all() {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            println!("Error: {:?}", err);
+            exit(1);
+        }
+    };
+    for token in tokens.iter() {
+        let debug = format!("{:?}", token.tokentype);
+        // The name of the variant, without its text.
+        let name = debug.split('(').next().unwrap();
+        let text = token.tokentype.to_string();
+        println!("{} {} {:?}", Span::from(token), name, text);
+    }
+}
@@ -0,0 +1,850 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use crate::{
+    lexer::Location,
+    parser::{IntLiteral, Intrinsic, LExp, RExp, Stmt, Term, Type},
+    CompileError,
+};
+
+/// How much effort goes into optimizing the program.
+///
+/// - `O0` compiles the program as written.
+/// - `O1` folds constant expressions, turns adding or subtracting a negation into the opposite
+///   operation, drops operations with their identity, like `a * 1`, and runs the peephole
+///   pass over the generated assembly. It also keeps a variable that was just loaded in its
+///   register, instead of loading it again.
+/// - `O2` also propagates immutable constants into the expressions that read them, removes
+///   code that can never run and variables that are never read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+}
+
+impl FromStr for OptLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Self::O0),
+            "1" => Ok(Self::O1),
+            "2" => Ok(Self::O2),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for OptLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::O0 => write!(f, "O0"),
+            Self::O1 => write!(f, "O1"),
+            Self::O2 => write!(f, "O2"),
+        }
+    }
+}
+
+/// Runs the AST passes enabled at `level`, always in the same order: propagation, folding,
+/// dead code elimination and then dead store elimination. The peephole pass runs on the assembly, see `Asm::opt_level`.
+///
+/// The passes assume the program compiles. Since they can remove code, errors should be looked
+/// for before optimizing. The only error found while optimizing is a constant expression that
+/// overflows, see `RExp::eval_const_checked`.
+pub fn optimize(stmts: &mut Vec<Stmt>, level: OptLevel) -> Result<(), CompileError> {
+    if level >= OptLevel::O2 {
+        Propagator::default().block(stmts);
+    }
+    if level >= OptLevel::O1 {
+        Folder::default().block(stmts)?;
+    }
+    if level >= OptLevel::O2 {
+        eliminate_dead_code(stmts);
+        eliminate_dead_stores(stmts);
+    }
+    return Ok(());
+}
+
+/// `eval_const` knows the value of a cast, but replacing the cast with a literal would also
+/// throw away its type.
+fn contains_cast(rexp: &RExp) -> bool {
+    match rexp {
+        RExp::Cast(_, _) => return true,
+        RExp::Term(term) => return term_contains_cast(term),
+        RExp::Block(_, tail) => return contains_cast(tail),
+        RExp::Add(lhs, rhs)
+        | RExp::Sub(lhs, rhs)
+        | RExp::Mul(lhs, rhs)
+        | RExp::Div(lhs, rhs)
+        | RExp::Equal(lhs, rhs)
+        | RExp::NotEqual(lhs, rhs)
+        | RExp::Less(lhs, rhs)
+        | RExp::LessEqual(lhs, rhs)
+        | RExp::Greater(lhs, rhs)
+        | RExp::GreaterEqual(lhs, rhs)
+        | RExp::And(lhs, rhs)
+        | RExp::Or(lhs, rhs) => return contains_cast(lhs) || contains_cast(rhs),
+    }
+}
+
+fn term_contains_cast(term: &Term) -> bool {
+    match term {
+        Term::LExp(_) | Term::IntLit(_) => return false,
+        Term::Neg(term) | Term::Not(term) => return term_contains_cast(term),
+        Term::Bracketed(rexp) => return contains_cast(rexp),
+        Term::Call(_, args) | Term::Group(args) => return args.iter().any(contains_cast),
+    }
+}
+
+fn literal(value: i64, start: Location) -> Term {
+    return Term::IntLit(IntLiteral {
+        file: None,
+        start,
+        end: start,
+        lexeme: value.to_string(),
+    });
+}
+
+fn rexps_mut(stmt: &mut Stmt) -> Vec<&mut RExp> {
+    match stmt {
+        Stmt::Declare(_) | Stmt::FnDef(_, _) | Stmt::Block(_) => return Vec::new(),
+        Stmt::Initialize(_, rexp)
+        | Stmt::Assign(_, rexp)
+        | Stmt::RExp(rexp)
+        | Stmt::If(rexp, _, _)
+        | Stmt::Exit(rexp)
+        | Stmt::Print(rexp, _)
+        | Stmt::Return(rexp) => return vec![rexp],
+    }
+}
+
+/// Replaces constant subexpressions with their values and simplifies operations with their
+/// identity or zero, e.g. `a + 0` to `a`.
+#[derive(Default)]
+struct Folder {
+    /// For every visible variable, whether it may be an `i32`, see `Folder::may_be_i32`.
+    scopes: Vec<HashMap<String, bool>>,
+}
+
+impl Folder {
+    fn block(&mut self, stmts: &mut [Stmt]) -> Result<(), CompileError> {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts.iter_mut() {
+            self.stmt(stmt)?;
+        }
+        self.scopes.pop();
+        return Ok(());
+    }
+
+    fn define(&mut self, lexeme: &str, may_be_i32: bool) {
+        let scope = self.scopes.last_mut().unwrap();
+        scope.insert(String::from(lexeme), may_be_i32);
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) -> Result<(), CompileError> {
+        for rexp in rexps_mut(stmt) {
+            self.rexp(rexp)?;
+        }
+        match stmt {
+            Stmt::Declare(decl) => self.define(&decl.ident.lexeme, decl.ty == Some(Type::I32)),
+            Stmt::Initialize(decl, rexp) => {
+                let may_be_i32 = match decl.ty {
+                    Some(ty) => ty == Type::I32,
+                    None => self.may_be_i32(rexp),
+                };
+                self.define(&decl.ident.lexeme, may_be_i32);
+            }
+            Stmt::Block(block) => self.block(block)?,
+            Stmt::If(_, if_block, else_stmt) => {
+                self.block(if_block)?;
+                if let Some(else_stmt) = else_stmt {
+                    self.stmt(else_stmt)?;
+                }
+            }
+            // Functions can't see the variables around them.
+            Stmt::FnDef(_, body) => {
+                let scopes = std::mem::take(&mut self.scopes);
+                let res = self.block(body);
+                self.scopes = scopes;
+                res?;
+            }
+            Stmt::Assign(_, _)
+            | Stmt::RExp(_)
+            | Stmt::Exit(_)
+            | Stmt::Print(_, _)
+            | Stmt::Return(_) => (),
+        }
+        return Ok(());
+    }
+
+    /// Replaces every constant subexpression with its value.
+    fn rexp(&mut self, rexp: &mut RExp) -> Result<(), CompileError> {
+        // Looked for first so that an overflow inside of a cast is reported too.
+        let value = rexp.eval_const_checked()?;
+        if !contains_cast(rexp) {
+            if let Some(value) = value {
+                *rexp = RExp::Term(literal(value, rexp.start()));
+                return Ok(());
+            }
+        }
+        match rexp {
+            RExp::Term(term) => self.term(term)?,
+            RExp::Cast(rexp, _) => self.rexp(rexp)?,
+            RExp::Block(block, tail) => {
+                self.scopes.push(HashMap::new());
+                let res = self.block_rexp(block, tail);
+                self.scopes.pop();
+                res?;
+            }
+            RExp::Add(lhs, rhs)
+            | RExp::Sub(lhs, rhs)
+            | RExp::Mul(lhs, rhs)
+            | RExp::Div(lhs, rhs)
+            | RExp::Equal(lhs, rhs)
+            | RExp::NotEqual(lhs, rhs)
+            | RExp::Less(lhs, rhs)
+            | RExp::LessEqual(lhs, rhs)
+            | RExp::Greater(lhs, rhs)
+            | RExp::GreaterEqual(lhs, rhs)
+            | RExp::And(lhs, rhs)
+            | RExp::Or(lhs, rhs) => {
+                self.rexp(lhs)?;
+                self.rexp(rhs)?;
+            }
+        }
+        while fold_negated_operand(rexp) {}
+        self.fold_identity(rexp);
+        return Ok(());
+    }
+
+    /// Folds the statements and the value of a block expression in the scope it opened.
+    fn block_rexp(&mut self, block: &mut [Stmt], tail: &mut RExp) -> Result<(), CompileError> {
+        for stmt in block.iter_mut() {
+            self.stmt(stmt)?;
+        }
+        return self.rexp(tail);
+    }
+
+    fn term(&mut self, term: &mut Term) -> Result<(), CompileError> {
+        match term {
+            Term::LExp(_) | Term::IntLit(_) => return Ok(()),
+            Term::Neg(term) | Term::Not(term) => return self.term(term),
+            Term::Bracketed(rexp) => return self.rexp(rexp),
+            Term::Call(_, args) | Term::Group(args) => {
+                for arg in args.iter_mut() {
+                    self.rexp(arg)?;
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    /// Turns `a + 0`, `0 + a`, `a - 0`, `a * 1`, `1 * a` and `a / 1` into `a`, and `a * 0` and
+    /// `0 * a` into `0`. The latter only if `a` has no side effects and isn't an `i32`, since
+    /// the `0` has no type of its own and would change the type that is inferred from it.
+    fn fold_identity(&self, rexp: &mut RExp) {
+        let start = rexp.start();
+        let constant = |rexp: &RExp| match rexp {
+            RExp::Term(Term::IntLit(_)) => rexp.eval_const(),
+            _ => None,
+        };
+        let (keep_lhs, keep_rhs) = match rexp {
+            RExp::Add(lhs, rhs) => (constant(rhs) == Some(0), constant(lhs) == Some(0)),
+            RExp::Sub(_, rhs) => (constant(rhs) == Some(0), false),
+            RExp::Div(_, rhs) => (constant(rhs) == Some(1), false),
+            RExp::Mul(lhs, rhs) => {
+                let erases = |rexp: &RExp| !has_side_effects(rexp) && !self.may_be_i32(rexp);
+                if (constant(rhs) == Some(0) && erases(lhs))
+                    || (constant(lhs) == Some(0) && erases(rhs))
+                {
+                    *rexp = RExp::Term(literal(0, start));
+                    return;
+                }
+                (constant(rhs) == Some(1), constant(lhs) == Some(1))
+            }
+            _ => return,
+        };
+        if !keep_lhs && !keep_rhs {
+            return;
+        }
+        *rexp = match std::mem::replace(rexp, RExp::Term(literal(0, start))) {
+            RExp::Add(lhs, rhs)
+            | RExp::Sub(lhs, rhs)
+            | RExp::Mul(lhs, rhs)
+            | RExp::Div(lhs, rhs) => match keep_lhs {
+                true => *lhs,
+                false => *rhs,
+            },
+            _ => unreachable!(),
+        };
+    }
+
+    /// Whether `rexp` may have the type `i32`, following how `Asm` infers types. Variables
+    /// that aren't known are assumed to be `i32`.
+    fn may_be_i32(&self, rexp: &RExp) -> bool {
+        match rexp {
+            RExp::Term(term) => return self.term_may_be_i32(term),
+            RExp::Cast(_, ty) => return *ty == Type::I32,
+            RExp::Add(lhs, rhs)
+            | RExp::Sub(lhs, rhs)
+            | RExp::Mul(lhs, rhs)
+            | RExp::Div(lhs, rhs) => return self.may_be_i32(lhs) || self.may_be_i32(rhs),
+            // Blocks and comparisons have no type of their own.
+            RExp::Block(_, _)
+            | RExp::Equal(_, _)
+            | RExp::NotEqual(_, _)
+            | RExp::Less(_, _)
+            | RExp::LessEqual(_, _)
+            | RExp::Greater(_, _)
+            | RExp::GreaterEqual(_, _)
+            | RExp::And(_, _)
+            | RExp::Or(_, _) => return false,
+        }
+    }
+
+    fn term_may_be_i32(&self, term: &Term) -> bool {
+        match term {
+            Term::LExp(LExp::Ident(ident)) => {
+                for scope in self.scopes.iter().rev() {
+                    if let Some(may_be_i32) = scope.get(&ident.lexeme) {
+                        return *may_be_i32;
+                    }
+                }
+                return true;
+            }
+            Term::LExp(LExp::Field(_, _)) => return true,
+            Term::IntLit(_) | Term::Not(_) | Term::Group(_) | Term::Call(_, _) => return false,
+            Term::Neg(term) => return self.term_may_be_i32(term),
+            Term::Bracketed(rexp) => return self.may_be_i32(rexp),
+        }
+    }
+}
+
+/// Turns `a - -b` into `a + b` and `a + -b` into `a - b`, which saves the `neg`.
+/// Returns whether `rexp` changed, since the new right hand side may be negated again.
+fn fold_negated_operand(rexp: &mut RExp) -> bool {
+    let start = rexp.start();
+    let (RExp::Add(_, rhs) | RExp::Sub(_, rhs)) = rexp else {
+        return false;
+    };
+    let RExp::Term(Term::Neg(negated)) = rhs.as_mut() else {
+        return false;
+    };
+    let negated = std::mem::replace(negated.as_mut(), literal(0, start));
+    **rhs = RExp::Term(negated);
+    *rexp = match std::mem::replace(rexp, RExp::Term(literal(0, start))) {
+        RExp::Add(lhs, rhs) => RExp::Sub(lhs, rhs),
+        RExp::Sub(lhs, rhs) => RExp::Add(lhs, rhs),
+        _ => unreachable!(),
+    };
+    return true;
+}
+
+/// Replaces reads of immutable `i64` variables that were initialized with a constant by that
+/// constant. `i32` variables are left alone, since a literal has no type.
+#[derive(Default)]
+struct Propagator {
+    /// The value of every visible variable, `None` if it isn't a known constant.
+    scopes: Vec<HashMap<String, Option<i64>>>,
+}
+
+impl Propagator {
+    fn block(&mut self, stmts: &mut [Stmt]) {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts.iter_mut() {
+            self.stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn define(&mut self, lexeme: &str, value: Option<i64>) {
+        let scope = self.scopes.last_mut().unwrap();
+        scope.insert(String::from(lexeme), value);
+    }
+
+    fn lookup(&self, lexeme: &str) -> Option<i64> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(value) = scope.get(lexeme) {
+                return *value;
+            }
+        }
+        return None;
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        for rexp in rexps_mut(stmt) {
+            self.rexp(rexp);
+        }
+        match stmt {
+            Stmt::Declare(decl) => self.define(&decl.ident.lexeme, None),
+            Stmt::Initialize(decl, rexp) => {
+                let is_i64 = decl.ty.unwrap_or(Type::I64) == Type::I64;
+                let value = match !decl.mutable && is_i64 && !contains_cast(rexp) {
+                    true => rexp.eval_const(),
+                    false => None,
+                };
+                self.define(&decl.ident.lexeme, value);
+            }
+            Stmt::Block(block) => self.block(block),
+            Stmt::If(_, if_block, else_stmt) => {
+                self.block(if_block);
+                if let Some(else_stmt) = else_stmt {
+                    self.stmt(else_stmt);
+                }
+            }
+            // Functions can't see the variables around them.
+            Stmt::FnDef(_, body) => {
+                let scopes = std::mem::take(&mut self.scopes);
+                self.block(body);
+                self.scopes = scopes;
+            }
+            Stmt::Assign(_, _)
+            | Stmt::RExp(_)
+            | Stmt::Exit(_)
+            | Stmt::Print(_, _)
+            | Stmt::Return(_) => (),
+        }
+    }
+
+    fn rexp(&mut self, rexp: &mut RExp) {
+        match rexp {
+            RExp::Term(term) => self.term(term),
+            RExp::Cast(rexp, _) => self.rexp(rexp),
+            RExp::Block(block, tail) => {
+                self.scopes.push(HashMap::new());
+                for stmt in block.iter_mut() {
+                    self.stmt(stmt);
+                }
+                self.rexp(tail);
+                self.scopes.pop();
+            }
+            RExp::Add(lhs, rhs)
+            | RExp::Sub(lhs, rhs)
+            | RExp::Mul(lhs, rhs)
+            | RExp::Div(lhs, rhs)
+            | RExp::Equal(lhs, rhs)
+            | RExp::NotEqual(lhs, rhs)
+            | RExp::Less(lhs, rhs)
+            | RExp::LessEqual(lhs, rhs)
+            | RExp::Greater(lhs, rhs)
+            | RExp::GreaterEqual(lhs, rhs)
+            | RExp::And(lhs, rhs)
+            | RExp::Or(lhs, rhs) => {
+                self.rexp(lhs);
+                self.rexp(rhs);
+            }
+        }
+    }
+
+    fn term(&mut self, term: &mut Term) {
+        match term {
+            Term::LExp(LExp::Ident(ident)) => {
+                if let Some(value) = self.lookup(&ident.lexeme) {
+                    *term = literal(value, ident.start);
+                }
+            }
+            Term::LExp(_) | Term::IntLit(_) => (),
+            Term::Neg(inner) | Term::Not(inner) => self.term(inner),
+            Term::Bracketed(rexp) => self.rexp(rexp),
+            Term::Call(_, args) | Term::Group(args) => {
+                args.iter_mut().for_each(|arg| self.rexp(arg))
+            }
+        }
+    }
+}
+
+/// Removes statements after an `exit` or a `return`, and the branches of an `if` whose
+/// condition is a known constant. Function definitions are kept, they don't run in place.
+fn eliminate_dead_code(stmts: &mut Vec<Stmt>) {
+    let mut live = Vec::new();
+    let mut reachable = true;
+    for mut stmt in std::mem::take(stmts) {
+        if !reachable && !matches!(stmt, Stmt::FnDef(_, _)) {
+            continue;
+        }
+        if let Stmt::If(_, _, _) = stmt {
+            match eliminate_dead_branches(stmt) {
+                Some(kept) => stmt = kept,
+                None => continue,
+            }
+        }
+        eliminate_nested_dead_code(&mut stmt);
+        if let Stmt::Exit(_) | Stmt::Return(_) = stmt {
+            reachable = false;
+        }
+        live.push(stmt);
+    }
+    *stmts = live;
+}
+
+fn eliminate_nested_dead_code(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Block(block) | Stmt::FnDef(_, block) => eliminate_dead_code(block),
+        Stmt::If(_, if_block, else_stmt) => {
+            eliminate_dead_code(if_block);
+            if let Some(else_stmt) = else_stmt {
+                eliminate_nested_dead_code(else_stmt);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Picks the branch of an `if` with a constant condition, which is `None` if no branch runs.
+fn eliminate_dead_branches(stmt: Stmt) -> Option<Stmt> {
+    let Stmt::If(condition, if_block, else_stmt) = stmt else {
+        return Some(stmt);
+    };
+    match condition.eval_const() {
+        None => return Some(Stmt::If(condition, if_block, else_stmt)),
+        Some(0) => return else_stmt.and_then(|else_stmt| eliminate_dead_branches(*else_stmt)),
+        Some(_) => return Some(Stmt::Block(if_block)),
+    }
+}
+
+/// Removes variables that are written but never read, along with every assignment to them.
+/// Values that may have side effects are still evaluated, as expression statements.
+fn eliminate_dead_stores(stmts: &mut Vec<Stmt>) {
+    for i in 0..stmts.len() {
+        let (Stmt::Declare(decl) | Stmt::Initialize(decl, _)) = &stmts[i] else {
+            continue;
+        };
+        let lexeme = decl.ident.lexeme.clone();
+        let rest = &mut stmts[i + 1..];
+        // A redeclaration's own value, like in `let x = x + 1`, still reads the variable.
+        let scope_len = rest
+            .iter()
+            .position(|stmt| stmt.declares(&lexeme))
+            .map_or(rest.len(), |pos| pos + 1);
+        let scope = &mut rest[..scope_len];
+        if scope.iter().any(|stmt| stmt.reads(&lexeme)) {
+            continue;
+        }
+        drop_stores(scope, &lexeme);
+        stmts[i] = discard(std::mem::replace(&mut stmts[i], removed()));
+    }
+    stmts.retain(|stmt| !matches!(stmt, Stmt::Block(block) if block.is_empty()));
+
+    // Nested scopes go last, since the stores removed above may be inside of them.
+    for stmt in stmts.iter_mut() {
+        match stmt {
+            Stmt::Block(block) | Stmt::FnDef(_, block) => eliminate_dead_stores(block),
+            Stmt::If(_, if_block, else_stmt) => {
+                eliminate_dead_stores(if_block);
+                let mut else_block: Vec<Stmt> = else_stmt.take().into_iter().map(|s| *s).collect();
+                eliminate_dead_stores(&mut else_block);
+                *else_stmt = else_block.pop().map(Box::new);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Stands in for a removed statement. Empty blocks don't do anything, so they're all removed.
+fn removed() -> Stmt {
+    return Stmt::Block(Vec::new());
+}
+
+/// What is left of a dead store: the stored value, if computing it may have side effects.
+fn discard(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Initialize(_, rexp) | Stmt::Assign(_, rexp) if has_side_effects(&rexp) => {
+            return Stmt::RExp(rexp)
+        }
+        _ => return removed(),
+    }
+}
+
+/// Removes the assignments to `lexeme` up to where a nested scope shadows it.
+fn drop_stores(stmts: &mut [Stmt], lexeme: &str) {
+    for stmt in stmts.iter_mut() {
+        if stmt.declares(lexeme) {
+            return;
+        }
+        match stmt {
+            Stmt::Assign(LExp::Ident(ident), _) if ident.lexeme == lexeme => {
+                *stmt = discard(std::mem::replace(stmt, removed()));
+            }
+            Stmt::Block(block) => drop_stores(block, lexeme),
+            Stmt::If(_, if_block, else_stmt) => {
+                drop_stores(if_block, lexeme);
+                if let Some(else_stmt) = else_stmt {
+                    drop_stores(std::slice::from_mut(else_stmt.as_mut()), lexeme);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Dividing can end the program when the divisor is zero, and fault when dividing the
+/// smallest `i64` by `-1`.
+fn has_side_effects(rexp: &RExp) -> bool {
+    match rexp {
+        RExp::Term(term) => return term_has_side_effects(term),
+        RExp::Cast(rexp, _) => return has_side_effects(rexp),
+        RExp::Block(_, _) => return true,
+        RExp::Div(lhs, rhs) => {
+            let safe_divisor = !matches!(rhs.eval_const(), None | Some(0) | Some(-1));
+            return !safe_divisor || has_side_effects(lhs) || has_side_effects(rhs);
+        }
+        RExp::Add(lhs, rhs)
+        | RExp::Sub(lhs, rhs)
+        | RExp::Mul(lhs, rhs)
+        | RExp::Equal(lhs, rhs)
+        | RExp::NotEqual(lhs, rhs)
+        | RExp::Less(lhs, rhs)
+        | RExp::LessEqual(lhs, rhs)
+        | RExp::Greater(lhs, rhs)
+        | RExp::GreaterEqual(lhs, rhs)
+        | RExp::And(lhs, rhs)
+        | RExp::Or(lhs, rhs) => return has_side_effects(lhs) || has_side_effects(rhs),
+    }
+}
+
+fn term_has_side_effects(term: &Term) -> bool {
+    match term {
+        Term::LExp(_) | Term::IntLit(_) => return false,
+        Term::Neg(term) | Term::Not(term) => return term_has_side_effects(term),
+        Term::Bracketed(rexp) => return has_side_effects(rexp),
+        Term::Group(rexps) => return rexps.iter().any(has_side_effects),
+        // Intrinsics only compute a value, anything else may be a function that does more.
+        Term::Call(ident, args) => match Intrinsic::resolve(ident, args) {
+            Ok(_) => return args.iter().any(has_side_effects),
+            Err(_) => return true,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codegen::Asm, parser::Parser, test_utils::assert_exit_code_at};
+
+    fn optimized(source: &str, level: OptLevel) -> Vec<String> {
+        let mut parser = Parser::new(String::from(source));
+        parser.parse_program().unwrap();
+        optimize(&mut parser.program.stmts, level).unwrap();
+        return parser
+            .program
+            .stmts
+            .iter()
+            .map(|stmt| stmt.to_string())
+            .collect();
+    }
+
+    fn assembly(source: &str, level: OptLevel) -> String {
+        let mut parser = Parser::new(String::from(source));
+        parser.parse_program().unwrap();
+        optimize(&mut parser.program.stmts, level).unwrap();
+        let mut asm = Asm::default();
+        asm.opt_level = level;
+        asm.gen(&parser.program.stmts).unwrap();
+        let mut buf = Vec::new();
+        asm.write_to(&mut buf).unwrap();
+        return String::from_utf8(buf).unwrap();
+    }
+
+    fn instructions(source: &str, level: OptLevel) -> usize {
+        return assembly(source, level)
+            .lines()
+            .filter(|line| line.starts_with("    ") && !line.trim_start().starts_with(';'))
+            .filter(|line| !line.trim().is_empty())
+            .count();
+    }
+
+    #[test]
+    fn levels() {
+        let source = "let a = 2 * 3\nlet mut b = a + 1\nexit b - (4 / 2)\n";
+        assert_eq!(
+            optimized(source, OptLevel::O0),
+            [
+                "Initialize(a, (2 * 3))",
+                "Initialize(mut b, (a + 1))",
+                "Exit((b - ((4 / 2))))"
+            ]
+        );
+        assert_eq!(
+            optimized(source, OptLevel::O1),
+            [
+                "Initialize(a, 6)",
+                "Initialize(mut b, (a + 1))",
+                "Exit((b - 2))"
+            ]
+        );
+        assert_eq!(
+            optimized(source, OptLevel::O2),
+            ["Initialize(mut b, 7)", "Exit((b - 2))"]
+        );
+    }
+
+    #[test]
+    fn propagation_respects_scopes_and_types() {
+        let source = "let a = 1\n{\n    let a\n    a = 5\n    exit a\n}\nlet b: i32 = 2\nlet c = 3 as i32\nexit a + b + c\n";
+        assert_eq!(
+            optimized(source, OptLevel::O2),
+            [
+                "Initialize(a, 1)",
+                "{\n  Declare(a)\n  Assign(a, 5)\n  Exit(a)\n}",
+                "Initialize(b: i32, 2)",
+                "Initialize(c, 3 as i32)",
+                "Exit(((1 + b) + c))",
+            ]
+        );
+    }
+
+    #[test]
+    fn dead_code() {
+        let source = "if 2 > 1 {\n    exit 1\n    exit 2\n} else {\n    exit 3\n}\nif 0 {\n    exit 4\n}\nexit 5\nexit 6\nfn main() {\n    return 7\n    return 8\n}\n";
+        assert_eq!(
+            optimized(source, OptLevel::O2),
+            ["{\n  Exit(1)\n}", "Exit(5)", "fn main() {\n  Return(7)\n}"]
+        );
+    }
+
+    #[test]
+    fn dead_stores() {
+        let source = "let mut a = 2 * 21\na = 3\nlet b = 1\nlet c = b / 0\nlet mut d\n{\n    d = 4\n    let d = 5\n    exit d\n}\nexit 0\n";
+        assert_eq!(
+            optimized(source, OptLevel::O2),
+            ["RExp((1 / 0))", "{\n  Exit(5)\n}", "Exit(0)"]
+        );
+        // The first `x` is read by the value of the `let` that shadows it.
+        assert_eq!(
+            optimized("let mut x = 1\nlet x = x + 1\nexit x\n", OptLevel::O2),
+            ["Initialize(mut x, 1)", "Initialize(x, (x + 1))", "Exit(x)"]
+        );
+
+        let mut parser = Parser::new(String::from(
+            "let x = 2 * 21\nlet mut y\ny = 1 + 1\nexit 0\n",
+        ));
+        parser.parse_program().unwrap();
+        optimize(&mut parser.program.stmts, OptLevel::O2).unwrap();
+        let mut asm = Asm::default();
+        asm.opt_level = OptLevel::O2;
+        asm.gen(&parser.program.stmts).unwrap();
+        let mut buf = Vec::new();
+        asm.write_to(&mut buf).unwrap();
+        assert!(!String::from_utf8(buf).unwrap().contains("mov qword [rbp-"));
+    }
+
+    #[test]
+    fn constant_overflow() {
+        assert_eq!(
+            optimized("exit 1000000000 * 1000000000\n", OptLevel::O1),
+            ["Exit(1000000000000000000)"]
+        );
+        assert_eq!(
+            optimized("exit (9223372036854775807 + 1) as i32\n", OptLevel::O0),
+            ["Exit(((9223372036854775807 + 1)) as i32)"]
+        );
+
+        let cases = [
+            ("exit 1 + 10000000000 * 10000000000\n", (1, 10), (1, 34)),
+            (
+                "let a = 2\nexit -(-9223372036854775807 - 1) / a\n",
+                (2, 9),
+                (2, 32),
+            ),
+            ("exit (9223372036854775807 + 1) as i32\n", (1, 7), (1, 30)),
+            ("let a = 9223372036854775807\nexit a + 1\n", (2, 6), (2, 10)),
+        ];
+        for (source, start, end) in cases {
+            let mut parser = Parser::new(String::from(source));
+            parser.parse_program().unwrap();
+            let Err(CompileError::ConstantOverflow(span)) =
+                optimize(&mut parser.program.stmts, OptLevel::O2)
+            else {
+                panic!("Expected an overflow in {:?}", source);
+            };
+            let row_col = |loc: Location| (loc.row, loc.col);
+            assert_eq!(
+                (row_col(span.start), row_col(span.end)),
+                (start, end),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn negated_operands() {
+        assert_eq!(
+            optimized("let a = 5\nlet b = 3\nexit a - -b + --b\n", OptLevel::O1)[2],
+            "Exit(((a + b) + b))"
+        );
+        assert_eq!(
+            optimized("let a = 5\nexit a + -(a - 1)\n", OptLevel::O1)[1],
+            "Exit((a - ((a - 1))))"
+        );
+
+        for source in ["exit 5 - -3\n", "let a = 5\nlet b = 3\nexit a - -b\n"] {
+            let asm = assembly(source, OptLevel::O1);
+            assert!(!asm.contains("neg "), "{}", asm);
+            assert_exit_code_at(source, OptLevel::O1, 8);
+        }
+        let asm = assembly("let a = 5\nlet b = 3\nexit a - -b\n", OptLevel::O1);
+        assert!(asm.contains("add rax, rbx"), "{}", asm);
+    }
+
+    #[test]
+    fn identities() {
+        let source = "let y = 5\nexit y * 1 + 0\n";
+        assert_eq!(optimized(source, OptLevel::O1)[1], "Exit(y)");
+        let asm = assembly(source, OptLevel::O1);
+        assert!(!asm.contains("imul") && !asm.contains("add rax"), "{}", asm);
+        assert_exit_code_at(source, OptLevel::O1, 5);
+
+        let cases = [
+            ("exit 0 + y - 0", "Exit(y)"),
+            ("exit 1 * (y / 1)", "Exit((y))"),
+            ("exit y * 0 + 3", "Exit(3)"),
+            ("exit 0 * -y", "Exit(0)"),
+            // The call and the division by `z` may have side effects.
+            ("exit f() * 0", "Exit((f() * 0))"),
+            ("exit 0 * (y / z)", "Exit((0 * ((y / z))))"),
+            // The `0` would lose the type of `b`.
+            ("exit b * 0", "Exit((b * 0))"),
+            ("let c = b\nexit 0 * -c", "Exit((0 * -c))"),
+            ("exit (y as i32) * 0", "Exit(((y as i32) * 0))"),
+            ("exit (b as i64) * 0", "Exit(0)"),
+        ];
+        for (exit, expected) in cases {
+            let source = format!("let y = 5\nlet mut z = 1\nlet b: i32 = 2\n{}\n", exit);
+            let stmts = optimized(&source, OptLevel::O1);
+            assert_eq!(stmts.last().unwrap(), expected, "{:?}", exit);
+        }
+
+        // `c` is still inferred to be an `i32`.
+        let source = "let b: i32 = 2\nlet c = b * 0\nlet d: i32 = c + 1\nexit d\n";
+        assembly(source, OptLevel::O1);
+        assert_exit_code_at(source, OptLevel::O1, 1);
+    }
+
+    #[test]
+    fn o2_emits_fewer_instructions() {
+        let source = "let a = (1 + 2) * (3 + 4)\nlet b = a / 3 - 2 * (5 - 1)\nlet mut c = b\nif a > b {\n    c = c + a - b\n}\nexit c\n";
+        let unoptimized = instructions(source, OptLevel::O0);
+        let optimized_count = instructions(source, OptLevel::O2);
+        assert!(
+            optimized_count < unoptimized,
+            "{} >= {}",
+            optimized_count,
+            unoptimized
+        );
+
+        let expected = {
+            let (a, b) = ((1 + 2) * (3 + 4), 21 / 3 - 2 * (5 - 1));
+            let mut c = b;
+            if a > b {
+                c = c + a - b;
+            }
+            c
+        };
+        assert_eq!(optimized(source, OptLevel::O2).last().unwrap(), "Exit(c)");
+        for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            assert_exit_code_at(source, level, expected);
+        }
+    }
+}
@@ -1,29 +1,82 @@
 use crate::{
-    lexer::{Location, Token, TokenType as TT},
+    lexer::{int_literal_radix, LexerConfig, Location, Span, Token, TokenType as TT},
     CompileError, HandleNotFound,
 };
 
 use super::lexer::Lexer;
-use std::{fmt::Display, rc::Rc};
+use alloc::{boxed::Box, format, rc::Rc, string::String, vec, vec::Vec};
+use core::fmt::Display;
+#[cfg(feature = "std")]
+use std::io::Read;
 
+/// The top-level statements of a program, in the order they appear in the source. Passes
+/// that rewrite the program can rely on that order, it's the order they're executed in.
 #[derive(Debug)]
 pub struct Program {
     pub stmts: Vec<Stmt>,
 }
 
+impl Program {
+    pub fn iter(&self) -> core::slice::Iter<'_, Stmt> {
+        return self.stmts.iter();
+    }
+
+    pub fn iter_mut(&mut self) -> core::slice::IterMut<'_, Stmt> {
+        return self.stmts.iter_mut();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.stmts.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.stmts.is_empty();
+    }
+
+    /// Adds `stmt` after the last statement.
+    pub fn push(&mut self, stmt: Stmt) {
+        self.stmts.push(stmt);
+    }
+
+    /// Appends the statements of `other`, parsed from another file, so that both compile into
+    /// one executable. The names `other` declares at its top level, variables and functions
+    /// alike, must not be declared at the top level of this program already.
+    pub fn append(&mut self, other: Program) -> Result<(), CompileError> {
+        for stmt in other.stmts.iter() {
+            let Some(ident) = stmt.declared_name() else {
+                continue;
+            };
+            let previous = self
+                .stmts
+                .iter()
+                .filter_map(|stmt| stmt.declared_name())
+                .find(|previous| previous.lexeme == ident.lexeme);
+            if let Some(previous) = previous {
+                return Err(CompileError::Redefinition(Box::new((
+                    ident.clone(),
+                    previous.clone(),
+                ))));
+            }
+        }
+        self.stmts.extend(other.stmts);
+        return Ok(());
+    }
+}
+
 impl Display for Program {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Program {{\n")?;
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "Program {{")?;
         for stmt in self.stmts.iter() {
-            write!(f, "{}\n", stmt)?;
-            write!(f, "---------------------------------------------------\n")?;
+            write!(f, "{:1$}", "", Stmt::INDENT_WIDTH)?;
+            stmt.fmt_indented(f, Stmt::INDENT_WIDTH)?;
+            writeln!(f)?;
         }
         write!(f, "}}")?;
         return Ok(());
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct IntLiteral {
     pub file: Option<Rc<str>>,
     pub start: Location,
@@ -32,10 +85,44 @@ pub struct IntLiteral {
 }
 
 impl Display for IntLiteral {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.lexeme)
     }
 }
+impl IntLiteral {
+    /// The literal's value, read in the base its prefix gives. `None` if it doesn't fit an i64.
+    pub fn value(&self) -> Option<i64> {
+        let mut chars = self.lexeme.chars();
+        if let (Some('0'), Some(prefix @ ('x' | 'b' | 'o'))) = (chars.next(), chars.next()) {
+            return i64::from_str_radix(chars.as_str(), int_literal_radix(prefix)).ok();
+        }
+        return self.lexeme.parse().ok();
+    }
+
+    /// The literal's value wrapped to 64 bits, the way nasm reads a constant that doesn't fit.
+    pub fn wrapping_value(&self) -> i64 {
+        // Literals made by constant folding may be negative, but those always fit.
+        if let Some(value) = self.value() {
+            return value;
+        }
+        let mut chars = self.lexeme.chars();
+        let radix = match (chars.next(), chars.next()) {
+            (Some('0'), Some(prefix @ ('x' | 'b' | 'o'))) => int_literal_radix(prefix),
+            _ => {
+                chars = self.lexeme.chars();
+                10
+            }
+        };
+        let value = chars.fold(0u64, |value, digit| {
+            let Some(digit) = digit.to_digit(radix) else {
+                panic!("[IntLiteral.wrapping_value] `{}` isn't a digit!", digit);
+            };
+            return value.wrapping_mul(radix as u64).wrapping_add(digit as u64);
+        });
+        return value as i64;
+    }
+}
+
 impl From<Token> for IntLiteral {
     fn from(value: Token) -> Self {
         let TT::IntLiteral(lexeme) = value.tokentype else {
@@ -50,7 +137,7 @@ impl From<Token> for IntLiteral {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Identifier {
     pub file: Option<Rc<str>>,
     pub start: Location,
@@ -65,7 +152,7 @@ impl Into<LExp> for Identifier {
 }
 
 impl Display for Identifier {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.lexeme)
     }
 }
@@ -84,12 +171,19 @@ impl From<Token> for Identifier {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum Term {
     LExp(LExp),
     IntLit(IntLiteral),
     Neg(Box<Term>),
+    /// `!term`, `1` if the term is `0` and `0` otherwise.
+    Not(Box<Term>),
     Bracketed(Box<RExp>),
+    /// `(rexp, ...)`, the start of tuples. A single expression is only a group if it's
+    /// followed by a comma. Groups can't be compiled yet.
+    Group(Vec<RExp>),
+    /// `f(arg, ...)`. Only calls to an `Intrinsic` can be compiled yet.
+    Call(Identifier, Vec<RExp>),
 }
 
 impl TryFrom<Token> for Term {
@@ -104,96 +198,481 @@ impl TryFrom<Token> for Term {
     }
 }
 
+impl Term {
+    /// Location of the first token of the term.
+    pub fn start(&self) -> Location {
+        match self {
+            Self::LExp(lexp) => lexp.start(),
+            Self::IntLit(intlit) => intlit.start,
+            Self::Neg(term) | Self::Not(term) => term.start(),
+            Self::Bracketed(rexp) => rexp.start(),
+            Self::Group(rexps) => rexps[0].start(),
+            Self::Call(ident, _) => ident.start,
+        }
+    }
+
+    /// See `RExp::prints`.
+    pub fn prints(&self) -> bool {
+        match self {
+            Self::LExp(_) | Self::IntLit(_) => return false,
+            Self::Neg(term) | Self::Not(term) => return term.prints(),
+            Self::Bracketed(rexp) => return rexp.prints(),
+            Self::Group(rexps) | Self::Call(_, rexps) => return rexps.iter().any(RExp::prints),
+        }
+    }
+
+    /// See `RExp::reads`.
+    pub fn reads(&self, lexeme: &str) -> bool {
+        match self {
+            Self::LExp(lexp) => return lexp.refers_to(lexeme),
+            Self::IntLit(_) => return false,
+            Self::Neg(term) | Self::Not(term) => return term.reads(lexeme),
+            Self::Bracketed(rexp) => return rexp.reads(lexeme),
+            // Functions can't see the variables of their caller.
+            Self::Call(_, args) | Self::Group(args) => {
+                return args.iter().any(|arg| arg.reads(lexeme))
+            }
+        }
+    }
+
+    /// The source code of the term, apart from a leading `-`, `!` or brackets.
+    pub fn span(&self) -> Span {
+        match self {
+            Self::LExp(lexp) => lexp.span(),
+            Self::IntLit(intlit) => Span {
+                file: intlit.file.clone(),
+                start: intlit.start,
+                end: intlit.end,
+            },
+            Self::Neg(term) | Self::Not(term) => term.span(),
+            Self::Bracketed(rexp) => rexp.span(),
+            Self::Group(rexps) => Span {
+                end: rexps[rexps.len() - 1].span().end,
+                ..rexps[0].span()
+            },
+            Self::Call(ident, args) => Span {
+                file: ident.file.clone(),
+                start: ident.start,
+                end: args.last().map_or(ident.end, |arg| arg.span().end),
+            },
+        }
+    }
+
+    /// See `RExp::eval_const`.
+    pub fn eval_const(&self) -> Option<i64> {
+        match self {
+            Self::LExp(_) | Self::Group(_) => return None,
+            Self::Call(ident, args) => {
+                let intrinsic = Intrinsic::resolve(ident, args).ok()?;
+                let args: Option<Vec<i64>> = args.iter().map(|arg| arg.eval_const()).collect();
+                return Some(intrinsic.eval(&args?));
+            }
+            Self::IntLit(intlit) => return intlit.value(),
+            Self::Neg(term) => return Some(term.eval_const()?.wrapping_neg()),
+            Self::Not(term) => return Some((term.eval_const()? == 0) as i64),
+            Self::Bracketed(rexp) => return rexp.eval_const(),
+        }
+    }
+
+    /// See `RExp::eval_const_checked`.
+    pub fn eval_const_checked(&self) -> Result<Option<i64>, CompileError> {
+        match self {
+            Self::Neg(term) => {
+                let Some(value) = term.eval_const_checked()? else {
+                    return Ok(None);
+                };
+                match value.checked_neg() {
+                    Some(value) => return Ok(Some(value)),
+                    None => return Err(CompileError::ConstantOverflow(self.span())),
+                }
+            }
+            Self::Not(term) => {
+                return Ok(term.eval_const_checked()?.map(|value| (value == 0) as i64))
+            }
+            Self::Bracketed(rexp) => return rexp.eval_const_checked(),
+            Self::Call(ident, args) => {
+                let mut values = Vec::new();
+                for arg in args.iter() {
+                    values.push(arg.eval_const_checked()?);
+                }
+                let Ok(intrinsic) = Intrinsic::resolve(ident, args) else {
+                    return Ok(None);
+                };
+                let values: Option<Vec<i64>> = values.into_iter().collect();
+                return Ok(values.map(|values| intrinsic.eval(&values)));
+            }
+            Self::Group(rexps) => {
+                for rexp in rexps.iter() {
+                    rexp.eval_const_checked()?;
+                }
+                return Ok(None);
+            }
+            Self::LExp(_) | Self::IntLit(_) => return Ok(self.eval_const()),
+        }
+    }
+}
+
+/// A function built into the language. It's called like any other function would be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intrinsic {
+    /// `sat_add(a, b)`, `a + b` clamped to the range of `i64` instead of wrapping.
+    SatAdd,
+    /// `sat_sub(a, b)`, `a - b` clamped to the range of `i64` instead of wrapping.
+    SatSub,
+}
+
+impl Intrinsic {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sat_add" => return Some(Self::SatAdd),
+            "sat_sub" => return Some(Self::SatSub),
+            _ => return None,
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            Self::SatAdd | Self::SatSub => return 2,
+        }
+    }
+
+    /// The intrinsic that `ident(args)` calls. Calls to anything else aren't implemented yet.
+    pub fn resolve(ident: &Identifier, args: &[RExp]) -> Result<Self, CompileError> {
+        let Some(intrinsic) = Self::from_name(&ident.lexeme) else {
+            return Err(CompileError::NotImplemented(ident.start));
+        };
+        if args.len() != intrinsic.arity() {
+            return Err(CompileError::ArgumentCount(
+                ident.clone(),
+                intrinsic.arity(),
+                args.len(),
+            ));
+        }
+        return Ok(intrinsic);
+    }
+
+    /// The result of calling the intrinsic with `args`, which has `arity` values.
+    pub fn eval(&self, args: &[i64]) -> i64 {
+        match self {
+            Self::SatAdd => return args[0].saturating_add(args[1]),
+            Self::SatSub => return args[0].saturating_sub(args[1]),
+        }
+    }
+}
+
 impl Display for Term {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
-            Self::LExp(LExp::Ident(ident)) => write!(f, "{}", ident.lexeme),
+            Self::LExp(lexp) => write!(f, "{}", lexp),
             Self::IntLit(intlit) => write!(f, "{}", intlit.lexeme),
             Self::Neg(term) => write!(f, "-{}", term),
+            Self::Not(term) => write!(f, "!{}", term),
             Self::Bracketed(rexp) => write!(f, "({})", rexp),
-            _ => panic!("[Display for Term] not implemented: {:?}", self),
+            Self::Group(rexps) => {
+                write!(f, "(")?;
+                for (i, rexp) in rexps.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", rexp)?;
+                }
+                // Like in Rust, a group of one is told apart from brackets by a trailing comma.
+                if rexps.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Self::Call(ident, args) => {
+                write!(f, "{}(", ident)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    I32,
+    I64,
+}
+
+impl Type {
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            Self::I32 => 4,
+            Self::I64 => 8,
+        }
+    }
+
+    pub fn signedness(&self) -> Signedness {
+        match self {
+            Self::I32 | Self::I64 => Signedness::Signed,
+        }
+    }
+}
+
+/// Whether a value is interpreted as two's complement, which decides e.g. between `imul` and
+/// `mul`. All the current types are signed, so this has a single variant until unsigned types
+/// exist; code generation matches on it irrefutably, making a new variant a compile error there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signedness {
+    Signed,
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::I32 => write!(f, "i32"),
+            Self::I64 => write!(f, "i64"),
+        }
+    }
+}
+
+impl TryFrom<&Identifier> for Type {
+    type Error = CompileError;
+
+    fn try_from(value: &Identifier) -> Result<Self, Self::Error> {
+        match value.lexeme.as_str() {
+            "i32" => Ok(Self::I32),
+            "i64" => Ok(Self::I64),
+            _ => Err(CompileError::UnknownType(value.clone())),
+        }
+    }
+}
+
+/// The binding introduced by a `let` statement.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Declaration {
+    pub ident: Identifier,
+    pub mutable: bool,
+    pub ty: Option<Type>,
+}
+
+impl Display for Declaration {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.mutable {
+            write!(f, "mut ")?;
         }
+        write!(f, "{}", self.ident)?;
+        if let Some(ty) = self.ty {
+            write!(f, ": {}", ty)?;
+        }
+        return Ok(());
     }
 }
 
 type Block = Vec<Stmt>;
 
-#[derive(Debug)]
+/// How a print statement writes its value, chosen by the keyword that starts it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrintFormat {
+    /// `print`: the value in decimal, followed by a newline.
+    Decimal,
+    /// `print_hex`: the value in lowercase hexadecimal, without a prefix and followed by a
+    /// newline. Negative values are written as their two's complement.
+    Hex,
+    /// `print_char`: the lowest byte of the value as a character.
+    Char,
+}
+
+impl PrintFormat {
+    /// The format a print keyword selects, or `None` if `name` isn't one.
+    pub fn from_keyword(name: &str) -> Option<Self> {
+        match name {
+            "print" => return Some(Self::Decimal),
+            "print_hex" => return Some(Self::Hex),
+            "print_char" => return Some(Self::Char),
+            _ => return None,
+        }
+    }
+
+    /// The keyword that selects the format.
+    pub fn keyword(self) -> &'static str {
+        match self {
+            Self::Decimal => return "print",
+            Self::Hex => return "print_hex",
+            Self::Char => return "print_char",
+        }
+    }
+}
+
+impl Display for PrintFormat {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Decimal => write!(f, "decimal"),
+            Self::Hex => write!(f, "hex"),
+            Self::Char => write!(f, "char"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum Stmt {
-    Declare(Identifier),
-    Initialize(Identifier, RExp),
+    Declare(Declaration),
+    Initialize(Declaration, RExp),
     Assign(LExp, RExp),
     RExp(RExp),
     Block(Block),
     If(RExp, Block, Option<Box<Stmt>>),
     Exit(RExp),
+    /// `print rexp`, `print_hex rexp` or `print_char rexp`. Parsed, but not compiled yet.
+    Print(RExp, PrintFormat),
+    FnDef(Identifier, Block),
+    Return(RExp),
 }
 
 impl Stmt {
+    /// The name a declaration or a function definition introduces.
+    fn declared_name(&self) -> Option<&Identifier> {
+        match self {
+            Self::Declare(decl) | Self::Initialize(decl, _) => return Some(&decl.ident),
+            Self::FnDef(ident, _) => return Some(ident),
+            _ => return None,
+        }
+    }
+
     pub fn is_if(&self) -> bool {
         match self {
             Self::If(_, _, _) => true,
             _ => false,
         }
     }
+
+    /// Whether the statement is or contains a print statement, in a nested block, function
+    /// body or block expression.
+    pub fn prints(&self) -> bool {
+        match self {
+            Self::Print(_, _) => return true,
+            Self::Declare(_) => return false,
+            Self::Block(block) | Self::FnDef(_, block) => return block.iter().any(Stmt::prints),
+            Self::If(rexp, if_block, else_stmt) => {
+                return rexp.prints()
+                    || if_block.iter().any(Stmt::prints)
+                    || else_stmt.as_ref().is_some_and(|stmt| stmt.prints())
+            }
+            Self::Initialize(_, rexp)
+            | Self::Assign(_, rexp)
+            | Self::RExp(rexp)
+            | Self::Exit(rexp)
+            | Self::Return(rexp) => return rexp.prints(),
+        }
+    }
+
+    /// Whether the statement declares the variable `lexeme`.
+    pub fn declares(&self, lexeme: &str) -> bool {
+        match self {
+            Self::Declare(decl) | Self::Initialize(decl, _) => return decl.ident.lexeme == lexeme,
+            _ => return false,
+        }
+    }
+
+    /// Whether the statement might read `lexeme`. Reads of a shadowing variable count too.
+    pub fn reads(&self, lexeme: &str) -> bool {
+        match self {
+            Self::Declare(_) | Self::FnDef(_, _) => return false,
+            Self::Block(block) => return block.iter().any(|stmt| stmt.reads(lexeme)),
+            Self::If(rexp, if_block, else_stmt) => {
+                return rexp.reads(lexeme)
+                    || if_block.iter().any(|stmt| stmt.reads(lexeme))
+                    || else_stmt.as_ref().is_some_and(|stmt| stmt.reads(lexeme))
+            }
+            Self::Initialize(_, rexp)
+            | Self::Assign(_, rexp)
+            | Self::RExp(rexp)
+            | Self::Exit(rexp)
+            | Self::Print(rexp, _)
+            | Self::Return(rexp) => return rexp.reads(lexeme),
+        }
+    }
+
+    /// Whether the statement might read or assign `lexeme`, i.e. whether the variable is used
+    /// at all.
+    pub fn mentions(&self, lexeme: &str) -> bool {
+        if self.reads(lexeme) {
+            return true;
+        }
+        match self {
+            Self::Assign(lexp, _) => return lexp.refers_to(lexeme),
+            Self::Block(block) => return block.iter().any(|stmt| stmt.mentions(lexeme)),
+            Self::If(_, if_block, else_stmt) => {
+                return if_block.iter().any(|stmt| stmt.mentions(lexeme))
+                    || else_stmt.as_ref().is_some_and(|stmt| stmt.mentions(lexeme))
+            }
+            _ => return false,
+        }
+    }
 }
 
-impl Display for Stmt {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Stmt {
+    const INDENT_WIDTH: usize = 2;
+
+    fn fmt_block(
+        block: &Block,
+        f: &mut core::fmt::Formatter<'_>,
+        indent: usize,
+    ) -> core::fmt::Result {
+        writeln!(f, "{{")?;
+        let inner_indent = indent + Self::INDENT_WIDTH;
+        for stmt in block {
+            write!(f, "{:1$}", "", inner_indent)?;
+            stmt.fmt_indented(f, inner_indent)?;
+            writeln!(f)?;
+        }
+        write!(f, "{:1$}}}", "", indent)
+    }
+
+    /// Writes the statement assuming the cursor is already at `indent`,
+    /// indenting every line after the first one by `indent` as well.
+    fn fmt_indented(&self, f: &mut core::fmt::Formatter<'_>, indent: usize) -> core::fmt::Result {
         match self {
-            Self::Declare(ident) => write!(f, "Declare({})", ident),
+            Self::Declare(decl) => write!(f, "Declare({})", decl),
             Self::Assign(lexp, rexp) => write!(f, "Assign({}, {})", lexp, rexp),
-            Self::Initialize(ident, rexp) => write!(f, "Initialize({}, {})", ident, rexp),
+            Self::Initialize(decl, rexp) => write!(f, "Initialize({}, {})", decl, rexp),
             Self::RExp(rexp) => write!(f, "RExp({})", rexp),
-            Self::Block(block) => {
-                writeln!(f, "{{")?;
-                for stmt in block {
-                    writeln!(f, "{}", stmt)?;
-                }
-                writeln!(f, "}}")?;
-                return Ok(());
-            }
+            Self::Block(block) => Self::fmt_block(block, f, indent),
             Self::If(rexp, if_block, else_block) => {
-                writeln!(f, "if {} {{", rexp)?;
-                for stmt in if_block {
-                    writeln!(f, "{}", stmt)?;
-                }
-                write!(f, "}}")?;
+                write!(f, "if {} ", rexp)?;
+                Self::fmt_block(if_block, f, indent)?;
                 let else_stmt = match else_block {
-                    None => return writeln!(f),
+                    None => return Ok(()),
                     Some(else_box) => {
                         write!(f, " else ")?;
                         else_box.as_ref()
                     }
                 };
                 match else_stmt {
-                    Stmt::Block(else_stmts) => {
-                        writeln!(f, "{{")?;
-                        for stmt in else_stmts {
-                            writeln!(f, "{}", stmt)?;
-                        }
-
-                        write!(f, "}}")?;
-                    }
-                    stmt if stmt.is_if() => write!(f, "{}", stmt)?,
-                    else_stmt => {
-                        panic!(
-                            "[Display for Stmt] else_block in if contains: {:?}",
-                            else_stmt
-                        )
-                    }
+                    Stmt::Block(else_stmts) => Self::fmt_block(else_stmts, f, indent),
+                    stmt if stmt.is_if() => stmt.fmt_indented(f, indent),
+                    // Only a block or another `if` can follow `else`.
+                    _ => write!(f, "<error>"),
                 }
-
-                return Ok(());
             }
 
             Self::Exit(rexp) => write!(f, "Exit({})", rexp),
-            _ => panic!("[Display for Stmt] unimplemented: {:?}", self),
+            Self::Print(rexp, PrintFormat::Decimal) => write!(f, "Print({})", rexp),
+            Self::Print(rexp, format) => write!(f, "Print({}, {})", rexp, format),
+            Self::FnDef(ident, block) => {
+                write!(f, "fn {}() ", ident)?;
+                Self::fmt_block(block, f, indent)
+            }
+            Self::Return(rexp) => write!(f, "Return({})", rexp),
         }
     }
 }
 
-#[derive(Debug)]
+impl Display for Stmt {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        return self.fmt_indented(f, 0);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum RExp {
     Term(Term),
     Add(Box<RExp>, Box<RExp>),
@@ -206,6 +685,15 @@ pub enum RExp {
     LessEqual(Box<RExp>, Box<RExp>),
     Greater(Box<RExp>, Box<RExp>),
     GreaterEqual(Box<RExp>, Box<RExp>),
+    /// `lhs && rhs`, `1` if both are nonzero and `0` otherwise. `rhs` is only evaluated if
+    /// `lhs` is nonzero.
+    And(Box<RExp>, Box<RExp>),
+    /// `lhs || rhs`, `1` if either is nonzero and `0` otherwise. `rhs` is only evaluated if
+    /// `lhs` is zero.
+    Or(Box<RExp>, Box<RExp>),
+    Cast(Box<RExp>, Type),
+    /// A block whose last statement is an expression, which is the value of the whole block.
+    Block(Block, Box<RExp>),
 }
 
 impl RExp {
@@ -223,6 +711,8 @@ impl RExp {
             TT::LessEqual => RExp::LessEqual(lhs, rhs),
             TT::Greater => RExp::Greater(lhs, rhs),
             TT::GreaterEqual => RExp::GreaterEqual(lhs, rhs),
+            TT::And => RExp::And(lhs, rhs),
+            TT::Or => RExp::Or(lhs, rhs),
             _ => panic!(
                 "[Parser] [RExp.from_bin_exp] Invalid operator: {:?}",
                 operator
@@ -231,8 +721,205 @@ impl RExp {
     }
 }
 
+impl RExp {
+    /// Location of the first term of the expression.
+    pub fn start(&self) -> Location {
+        match self {
+            Self::Term(term) => term.start(),
+            Self::Cast(rexp, _) => rexp.start(),
+            Self::Block(_, tail) => tail.start(),
+            Self::Add(lhs, _)
+            | Self::Sub(lhs, _)
+            | Self::Mul(lhs, _)
+            | Self::Div(lhs, _)
+            | Self::Equal(lhs, _)
+            | Self::NotEqual(lhs, _)
+            | Self::Less(lhs, _)
+            | Self::LessEqual(lhs, _)
+            | Self::Greater(lhs, _)
+            | Self::GreaterEqual(lhs, _)
+            | Self::And(lhs, _)
+            | Self::Or(lhs, _) => lhs.start(),
+        }
+    }
+
+    /// Whether the expression contains a block with a print statement.
+    pub fn prints(&self) -> bool {
+        match self {
+            Self::Term(term) => return term.prints(),
+            Self::Cast(rexp, _) => return rexp.prints(),
+            Self::Block(block, tail) => return block.iter().any(Stmt::prints) || tail.prints(),
+            Self::Add(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Equal(lhs, rhs)
+            | Self::NotEqual(lhs, rhs)
+            | Self::Less(lhs, rhs)
+            | Self::LessEqual(lhs, rhs)
+            | Self::Greater(lhs, rhs)
+            | Self::GreaterEqual(lhs, rhs)
+            | Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs) => return lhs.prints() || rhs.prints(),
+        }
+    }
+
+    /// Whether evaluating the expression might read `lexeme`.
+    pub fn reads(&self, lexeme: &str) -> bool {
+        match self {
+            Self::Term(term) => return term.reads(lexeme),
+            Self::Cast(rexp, _) => return rexp.reads(lexeme),
+            // The statements of a block expression aren't looked into, so it might read any
+            // variable.
+            Self::Block(_, _) => return true,
+            Self::Add(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Equal(lhs, rhs)
+            | Self::NotEqual(lhs, rhs)
+            | Self::Less(lhs, rhs)
+            | Self::LessEqual(lhs, rhs)
+            | Self::Greater(lhs, rhs)
+            | Self::GreaterEqual(lhs, rhs)
+            | Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs) => return lhs.reads(lexeme) || rhs.reads(lexeme),
+        }
+    }
+
+    /// How the operator of a binary expression is written, e.g. `+` for `Add`.
+    pub fn operator(&self) -> Option<&'static str> {
+        match self {
+            Self::Add(_, _) => Some("+"),
+            Self::Sub(_, _) => Some("-"),
+            Self::Mul(_, _) => Some("*"),
+            Self::Div(_, _) => Some("/"),
+            Self::Equal(_, _) => Some("=="),
+            Self::NotEqual(_, _) => Some("!="),
+            Self::Less(_, _) => Some("<"),
+            Self::LessEqual(_, _) => Some("<="),
+            Self::Greater(_, _) => Some(">"),
+            Self::GreaterEqual(_, _) => Some(">="),
+            Self::And(_, _) => Some("&&"),
+            Self::Or(_, _) => Some("||"),
+            Self::Term(_) | Self::Cast(_, _) | Self::Block(_, _) => None,
+        }
+    }
+
+    /// The source code from the first term of the expression up to its last one.
+    pub fn span(&self) -> Span {
+        let (lhs, rhs) = match self {
+            Self::Term(term) => return term.span(),
+            Self::Cast(rexp, _) => return rexp.span(),
+            Self::Block(_, tail) => return tail.span(),
+            Self::Add(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Equal(lhs, rhs)
+            | Self::NotEqual(lhs, rhs)
+            | Self::Less(lhs, rhs)
+            | Self::LessEqual(lhs, rhs)
+            | Self::Greater(lhs, rhs)
+            | Self::GreaterEqual(lhs, rhs)
+            | Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs) => (lhs.span(), rhs.span()),
+        };
+        return Span {
+            file: lhs.file,
+            start: Location::min(lhs.start, rhs.start),
+            end: Location::max(lhs.end, rhs.end),
+        };
+    }
+
+    /// Evaluates an expression made up only of literals and operators, with the same
+    /// semantics as the generated code: arithmetic wraps, division truncates towards zero and
+    /// comparisons produce `0` or `1`.
+    /// Returns `None` if the expression reads a variable, or if it divides by zero or
+    /// overflows a division, which would fault at runtime.
+    pub fn eval_const(&self) -> Option<i64> {
+        let (lhs, rhs) = match self {
+            Self::Term(term) => return term.eval_const(),
+            Self::Cast(rexp, Type::I32) => return Some(rexp.eval_const()? as i32 as i64),
+            Self::Cast(rexp, Type::I64) => return rexp.eval_const(),
+            // The statements of the block may have effects of their own.
+            Self::Block(_, _) => return None,
+            Self::Add(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Equal(lhs, rhs)
+            | Self::NotEqual(lhs, rhs)
+            | Self::Less(lhs, rhs)
+            | Self::LessEqual(lhs, rhs)
+            | Self::Greater(lhs, rhs)
+            | Self::GreaterEqual(lhs, rhs)
+            | Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs) => (lhs.eval_const()?, rhs.eval_const()?),
+        };
+        let value = match self {
+            Self::Term(_) | Self::Cast(_, _) | Self::Block(_, _) => unreachable!(),
+            Self::Add(_, _) => lhs.wrapping_add(rhs),
+            Self::Sub(_, _) => lhs.wrapping_sub(rhs),
+            Self::Mul(_, _) => lhs.wrapping_mul(rhs),
+            Self::Div(_, _) => lhs.checked_div(rhs)?,
+            Self::Equal(_, _) => (lhs == rhs) as i64,
+            Self::NotEqual(_, _) => (lhs != rhs) as i64,
+            Self::Less(_, _) => (lhs < rhs) as i64,
+            Self::LessEqual(_, _) => (lhs <= rhs) as i64,
+            Self::Greater(_, _) => (lhs > rhs) as i64,
+            Self::GreaterEqual(_, _) => (lhs >= rhs) as i64,
+            Self::And(_, _) => (lhs != 0 && rhs != 0) as i64,
+            Self::Or(_, _) => (lhs != 0 || rhs != 0) as i64,
+        };
+        return Some(value);
+    }
+
+    /// Like `eval_const`, but arithmetic that overflows an `i64` is reported as a
+    /// `ConstantOverflow` instead of wrapping.
+    /// Dividing by zero is still `None`, since that is checked for at runtime.
+    pub fn eval_const_checked(&self) -> Result<Option<i64>, CompileError> {
+        let (lhs, rhs) = match self {
+            Self::Term(term) => return term.eval_const_checked(),
+            Self::Cast(rexp, Type::I32) => {
+                return Ok(rexp.eval_const_checked()?.map(|value| value as i32 as i64))
+            }
+            Self::Cast(rexp, Type::I64) => return rexp.eval_const_checked(),
+            Self::Block(_, _) => return Ok(None),
+            Self::Add(lhs, rhs)
+            | Self::Sub(lhs, rhs)
+            | Self::Mul(lhs, rhs)
+            | Self::Div(lhs, rhs)
+            | Self::Equal(lhs, rhs)
+            | Self::NotEqual(lhs, rhs)
+            | Self::Less(lhs, rhs)
+            | Self::LessEqual(lhs, rhs)
+            | Self::Greater(lhs, rhs)
+            | Self::GreaterEqual(lhs, rhs)
+            | Self::And(lhs, rhs)
+            | Self::Or(lhs, rhs) => (lhs.eval_const_checked()?, rhs.eval_const_checked()?),
+        };
+        let (Some(lhs), Some(rhs)) = (lhs, rhs) else {
+            return Ok(None);
+        };
+        let value = match self {
+            Self::Add(_, _) => lhs.checked_add(rhs),
+            Self::Sub(_, _) => lhs.checked_sub(rhs),
+            Self::Mul(_, _) => lhs.checked_mul(rhs),
+            Self::Div(_, _) if rhs == 0 => return Ok(None),
+            Self::Div(_, _) => lhs.checked_div(rhs),
+            // Comparisons and logical operators can't overflow.
+            _ => return Ok(self.eval_const()),
+        };
+        match value {
+            Some(value) => return Ok(Some(value)),
+            None => return Err(CompileError::ConstantOverflow(self.span())),
+        }
+    }
+}
+
 impl Display for RExp {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             RExp::Add(lhs, rhs) => write!(f, "({} + {})", lhs, rhs),
             RExp::Mul(lhs, rhs) => write!(f, "({} * {})", lhs, rhs),
@@ -244,8 +931,17 @@ impl Display for RExp {
             RExp::LessEqual(lhs, rhs) => write!(f, "({} <= {})", lhs, rhs),
             RExp::Greater(lhs, rhs) => write!(f, "({} > {})", lhs, rhs),
             RExp::GreaterEqual(lhs, rhs) => write!(f, "({} >= {})", lhs, rhs),
+            RExp::And(lhs, rhs) => write!(f, "({} && {})", lhs, rhs),
+            RExp::Or(lhs, rhs) => write!(f, "({} || {})", lhs, rhs),
+            RExp::Cast(rexp, ty) => write!(f, "{} as {}", rexp, ty),
             RExp::Term(term) => write!(f, "{}", term),
-            _ => panic!("[RExp.Display] not implemented for: {:?}", self),
+            RExp::Block(block, tail) => {
+                write!(f, "{{ ")?;
+                for stmt in block.iter() {
+                    write!(f, "{}; ", stmt)?;
+                }
+                write!(f, "{} }}", tail)
+            }
         }
     }
 }
@@ -272,15 +968,50 @@ impl From<Term> for RExp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum LExp {
     Ident(Identifier),
+    /// `lexp.field`. Parsed in preparation for structs, but not compiled yet.
+    Field(Box<LExp>, Identifier),
+}
+
+impl LExp {
+    pub fn start(&self) -> Location {
+        match self {
+            Self::Ident(ident) => ident.start,
+            Self::Field(lexp, _) => lexp.start(),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Ident(ident) => Span {
+                file: ident.file.clone(),
+                start: ident.start,
+                end: ident.end,
+            },
+            Self::Field(lexp, field) => Span {
+                end: field.end,
+                ..lexp.span()
+            },
+        }
+    }
+
+    /// Whether this is the variable `lexeme` or one of its fields.
+    pub fn refers_to(&self, lexeme: &str) -> bool {
+        match self {
+            Self::Ident(ident) => return ident.lexeme == lexeme,
+            Self::Field(lexp, _) => return lexp.refers_to(lexeme),
+        }
+    }
 }
 impl TryFrom<RExp> for LExp {
     type Error = RExp;
     fn try_from(value: RExp) -> Result<Self, Self::Error> {
         return match value {
             RExp::Term(Term::LExp(lexp)) => Ok(lexp),
+            // `(x) = 5` assigns to `x`.
+            RExp::Term(Term::Bracketed(rexp)) => LExp::try_from(*rexp),
             _ => Err(value),
         };
     }
@@ -297,43 +1028,58 @@ impl TryFrom<Token> for LExp {
 }
 
 impl Display for LExp {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::Ident(ident) => write!(f, "{}", ident),
+            Self::Field(lexp, field) => write!(f, "{}.{}", lexp, field),
         }
     }
 }
 
-fn is_op(tokentype: &TT) -> bool {
-    match tokentype {
-        TT::Minus
-        | TT::Plus
-        | TT::Asterisk
-        | TT::ForwardSlash
-        | TT::Equal
-        | TT::NotEqual
-        | TT::Less
-        | TT::LessEqual
-        | TT::Greater
-        | TT::GreaterEqual => true,
-        _ => false,
-    }
-}
-
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum OpAssoc {
     Left,
     Right,
 }
 
-fn op_prec_and_assoc(tokentype: &TT) -> (usize, OpAssoc) {
-    match tokentype {
-        TT::Equal | TT::NotEqual | TT::Less | TT::LessEqual | TT::Greater | TT::GreaterEqual => {
-            (1, OpAssoc::Right)
-        }
-        TT::Minus | TT::Plus => (2, OpAssoc::Left),
-        TT::Asterisk | TT::ForwardSlash => (3, OpAssoc::Left),
-        _ => panic!("{:?} is not an operator.", tokentype),
-    }
+/// Every binary operator along with its precedence and associativity. Operators with a higher
+/// precedence bind tighter. `is_op` and `op_prec_and_assoc` are both derived from this table.
+const BINARY_OPERATORS: &[(TT, usize, OpAssoc)] = &[
+    (TT::Or, 1, OpAssoc::Left),
+    (TT::And, 2, OpAssoc::Left),
+    (TT::Equal, 3, OpAssoc::Right),
+    (TT::NotEqual, 3, OpAssoc::Right),
+    (TT::Less, 3, OpAssoc::Right),
+    (TT::LessEqual, 3, OpAssoc::Right),
+    (TT::Greater, 3, OpAssoc::Right),
+    (TT::GreaterEqual, 3, OpAssoc::Right),
+    (TT::Minus, 4, OpAssoc::Left),
+    (TT::Plus, 4, OpAssoc::Left),
+    (TT::Asterisk, 5, OpAssoc::Left),
+    (TT::ForwardSlash, 5, OpAssoc::Left),
+];
+
+fn is_op(tokentype: &TT) -> bool {
+    return op_prec_and_assoc(tokentype).is_some();
+}
+
+fn op_prec_and_assoc(tokentype: &TT) -> Option<(usize, OpAssoc)> {
+    return BINARY_OPERATORS
+        .iter()
+        .find(|(op, _, _)| op == tokentype)
+        .map(|(_, prec, assoc)| (*prec, *assoc));
+}
+
+/// The error for when a parsing function is called without its first token being next, which
+/// its caller should have checked.
+fn expected_token(function: &str, expected: &str, token: Token) -> CompileError {
+    return CompileError::Internal {
+        message: format!(
+            "{} Expected `{}` but got: {:?}",
+            function, expected, token.tokentype
+        ),
+        location: Some(token.start),
+    };
 }
 
 macro_rules! parse_terminal {
@@ -349,38 +1095,125 @@ macro_rules! parse_terminal {
     }};
 }
 
+/// Options for the parser. The defaults parse the way `Parser::new` does.
+/// Of the lexer's options only `collapse_newlines` is offered, since the parser can't read
+/// comment tokens.
+#[derive(Debug, Clone)]
+pub struct ParserConfig {
+    lexer: LexerConfig,
+    max_depth: u32,
+    recover: bool,
+}
+
+/// How deeply statements and terms may be nested by default. Each level takes several frames
+/// of the parser's recursion, and this stays within the 2 MiB stack of a spawned thread even
+/// in a debug build.
+pub const DEFAULT_MAX_DEPTH: u32 = 64;
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        let mut lexer = LexerConfig::default();
+        lexer.collapse_newlines(true);
+        return Self {
+            lexer,
+            max_depth: DEFAULT_MAX_DEPTH,
+            recover: false,
+        };
+    }
+}
+
+impl ParserConfig {
+    /// See `Lexer::collapse_newlines`.
+    pub fn collapse_newlines(&mut self, collapse_newlines: bool) -> &mut Self {
+        self.lexer.collapse_newlines(collapse_newlines);
+        return self;
+    }
+    /// How deeply statements and terms may be nested before parsing fails with
+    /// `NestingTooDeep`, rather than overflowing the stack.
+    pub fn max_depth(&mut self, max_depth: u32) -> &mut Self {
+        self.max_depth = max_depth;
+        return self;
+    }
+    /// Instead of stopping at a `let` without a name, note the error in `Parser::errors`, skip
+    /// the rest of the statement's line and go on with the next one. `parse_program` then
+    /// succeeds even if errors were noted, so they have to be checked separately.
+    pub fn recover(&mut self, recover: bool) -> &mut Self {
+        self.recover = recover;
+        return self;
+    }
+}
+
 pub struct Parser {
     lexer: Lexer,
     rexp_nesting_level: u32,
+    /// How many nested statements and terms are being parsed.
+    depth: u32,
+    max_depth: u32,
+    recover: bool,
     pub program: Program,
+    /// The errors recovered from, see `ParserConfig::recover`.
+    pub errors: Vec<CompileError>,
 }
 
 impl Parser {
     pub fn new(source: String) -> Self {
-        return Self {
-            lexer: Lexer::new(source),
-            program: Program { stmts: Vec::new() },
-            rexp_nesting_level: 0,
-        };
+        return Self::new_with_config(source, &ParserConfig::default());
+    }
+    pub fn new_with_config(source: String, config: &ParserConfig) -> Self {
+        let mut parser = Self::with_lexer(Lexer::new_with_config(source, &config.lexer));
+        parser.max_depth = config.max_depth;
+        parser.recover = config.recover;
+        return parser;
     }
-    pub fn from_file(path: Rc<str>) -> Self {
+    #[cfg(feature = "std")]
+    pub fn from_file(path: Rc<str>) -> std::io::Result<Self> {
+        let mut lexer = Lexer::from_file(path)?;
+        lexer.configure(&ParserConfig::default().lexer);
+        return Ok(Self::with_lexer(lexer));
+    }
+    /// Parses source read from `reader`, e.g. stdin. `name` takes the place of the file name.
+    #[cfg(feature = "std")]
+    pub fn from_reader<R: Read>(reader: R, name: Option<Rc<str>>) -> std::io::Result<Self> {
+        let mut lexer = Lexer::from_reader(reader, name)?;
+        lexer.configure(&ParserConfig::default().lexer);
+        return Ok(Self::with_lexer(lexer));
+    }
+
+    fn with_lexer(lexer: Lexer) -> Self {
         return Self {
-            lexer: Lexer::from_file(path),
+            lexer,
             program: Program { stmts: Vec::new() },
             rexp_nesting_level: 0,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            recover: false,
+            errors: Vec::new(),
         };
     }
 
+    /// Creates a parser over an already lexed token stream.
+    /// Since the tokens are lexed up front, newlines inside brackets must already be left out.
+    /// Comment tokens are dropped, so a stream lexed with `preserve_trivia` can be parsed as is.
+    pub fn from_tokens(mut tokens: Vec<Token>) -> Self {
+        tokens.retain(|token| !token.tokentype.is_trivia());
+        return Self::with_lexer(Lexer::from_tokens(tokens));
+    }
+
     pub fn parse_program(&mut self) -> Result<(), CompileError> {
         loop {
             self.skip_newlines()?;
-            match self.stmt() {
+            let start = self.lexer.peek();
+            let stmt = match start.tokentype {
+                TT::Fn => self.fn_def(),
+                _ => self.stmt(),
+            };
+            match stmt {
                 Ok(stmt) => self.program.stmts.push(stmt),
                 Err(CompileError::NotFound) => {
-                    println!("Notfound");
+                    debug_println!("Notfound");
                     break;
                 }
-                Err(err) => return Err(err),
+                Err(err) => self.recover_from(&start, err)?,
             }
 
             match parse_terminal!(self.lexer, TT::NewLine) {
@@ -389,7 +1222,7 @@ impl Parser {
             }
             match parse_terminal!(self.lexer, TT::EndOfFile) {
                 Ok(_) => break,
-                Err(token) => return Err(CompileError::ExpectedNewline(token.start)),
+                Err(token) => return Err(CompileError::ExpectedNewline(token)),
             }
         }
         return Ok(());
@@ -400,19 +1233,72 @@ impl Parser {
 
         let stmt = match token.tokentype {
             TT::Let => self.decl_or_init(),
-            TT::Ident(_) | TT::IntLiteral(_) | TT::SBrace | TT::Minus => self.assign_stmt_or_rexp(),
+            TT::Ident(ref name) if PrintFormat::from_keyword(name).is_some() => {
+                self.print(PrintFormat::from_keyword(name).unwrap())
+            }
+            TT::Ident(_) | TT::IntLiteral(_) | TT::SBrace | TT::Minus | TT::Plus | TT::Not => {
+                self.assign_stmt_or_rexp()
+            }
             TT::Exit => self.exit(),
             TT::SCurly => self.block(),
             TT::If => self.if_(),
+            TT::Return => self.return_(),
+            // `parse_program` handles the ones at the top level.
+            TT::Fn => Err(CompileError::MisplacedStatement(
+                Span::join(&token, &token),
+                "functions can only be defined at the top level",
+            )),
             _ => Err(CompileError::NotFound),
         };
         match stmt {
-            Ok(ref stmt) => println!("[Parser.stmt] Ok({})", stmt),
-            Err(ref err) => println!("[Parser.stmt] Err({:?})", err),
+            Ok(ref stmt) => debug_println!("[Parser.stmt] Ok({})", stmt),
+            Err(ref err) => debug_println!("[Parser.stmt] Err({:?})", err),
         }
         stmt
     }
 
+    /// Runs `parse` one level deeper, or fails if that is deeper than `max_depth`.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, CompileError>,
+    ) -> Result<T, CompileError> {
+        if self.depth >= self.max_depth {
+            return Err(CompileError::NestingTooDeep(self.lexer.peek().start));
+        }
+        self.depth += 1;
+        let res = parse(self);
+        self.depth -= 1;
+        return res;
+    }
+
+    /// Notes `err`, raised by the statement starting at `start`, and skips to the end of the
+    /// statement's line if the parser recovers from it. Otherwise returns it.
+    /// Blocks opened on the line are skipped as a whole, and a `}` that closes the enclosing
+    /// block ends the line early.
+    fn recover_from(&mut self, start: &Token, err: CompileError) -> Result<(), CompileError> {
+        let recoverable = matches!(
+            (&start.tokentype, &err),
+            (TT::Let, CompileError::ExpectedIdent(_))
+        );
+        if !self.recover || !recoverable {
+            return Err(err);
+        }
+        debug_println!("[Parser.recover_from] {:?}", err);
+        self.errors.push(err);
+        let mut depth = 0;
+        loop {
+            match self.lexer.peek().tokentype {
+                TT::EndOfFile => break,
+                TT::NewLine | TT::ECurly if depth == 0 => break,
+                TT::SCurly => depth += 1,
+                TT::ECurly => depth -= 1,
+                _ => (),
+            }
+            self.lexer.consume()?;
+        }
+        return Ok(());
+    }
+
     fn skip_newlines(&mut self) -> Result<bool, CompileError> {
         let mut newlines_skipped = false;
         while parse_terminal!(self.lexer, TT::NewLine | TT::StartOfFile).is_ok() {
@@ -426,17 +1312,30 @@ impl Parser {
             Err(_) => return Err(CompileError::NotFound),
             _ => (),
         }
+        if self.lexer.peek().tokentype == TT::Let {
+            return self.if_let();
+        }
         let rexp = self
             .rexp()
             .handle_not_found(CompileError::ExpectedExpression(self.lexer.peek().start))?;
+        return self.if_branches(rexp);
+    }
 
-        let if_block = match self
-            .block()
-            .handle_not_found(CompileError::ExpectedBlock(self.lexer.peek().start))?
-        {
-            Stmt::Block(block) => block,
-            stmt => panic!("[Parser.if_] Parser.block returned: {}", stmt),
+    /// Parses `if let x = rexp` followed by its branches, taking the value of `x` as the
+    /// condition. `x` is only in scope in the branches, so the `if` is put in a block along
+    /// with the initialization of `x`.
+    fn if_let(&mut self) -> Result<Stmt, CompileError> {
+        let init = self.decl_or_init()?;
+        let ident = match &init {
+            Stmt::Initialize(decl, _) => decl.ident.clone(),
+            _ => return Err(CompileError::UnexpectedToken(self.lexer.peek())),
         };
+        let if_ = self.if_branches(RExp::from(LExp::Ident(ident)))?;
+        return Ok(Stmt::Block(vec![init, if_]));
+    }
+
+    fn if_branches(&mut self, rexp: RExp) -> Result<Stmt, CompileError> {
+        let if_block = self.branch()?;
 
         match parse_terminal!(self.lexer, TT::Else) {
             Err(_) => {
@@ -446,7 +1345,7 @@ impl Parser {
             _ => (),
         }
 
-        match self.if_() {
+        match self.nested(Self::if_) {
             Ok(else_if_block) => {
                 return Ok(Stmt::If(rexp, if_block, Some(Box::new(else_if_block))))
             }
@@ -454,53 +1353,184 @@ impl Parser {
             Err(err) => return Err(err),
         }
 
-        match self.block() {
-            Ok(else_block) => return Ok(Stmt::If(rexp, if_block, Some(Box::new(else_block)))),
-            Err(CompileError::NotFound) => {
-                return Err(CompileError::ExpectedBlock(self.lexer.peek().start))
-            }
-            Err(err) => return Err(err),
+        let else_block = Stmt::Block(self.branch()?);
+        return Ok(Stmt::If(rexp, if_block, Some(Box::new(else_block))));
+    }
+
+    /// Parses the body of an `if` or `else`, which is either a block or a single statement.
+    /// A single statement is wrapped in a block of its own, so `if c exit 1` is the same as
+    /// `if c { exit 1 }`. An `else` after it belongs to the innermost `if`.
+    fn branch(&mut self) -> Result<Block, CompileError> {
+        let stmt = match self.lexer.peek().tokentype {
+            TT::SCurly => self.nested(Self::block),
+            _ => self.nested(Self::stmt),
+        };
+        match stmt.handle_not_found(CompileError::ExpectedBlock(self.lexer.peek().start))? {
+            Stmt::Block(block) => return Ok(block),
+            stmt => return Ok(vec![stmt]),
         }
     }
 
     fn block(&mut self) -> Result<Stmt, CompileError> {
+        let (stmts, _) = self.block_body()?;
+        self.lexer.consume()?;
+        return Ok(Stmt::Block(stmts));
+    }
+
+    /// Parses `{ Stmt*` and checks that `}` is next, without consuming it.
+    /// Also reports whether the last statement was followed by a newline.
+    fn block_body(&mut self) -> Result<(Block, bool), CompileError> {
         match parse_terminal!(self.lexer, TT::SCurly) {
             Ok(_) => (),
             Err(_) => return Err(CompileError::NotFound),
         }
         let mut stmts = Vec::<Stmt>::new();
+        let mut terminated = true;
 
         loop {
             while parse_terminal!(self.lexer, TT::NewLine).is_ok() {}
-            match self.stmt() {
+            let start = self.lexer.peek();
+            match self.nested(Self::stmt) {
                 Ok(stmt) => stmts.push(stmt),
                 Err(CompileError::NotFound) => break,
-                err => return err,
+                Err(err) => self.recover_from(&start, err)?,
             }
             match parse_terminal!(self.lexer, TT::NewLine) {
-                Err(_) => break,
+                Err(_) => {
+                    terminated = false;
+                    break;
+                }
                 _ => (),
             }
         }
 
-        match parse_terminal!(self.lexer, TT::ECurly) {
-            Ok(_) => (),
-            Err(token) => return Err(CompileError::ExpectedECurly(token.start)),
+        let token = self.lexer.peek();
+        match token.tokentype {
+            TT::ECurly => return Ok((stmts, terminated)),
+            _ => return Err(CompileError::ExpectedECurly(token.start)),
         }
-        return Ok(Stmt::Block(stmts));
+    }
+
+    /// Parses a block used as an expression. Its value is the expression it ends with, which
+    /// must be on the same line as the closing `}`.
+    fn block_rexp(&mut self) -> Result<RExp, CompileError> {
+        // Statements are separated by newlines even when the block is inside brackets.
+        let nesting_level = core::mem::replace(&mut self.rexp_nesting_level, 0);
+        self.lexer.emit_newline = true;
+        let body = self.block_body();
+        self.rexp_nesting_level = nesting_level;
+        self.lexer.emit_newline = nesting_level == 0;
+        let (mut stmts, terminated) = body?;
+
+        // Consumed only after restoring the brackets, like `)`.
+        let ecurly = self.lexer.peek();
+        self.lexer.consume()?;
+        match stmts.pop() {
+            Some(Stmt::RExp(tail)) if !terminated => return Ok(RExp::Block(stmts, Box::new(tail))),
+            _ => return Err(CompileError::ExpectedExpression(ecurly.start)),
+        }
+    }
+
+    /// `print`, `print_hex` and `print_char` are soft keywords: they're lexed as identifiers and
+    /// only start a print statement if an operand follows them directly. Otherwise they're names
+    /// like any other, e.g. in `print = 1` or `exit print + 1`, so programs that use them as
+    /// such keep working.
+    fn print(&mut self, format: PrintFormat) -> Result<Stmt, CompileError> {
+        self.lexer.consume()?;
+        match self.lexer.peek().tokentype {
+            TT::Ident(_) | TT::IntLiteral(_) | TT::SBrace => (),
+            _ => {
+                self.lexer.rewind();
+                return self.assign_stmt_or_rexp();
+            }
+        }
+        let rexp = self
+            .rexp()
+            .handle_not_found(CompileError::ExpectedExpression(self.lexer.peek().start))?;
+        return Ok(Stmt::Print(rexp, format));
     }
 
     fn exit(&mut self) -> Result<Stmt, CompileError> {
-        let exit_kw_loc = match parse_terminal!(self.lexer, TT::Exit) {
-            Ok(token) => token.end,
+        let exit_kw = match parse_terminal!(self.lexer, TT::Exit) {
+            Ok(token) => token,
             Err(_) => return Err(CompileError::NotFound),
         };
+        // A bare `exit` at the end of a statement is shorthand for `exit 0`.
+        match self.lexer.peek().tokentype {
+            TT::NewLine | TT::EndOfFile | TT::ECurly => {
+                let zero = IntLiteral {
+                    file: exit_kw.file,
+                    start: exit_kw.end,
+                    end: exit_kw.end,
+                    lexeme: String::from("0"),
+                };
+                return Ok(Stmt::Exit(RExp::Term(Term::IntLit(zero))));
+            }
+            _ => (),
+        }
         let rexp = self
             .rexp()
-            .handle_not_found(CompileError::ExpectedExpression(exit_kw_loc))?;
+            .handle_not_found(CompileError::ExpectedExpression(exit_kw.end))?;
         return Ok(Stmt::Exit(rexp));
     }
 
+    fn fn_def(&mut self) -> Result<Stmt, CompileError> {
+        match parse_terminal!(self.lexer, TT::Fn) {
+            Err(_) => return Err(CompileError::NotFound),
+            _ => (),
+        }
+        let ident = match parse_terminal!(self.lexer, TT::Ident(_)) {
+            Ok(token) => Identifier::from(token),
+            Err(token) => return Err(CompileError::ExpectedIdent(token.start)),
+        };
+        match parse_terminal!(self.lexer, TT::SBrace) {
+            Err(token) => return Err(CompileError::UnexpectedToken(token)),
+            _ => (),
+        }
+        match parse_terminal!(self.lexer, TT::EBrace) {
+            Err(token) => return Err(CompileError::ExpectedEBrace(token.start)),
+            _ => (),
+        }
+
+        let body = match self
+            .block()
+            .handle_not_found(CompileError::ExpectedBlock(self.lexer.peek().start))?
+        {
+            Stmt::Block(block) => block,
+            stmt => {
+                return Err(CompileError::Internal {
+                    message: format!("[Parser.fn_def] Parser.block returned: {}", stmt),
+                    location: Some(ident.start),
+                })
+            }
+        };
+        return Ok(Stmt::FnDef(ident, body));
+    }
+
+    fn return_(&mut self) -> Result<Stmt, CompileError> {
+        let return_kw = match parse_terminal!(self.lexer, TT::Return) {
+            Ok(token) => token,
+            Err(_) => return Err(CompileError::NotFound),
+        };
+        // Like `exit`, a bare `return` is shorthand for `return 0`.
+        match self.lexer.peek().tokentype {
+            TT::NewLine | TT::EndOfFile | TT::ECurly => {
+                let zero = IntLiteral {
+                    file: return_kw.file,
+                    start: return_kw.end,
+                    end: return_kw.end,
+                    lexeme: String::from("0"),
+                };
+                return Ok(Stmt::Return(RExp::Term(Term::IntLit(zero))));
+            }
+            _ => (),
+        }
+        let rexp = self
+            .rexp()
+            .handle_not_found(CompileError::ExpectedExpression(return_kw.end))?;
+        return Ok(Stmt::Return(rexp));
+    }
+
     fn assign_stmt_or_rexp(&mut self) -> Result<Stmt, CompileError> {
         let exp = self
             .rexp()
@@ -521,14 +1551,18 @@ impl Parser {
     }
 
     fn rexp_min_prec(&mut self, min_prec: usize) -> Result<RExp, CompileError> {
-        let mut rexp = self.term()?.into();
+        let mut rexp = match self.lexer.peek().tokentype {
+            TT::SCurly => self.block_rexp()?,
+            _ => self.nested(Self::term)?.into(),
+        };
+        rexp = self.cast(rexp)?;
         loop {
             let op = self.lexer.peek();
             if !is_op(&op.tokentype) {
                 break;
             }
             let op_location = op.end;
-            let (prec, assoc) = op_prec_and_assoc(&op.tokentype);
+            let (prec, assoc) = op_prec_and_assoc(&op.tokentype).unwrap();
             if prec < min_prec {
                 break;
             }
@@ -545,63 +1579,1185 @@ impl Parser {
         return Ok(rexp);
     }
 
+    /// Parses any number of `as Type` suffixes, which bind tighter than binary operators.
+    fn cast(&mut self, mut rexp: RExp) -> Result<RExp, CompileError> {
+        while let Ok(as_kw) = parse_terminal!(self.lexer, TT::As) {
+            let ty = self.ty(as_kw.end)?;
+            rexp = RExp::Cast(Box::new(rexp), ty);
+        }
+        return Ok(rexp);
+    }
+
+    /// Parses a type name, `after` being where the type was expected to start.
+    fn ty(&mut self, after: Location) -> Result<Type, CompileError> {
+        match parse_terminal!(self.lexer, TT::Ident(_)) {
+            Ok(token) => return Type::try_from(&Identifier::from(token)),
+            Err(_) => return Err(CompileError::ExpectedType(after)),
+        }
+    }
+
     fn rexp(&mut self) -> Result<RExp, CompileError> {
         return self.rexp_min_prec(0);
     }
 
     fn term(&mut self) -> Result<Term, CompileError> {
         match parse_terminal!(self.lexer, TT::Ident(_) | TT::IntLiteral(_)) {
-            Ok(token) => return Ok(token.try_into().unwrap()),
+            Ok(token) => match token.try_into().unwrap() {
+                Term::LExp(LExp::Ident(ident)) if self.lexer.peek().tokentype == TT::SBrace => {
+                    return self.call(ident)
+                }
+                Term::LExp(lexp) => return Ok(Term::LExp(self.fields(lexp)?)),
+                term => return Ok(term),
+            },
             _ => (),
         }
         match parse_terminal!(self.lexer, TT::Minus) {
-            Ok(_) => return Ok(Term::Neg(Box::new(self.term()?))),
+            Ok(_) => return Ok(Term::Neg(Box::new(self.nested(Self::term)?))),
             _ => (),
         }
-        let token = self.lexer.peek();
-        match token.tokentype {
-            TT::SBrace => {
-                self.rexp_nesting_level += 1;
-                self.lexer.emit_newline = false;
+        match parse_terminal!(self.lexer, TT::Not) {
+            Ok(_) => return Ok(Term::Not(Box::new(self.nested(Self::term)?))),
+            _ => (),
+        }
+        // Unary plus doesn't do anything, so it isn't kept in the tree.
+        match parse_terminal!(self.lexer, TT::Plus) {
+            Ok(_) => return self.nested(Self::term),
+            _ => (),
+        }
+        match self.lexer.peek().tokentype {
+            TT::SBrace => (),
+            _ => return Err(CompileError::NotFound),
+        }
+
+        // Newlines are insignificant inside brackets. The lexer state is restored whether or not
+        // the bracketed expression parses, so an error can't leave newlines switched off.
+        self.enter_brackets();
+        let term = self.bracketed();
+        self.exit_brackets();
+        let term = term?;
+        // Consumed only after leaving the brackets so the token after `)` is lexed with newlines.
+        self.lexer.consume()?;
+        return Ok(term);
+    }
+
+    /// Parses the arguments of a call to `ident`, starting at `(`.
+    fn call(&mut self, ident: Identifier) -> Result<Term, CompileError> {
+        self.enter_brackets();
+        let args = self.args();
+        self.exit_brackets();
+        let args = args?;
+        // Consumed only after leaving the brackets, like the `)` of a bracketed expression.
+        self.lexer.consume()?;
+        return Ok(Term::Call(ident, args));
+    }
+
+    /// Parses `( RExp, ... ` and checks that `)` is next, without consuming it.
+    fn args(&mut self) -> Result<Vec<RExp>, CompileError> {
+        if let Err(token) = parse_terminal!(self.lexer, TT::SBrace) {
+            return Err(expected_token("[Parser.args]", "(", token));
+        }
+        return self.parse_separated(TT::Comma, TT::EBrace, |parser| parser.rexp());
+    }
+
+    /// Parses items separated by `sep` up to `end`, and checks that `end` is next without
+    /// consuming it. The list may be empty and may have a trailing separator.
+    fn parse_separated<T>(
+        &mut self,
+        sep: TT,
+        end: TT,
+        mut parse_item: impl FnMut(&mut Self) -> Result<T, CompileError>,
+    ) -> Result<Vec<T>, CompileError> {
+        let mut items = Vec::new();
+        loop {
+            let token = self.lexer.peek();
+            if token.tokentype == end {
+                return Ok(items);
+            }
+            match parse_item(self) {
+                Ok(item) => items.push(item),
+                Err(CompileError::NotFound) => return Err(CompileError::UnexpectedToken(token)),
+                Err(err) => return Err(err),
+            }
+            let token = self.lexer.peek();
+            if token.tokentype == sep {
                 self.lexer.consume()?;
+                continue;
             }
-            _ => return Err(CompileError::NotFound),
+            match end {
+                _ if token.tokentype == end => return Ok(items),
+                TT::EBrace => return Err(CompileError::ExpectedEBrace(token.start)),
+                TT::ECurly => return Err(CompileError::ExpectedECurly(token.start)),
+                _ => return Err(CompileError::UnexpectedToken(token)),
+            }
+        }
+    }
+
+    /// Parses any number of `.field` suffixes.
+    fn fields(&mut self, mut lexp: LExp) -> Result<LExp, CompileError> {
+        while let Ok(dot) = parse_terminal!(self.lexer, TT::Dot) {
+            let field = match parse_terminal!(self.lexer, TT::Ident(_)) {
+                Ok(token) => Identifier::from(token),
+                Err(_) => return Err(CompileError::ExpectedIdent(dot.end)),
+            };
+            lexp = LExp::Field(Box::new(lexp), field);
+        }
+        return Ok(lexp);
+    }
+
+    fn enter_brackets(&mut self) {
+        self.rexp_nesting_level += 1;
+        self.lexer.emit_newline = false;
+    }
+
+    fn exit_brackets(&mut self) {
+        self.rexp_nesting_level -= 1;
+        if self.rexp_nesting_level == 0 {
+            self.lexer.emit_newline = true;
+        }
+    }
+
+    /// Parses `( RExp` or `( RExp, ...` and checks that `)` is next, without consuming it.
+    fn bracketed(&mut self) -> Result<Term, CompileError> {
+        let sbrace = match parse_terminal!(self.lexer, TT::SBrace) {
+            Err(token) => return Err(expected_token("[Parser.bracketed]", "(", token)),
+            Ok(token) => token,
+        };
+        let token = self.lexer.peek();
+        if token.tokentype == TT::EBrace {
+            return Err(CompileError::EmptyBrackets(Span::join(&sbrace, &token)));
         }
         let rexp = self
             .rexp()
-            .handle_not_found(CompileError::ExpectedExpression(self.lexer.peek().start))?;
+            .handle_not_found(CompileError::ExpectedExpression(token.start))?;
         let token = self.lexer.peek();
         match token.tokentype {
-            TT::EBrace => {
-                self.rexp_nesting_level -= 1;
-                if self.rexp_nesting_level <= 0 {
-                    self.lexer.emit_newline = true;
-                }
-                self.lexer.consume()?;
-            }
+            TT::EBrace => return Ok(Term::Bracketed(Box::new(rexp))),
+            TT::Comma => self.lexer.consume()?,
             _ => return Err(CompileError::UnexpectedToken(token)),
         }
-        return Ok(Term::Bracketed(Box::new(rexp)));
+        let mut rexps = vec![rexp];
+        rexps.extend(self.parse_separated(TT::Comma, TT::EBrace, |parser| parser.rexp())?);
+        return Ok(Term::Group(rexps));
     }
 
     fn decl_or_init(&mut self) -> Result<Stmt, CompileError> {
         match parse_terminal!(self.lexer, TT::Let) {
-            Err(token) => panic!("[Parser.decl_or_init] Expected `let` but got: {:?}", token),
+            Err(token) => return Err(expected_token("[Parser.decl_or_init]", "let", token)),
             Ok(_) => (),
         }
+        let mutable = parse_terminal!(self.lexer, TT::Mut).is_ok();
         let ident = match parse_terminal!(self.lexer, TT::Ident(_)) {
             Ok(token) => Identifier::from(token),
             Err(token) => return Err(CompileError::ExpectedIdent(token.start)),
         };
+        let ty = match parse_terminal!(self.lexer, TT::Colon) {
+            Err(_) => None,
+            Ok(colon) => Some(self.ty(colon.end)?),
+        };
+        let decl = Declaration { ident, mutable, ty };
 
         match parse_terminal!(self.lexer, TT::Assign) {
-            Err(_) => return Ok(Stmt::Declare(ident)),
+            Err(_) => return Ok(Stmt::Declare(decl)),
             Ok(_) => (),
         }
 
         let rexp = self
             .rexp()
             .handle_not_found(CompileError::ExpectedExpression(self.lexer.peek().start))?;
-        return Ok(Stmt::Initialize(ident.into(), rexp));
+        return Ok(Stmt::Initialize(decl, rexp));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Program, CompileError> {
+        let mut parser = Parser::new(String::from(source));
+        parser.parse_program()?;
+        return Ok(parser.program);
+    }
+
+    fn eval(rexp: &str) -> Option<i64> {
+        let program = parse(&format!("let a = 1\nexit {}\n", rexp)).unwrap();
+        let Stmt::Exit(rexp) = &program.stmts[1] else {
+            panic!("Expected an exit statement");
+        };
+        return rexp.eval_const();
+    }
+
+    #[test]
+    fn eval_const_operators() {
+        let cases = [
+            ("7", 7),
+            ("-7", -7),
+            ("2 + 3", 5),
+            ("2 - 3", -1),
+            ("-4 * 3", -12),
+            ("7 / 2", 3),
+            ("-7 / 2", -3),
+            ("7 / -2", -3),
+            ("3 == 3", 1),
+            ("3 != 3", 0),
+            ("-1 < 0", 1),
+            ("2 <= 1", 0),
+            ("-1 > -2", 1),
+            ("-2 >= -1", 0),
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("-(2 - 5) * (8 / (3 + 1))", 6),
+            ("1 + 1 == 2", 1),
+            ("9223372036854775807 + 1", i64::MIN),
+            ("9223372036854775807 * 2", -2),
+            ("-(-9223372036854775807 - 1)", i64::MIN),
+            ("sat_add(9223372036854775807, 1)", i64::MAX),
+            ("sat_sub(-9223372036854775807, 2)", i64::MIN),
+            ("not 0", 1),
+            ("!5", 0),
+            ("1 and 0", 0),
+            ("2 && 3", 1),
+            ("0 or 0", 0),
+            ("0 || -1", 1),
+            ("1 || 0 && 0", 1),
+        ];
+        for (rexp, expected) in cases {
+            assert_eq!(eval(rexp), Some(expected), "{}", rexp);
+        }
+    }
+
+    #[test]
+    fn int_literal_values() {
+        let cases = [
+            ("0", Some(0)),
+            ("0x0", Some(0)),
+            ("0x007", Some(7)),
+            ("0x10", Some(16)),
+            ("0xfF", Some(255)),
+            ("0b10", Some(2)),
+            ("0o17", Some(15)),
+            ("-0x10", Some(-16)),
+            ("0x7fffffffffffffff", Some(i64::MAX)),
+            ("0x8000000000000000", None),
+        ];
+        for (rexp, expected) in cases {
+            assert_eq!(eval(rexp), expected, "{}", rexp);
+        }
+    }
+
+    #[test]
+    fn int_literal_wrapping_values() {
+        let cases = [
+            ("0", 0),
+            ("0x10", 16),
+            ("0o17", 15),
+            ("9223372036854775807", i64::MAX),
+            ("9223372036854775808", i64::MIN),
+            ("0xffffffffffffffff", -1),
+            ("0x10000000000000001", 1),
+        ];
+        for (lexeme, expected) in cases {
+            let program = parse(&format!("exit {}\n", lexeme)).unwrap();
+            let Stmt::Exit(RExp::Term(term)) = &program.stmts[0] else {
+                panic!("Expected an exit statement");
+            };
+            let Term::IntLit(intlit) = term else {
+                panic!("Expected an integer literal");
+            };
+            assert_eq!(intlit.wrapping_value(), expected, "{}", lexeme);
+        }
+    }
+
+    #[test]
+    fn eval_const_non_constant() {
+        assert_eq!(eval("a"), None);
+        assert_eq!(eval("1 + a * 2"), None);
+        assert_eq!(eval("1 / 0"), None);
+        assert_eq!(eval("1 / (2 - 2)"), None);
+        assert_eq!(eval("(-9223372036854775807 - 1) / -1"), None);
+    }
+
+    #[test]
+    fn bare_exit_defaults_to_zero() {
+        let program = parse("exit\n").unwrap();
+        assert_eq!(program.stmts.len(), 1);
+        assert_eq!(format!("{}", program.stmts[0]), "Exit(0)");
+
+        let program = parse("{ exit }").unwrap();
+        assert_eq!(format!("{}", program.stmts[0]), "{\n  Exit(0)\n}");
+
+        let program = parse("exit").unwrap();
+        assert_eq!(format!("{}", program.stmts[0]), "Exit(0)");
+    }
+
+    #[test]
+    fn exit_with_expression() {
+        let program = parse("exit 5\n").unwrap();
+        assert_eq!(format!("{}", program.stmts[0]), "Exit(5)");
+    }
+
+    #[test]
+    fn end_of_file_terminates_the_last_statement() {
+        for source in [
+            "exit 0",
+            "exit 0   ",
+            "let a = 1\nexit a",
+            "let a = 1\nexit a \t ",
+            "let a = 1\n\nexit a  // done",
+        ] {
+            let program = parse(source).unwrap();
+            let last = program.stmts.last().map(|stmt| stmt.to_string());
+            assert!(
+                matches!(last.as_deref(), Some("Exit(0)" | "Exit(a)")),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn block_expressions() {
+        let program = parse("let x = { let t = 1\n t + 1 }\nexit ({ x } * 2)\n").unwrap();
+        let stmts: Vec<String> = program.stmts.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            stmts,
+            [
+                "Initialize(x, { Initialize(t, 1); (t + 1) })",
+                "Exit((({ x } * 2)))"
+            ]
+        );
+
+        // Statements keep being separated by newlines inside of brackets.
+        let program = parse("exit ({\n    let t = 2\n    t } + 1)\n").unwrap();
+        assert_eq!(
+            program.stmts[0].to_string(),
+            "Exit((({ Initialize(t, 2); t } + 1)))"
+        );
+
+        // A block that ends with a newline or a statement has no value.
+        for source in [
+            "let x = {\n    1\n}\n",
+            "let x = { exit 1 }\n",
+            "let x = {}\n",
+        ] {
+            let res = parse(source);
+            assert!(
+                matches!(res, Err(CompileError::ExpectedExpression(_))),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn unary_and_binary_minus() {
+        let cases = [
+            ("-5", "-5", Some(-5)),
+            ("a - 5", "(a - 5)", None),
+            ("a - -5", "(a - -5)", None),
+            ("-a - 5", "(-a - 5)", None),
+            ("5 - - - 5", "(5 - --5)", Some(0)),
+            ("(-5)", "(-5)", Some(-5)),
+            ("- -(5 - 3)", "--((5 - 3))", Some(2)),
+        ];
+        for (rexp, expected, value) in cases {
+            let program = parse(&format!("let a = 1\nexit {}\n", rexp)).unwrap();
+            assert_eq!(
+                program.stmts[1].to_string(),
+                format!("Exit({})", expected),
+                "{}",
+                rexp
+            );
+            assert_eq!(eval(rexp), value, "{}", rexp);
+        }
+
+        let Stmt::Exit(rexp) = &parse("exit 5 - - - 5\n").unwrap().stmts[0] else {
+            panic!("Expected an exit statement");
+        };
+        let RExp::Sub(_, rhs) = rexp else {
+            panic!("Expected a subtraction");
+        };
+        let RExp::Term(Term::Neg(inner)) = rhs.as_ref() else {
+            panic!("Expected a negation");
+        };
+        assert!(matches!(inner.as_ref(), Term::Neg(_)));
+    }
+
+    #[test]
+    fn negation_display() {
+        let cases = [
+            ("-2 * 3", "(-2 * 3)"),
+            ("2 * -3", "(2 * -3)"),
+            ("-(2 * 3)", "-((2 * 3))"),
+            ("-(2 + 3) * 4", "(-((2 + 3)) * 4)"),
+            ("-2 + -3 * 4", "(-2 + (-3 * 4))"),
+            ("-7 / 2 - 1", "((-7 / 2) - 1)"),
+            ("2 - -3", "(2 - -3)"),
+        ];
+        for (source, expected) in cases {
+            let program = parse(&format!("exit {}\n", source)).unwrap();
+            let Stmt::Exit(rexp) = &program.stmts[0] else {
+                panic!("Expected an exit statement");
+            };
+            let displayed = rexp.to_string();
+            assert_eq!(displayed, expected, "{}", source);
+            // Reading the display back has to give the same grouping.
+            assert_eq!(eval(&displayed), eval(source), "{}", source);
+        }
+    }
+
+    #[test]
+    fn unary_plus() {
+        let program = parse("let a = 1\nexit +5\nexit a + +5\nexit -+-a\n+a\n").unwrap();
+        let stmts: Vec<String> = program.stmts.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            stmts,
+            [
+                "Initialize(a, 1)",
+                "Exit(5)",
+                "Exit((a + 5))",
+                "Exit(--a)",
+                "RExp(a)"
+            ]
+        );
+        assert!(matches!(
+            &program.stmts[1],
+            Stmt::Exit(RExp::Term(Term::IntLit(_)))
+        ));
+        let Stmt::Exit(RExp::Add(lhs, rhs)) = &program.stmts[2] else {
+            panic!("Expected an addition");
+        };
+        assert!(matches!(lhs.as_ref(), RExp::Term(Term::LExp(_))));
+        assert!(matches!(rhs.as_ref(), RExp::Term(Term::IntLit(_))));
+
+        for source in ["exit +\n", "exit 1 + +\n", "let a = +\n"] {
+            let res = parse(source);
+            assert!(
+                matches!(res, Err(CompileError::ExpectedExpression(_))),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn exit_followed_by_operator_is_an_error() {
+        let res = parse("exit +\n");
+        assert!(matches!(res, Err(CompileError::ExpectedExpression(_))));
+    }
+
+    #[test]
+    fn statements_on_one_line_need_a_newline() {
+        let res = parse("exit 1 exit 2\n");
+        assert!(matches!(res, Err(CompileError::ExpectedNewline(token))
+            if token.tokentype == TT::Exit && (token.start.row, token.start.col) == (1, 8)));
+        let res = parse("let a = 1\nlet b = a let c = b\n");
+        assert!(matches!(res, Err(CompileError::ExpectedNewline(token))
+            if token.tokentype == TT::Let && (token.start.row, token.start.col) == (2, 11)));
+    }
+
+    #[test]
+    fn display_indents_nested_blocks() {
+        let source = "{\n    let a = 1\n    {\n        if a {\n            exit a\n        } else {\n            exit 2\n        }\n    }\n}\nexit 0\n";
+        let program = parse(source).unwrap();
+        let expected = [
+            "Program {",
+            "  {",
+            "    Initialize(a, 1)",
+            "    {",
+            "      if a {",
+            "        Exit(a)",
+            "      } else {",
+            "        Exit(2)",
+            "      }",
+            "    }",
+            "  }",
+            "  Exit(0)",
+            "}",
+        ];
+        assert_eq!(program.to_string(), expected.join("\n"));
+    }
+
+    #[test]
+    fn single_statement_branches() {
+        let cases = [
+            ("if c exit 1\n", "if c {\n  Exit(1)\n}"),
+            (
+                "if c exit 1 else exit 2\n",
+                "if c {\n  Exit(1)\n} else {\n  Exit(2)\n}",
+            ),
+            (
+                "if c exit 1 else if d exit 2 else { exit 3 }\n",
+                "if c {\n  Exit(1)\n} else if d {\n  Exit(2)\n} else {\n  Exit(3)\n}",
+            ),
+            // The `else` belongs to the innermost `if`.
+            (
+                "if c if d exit 1 else exit 2\n",
+                "if c {\n  if d {\n    Exit(1)\n  } else {\n    Exit(2)\n  }\n}",
+            ),
+        ];
+        for (source, expected) in cases {
+            let program = parse(source).unwrap();
+            assert_eq!(program.stmts.len(), 1, "{}", source);
+            assert_eq!(program.stmts[0].to_string(), expected, "{}", source);
+        }
+
+        let res = parse("if c\n");
+        assert!(matches!(res, Err(CompileError::ExpectedBlock(_))));
+        let res = parse("if c exit 1 else\n");
+        assert!(matches!(res, Err(CompileError::ExpectedBlock(_))));
+    }
+
+    #[test]
+    fn print_is_a_soft_keyword() {
+        let source =
+            "let mut print = 1\nprint print\nprint = 2\nprint + 1\nprint (print * 3)\nexit print\n";
+        let program = parse(source).unwrap();
+        let stmts: Vec<String> = program.stmts.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            stmts,
+            [
+                "Initialize(mut print, 1)",
+                "Print(print)",
+                "Assign(print, 2)",
+                "RExp((print + 1))",
+                "Print(((print * 3)))",
+                "Exit(print)",
+            ]
+        );
+    }
+
+    #[test]
+    fn print_formats() {
+        let source = "print_hex 255\nprint_char 65\nlet print_hex = 1\nprint_char print_hex\nexit print_hex\n";
+        let program = parse(source).unwrap();
+        let stmts: Vec<String> = program.stmts.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            stmts,
+            [
+                "Print(255, hex)",
+                "Print(65, char)",
+                "Initialize(print_hex, 1)",
+                "Print(print_hex, char)",
+                "Exit(print_hex)",
+            ]
+        );
+        assert_eq!(
+            PrintFormat::from_keyword("print"),
+            Some(PrintFormat::Decimal)
+        );
+        assert_eq!(PrintFormat::from_keyword("println"), None);
+        for format in [PrintFormat::Decimal, PrintFormat::Hex, PrintFormat::Char] {
+            assert_eq!(PrintFormat::from_keyword(format.keyword()), Some(format));
+        }
+
+        let prints = |source: &str| parse(source).unwrap().stmts.iter().any(Stmt::prints);
+        assert!(prints("print 1\n"));
+        assert!(prints("if 1 {\n    print_hex 1\n}\n"));
+        assert!(prints("fn f() {\n    print_char 65\n}\n"));
+        assert!(prints("exit 1 + { print 2\n3 }\n"));
+        assert!(!prints("let print = 1\nexit print\n"));
+    }
+
+    #[test]
+    fn else_needs_a_branch() {
+        // `else` is followed by a block, another `if` or a single statement.
+        let program = parse("if 1 {\n} else exit 1\nif 1 exit 2 else a + 1\n").unwrap();
+        let stmts: Vec<String> = program.stmts.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            stmts,
+            [
+                "if 1 {\n} else {\n  Exit(1)\n}",
+                "if 1 {\n  Exit(2)\n} else {\n  RExp((a + 1))\n}"
+            ]
+        );
+
+        // Anything that can't start a statement is reported where the branch should be.
+        let location = |row, col, offset| Location { row, col, offset };
+        let cases = [
+            ("if 1 {\n} else }\n", location(2, 8, 14)),
+            ("if 1 {\n} else )\n", location(2, 8, 14)),
+            ("if 1 {\n} else = 2\n", location(2, 8, 14)),
+            ("if 1 {\n} else else exit 1\n", location(2, 8, 14)),
+            ("{\n    if 1 exit 0 else }\n", location(2, 22, 23)),
+            // A newline is located at the end of the line it ends.
+            ("if 1 exit 0 else\nexit 1\n", location(1, 16, 16)),
+        ];
+        for (source, location) in cases {
+            assert_eq!(
+                parse(source).unwrap_err(),
+                CompileError::ExpectedBlock(location),
+                "{:?}",
+                source
+            );
+        }
+
+        // An unfinished expression is a statement that's missing its operand, not a missing
+        // branch.
+        assert_eq!(
+            parse("if 1 exit 0 else +\n").unwrap_err(),
+            CompileError::ExpectedExpression(location(1, 18, 18))
+        );
+    }
+
+    #[test]
+    fn if_let() {
+        let program = parse("if let x = a - 1 {\n    exit x\n} else exit 0\n").unwrap();
+        assert_eq!(program.stmts.len(), 1);
+        let Stmt::Block(block) = &program.stmts[0] else {
+            panic!("Expected the `if` to be in a block of its own");
+        };
+        assert!(matches!(&block[0], Stmt::Initialize(decl, _) if decl.ident.lexeme == "x"));
+        assert!(matches!(
+            &block[1],
+            Stmt::If(RExp::Term(Term::LExp(LExp::Ident(ident))), _, Some(_)) if ident.lexeme == "x"
+        ));
+        assert_eq!(
+            program.stmts[0].to_string(),
+            "{\n  Initialize(x, (a - 1))\n  if x {\n    Exit(x)\n  } else {\n    Exit(0)\n  }\n}"
+        );
+
+        let program = parse("if c exit 1 else if let mut y: i32 = 2 exit y\n").unwrap();
+        let Stmt::If(_, _, Some(else_stmt)) = &program.stmts[0] else {
+            panic!("Expected an if statement");
+        };
+        assert!(matches!(else_stmt.as_ref(), Stmt::Block(block) if block.len() == 2));
+
+        let res = parse("if let x {\n    exit x\n}\n");
+        assert!(matches!(res, Err(CompileError::UnexpectedToken(_))));
+        let res = parse("if let = 1 exit 1\n");
+        assert!(matches!(res, Err(CompileError::ExpectedIdent(_))));
+    }
+
+    #[test]
+    fn nested_brackets() {
+        let cases = [
+            ("exit ((1 + 2))\nexit 0\n", "Exit((((1 + 2))))"),
+            ("exit ((((5))))\nexit 0\n", "Exit(((((5)))))"),
+            (
+                "exit (1 +\n    (2\n    * 3)\n)\nexit 0\n",
+                "Exit(((1 + ((2 * 3)))))",
+            ),
+        ];
+        for (source, expected) in cases {
+            let mut parser = Parser::new(String::from(source));
+            parser.parse_program().unwrap();
+            assert!(parser.lexer.emit_newline, "{:?}", source);
+            assert_eq!(parser.rexp_nesting_level, 0, "{:?}", source);
+
+            // The newline after the closing bracket must still terminate the first statement.
+            let stmts = &parser.program.stmts;
+            assert_eq!(stmts.len(), 2, "{:?}", source);
+            assert_eq!(stmts[0].to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn groups() {
+        let program =
+            parse("exit (a)\nexit (a, b)\nexit (a,)\nexit (\n    a,\n    b + 1,\n)\n").unwrap();
+        let expected = [
+            "Exit((a))",
+            "Exit((a, b))",
+            "Exit((a,))",
+            "Exit((a, (b + 1)))",
+        ];
+        for (stmt, expected) in program.stmts.iter().zip(expected) {
+            assert_eq!(stmt.to_string(), expected);
+        }
+        assert!(matches!(
+            &program.stmts[0],
+            Stmt::Exit(RExp::Term(Term::Bracketed(_)))
+        ));
+        assert!(matches!(
+            &program.stmts[1],
+            Stmt::Exit(RExp::Term(Term::Group(rexps))) if rexps.len() == 2
+        ));
+
+        let res = parse("let a = 1\nexit ()\n");
+        assert!(matches!(res, Err(CompileError::EmptyBrackets(span))
+            if (span.start.row, span.start.col, span.end.col) == (2, 6, 7)));
+        let res = parse("exit (a b)\n");
+        assert!(matches!(res, Err(CompileError::UnexpectedToken(token))
+            if (token.start.row, token.start.col) == (1, 9)));
+    }
+
+    #[test]
+    fn error_inside_brackets_restores_newlines() {
+        let sources = [
+            "exit (1 + )\n",
+            "exit (2 * (1 +\n",
+            "exit ((1 2))\n",
+            "exit (3 @)\n",
+        ];
+        for source in sources {
+            let mut parser = Parser::new(String::from(source));
+            assert!(parser.parse_program().is_err(), "{:?}", source);
+            assert!(parser.lexer.emit_newline, "{:?}", source);
+            assert_eq!(parser.rexp_nesting_level, 0, "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn mutable_declarations() {
+        let program = parse("let a\nlet mut b\nlet c = 1\nlet mut d = 2\n").unwrap();
+        let stmts: Vec<String> = program.stmts.iter().map(|stmt| stmt.to_string()).collect();
+        assert_eq!(
+            stmts,
+            [
+                "Declare(a)",
+                "Declare(mut b)",
+                "Initialize(c, 1)",
+                "Initialize(mut d, 2)"
+            ]
+        );
+
+        let res = parse("let mut = 1\n");
+        assert!(matches!(res, Err(CompileError::ExpectedIdent(_))));
+    }
+
+    #[test]
+    fn type_annotations() {
+        let program = parse("let a: i32\nlet mut b: i64 = 2\nlet c = 3\n").unwrap();
+        let stmts: Vec<String> = program.stmts.iter().map(|stmt| stmt.to_string()).collect();
+        assert_eq!(
+            stmts,
+            [
+                "Declare(a: i32)",
+                "Initialize(mut b: i64, 2)",
+                "Initialize(c, 3)"
+            ]
+        );
+
+        let res = parse("let a: u8 = 1\n");
+        assert!(matches!(res, Err(CompileError::UnknownType(ident)) if ident.lexeme == "u8"));
+        let res = parse("let a: = 1\n");
+        assert!(matches!(res, Err(CompileError::ExpectedType(_))));
+    }
+
+    #[test]
+    fn casts() {
+        let program = parse("let a = x as i32\nlet b = -x as i64 as i32 + 1 * y as i64\n").unwrap();
+        let stmts: Vec<String> = program.stmts.iter().map(|stmt| stmt.to_string()).collect();
+        assert_eq!(
+            stmts,
+            [
+                "Initialize(a, x as i32)",
+                "Initialize(b, (-x as i64 as i32 + (1 * y as i64)))"
+            ]
+        );
+
+        assert_eq!(eval("4294967295 as i32"), Some(-1));
+        assert_eq!(eval("4294967295 as i32 as i64"), Some(-1));
+        assert_eq!(eval("2147483648 as i32"), Some(i32::MIN as i64));
+        assert_eq!(eval("-5 as i64"), Some(-5));
+
+        let res = parse("exit x as\n");
+        assert!(matches!(res, Err(CompileError::ExpectedType(_))));
+    }
+
+    #[test]
+    fn field_access() {
+        let program = parse("a.b.c = 1\nexit a.b + 1\n").unwrap();
+        let Stmt::Assign(lexp, _) = &program.stmts[0] else {
+            panic!("Expected an assignment");
+        };
+        let LExp::Field(inner, c) = lexp else {
+            panic!("Expected a field access");
+        };
+        assert_eq!(c.lexeme, "c");
+        let LExp::Field(inner, b) = inner.as_ref() else {
+            panic!("Expected a field access");
+        };
+        assert_eq!(b.lexeme, "b");
+        assert!(matches!(inner.as_ref(), LExp::Ident(a) if a.lexeme == "a"));
+
+        let stmts: Vec<String> = program.stmts.iter().map(|stmt| stmt.to_string()).collect();
+        assert_eq!(stmts, ["Assign(a.b.c, 1)", "Exit((a.b + 1))"]);
+
+        let res = parse("a. = 1\n");
+        assert!(matches!(res, Err(CompileError::ExpectedIdent(_))));
+    }
+
+    #[test]
+    fn separated_lists() {
+        let list = |source: &str| {
+            let mut parser = Parser::new(String::from(source));
+            parser.skip_newlines()?;
+            let items = parser.parse_separated(TT::Comma, TT::EndOfFile, |parser| parser.rexp())?;
+            return Ok(items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>());
+        };
+        assert_eq!(list("1, 2, 3").unwrap(), ["1", "2", "3"]);
+        assert_eq!(list("1, 2 + 3,").unwrap(), ["1", "(2 + 3)"]);
+        assert!(list("").unwrap().is_empty());
+        assert!(matches!(
+            list("1, , 3"),
+            Err(CompileError::UnexpectedToken(token)) if token.tokentype == TT::Comma
+        ));
+        assert!(matches!(
+            list("1 2"),
+            Err(CompileError::UnexpectedToken(token)) if token.tokentype == TT::IntLiteral(String::from("2"))
+        ));
+    }
+
+    #[test]
+    fn calls() {
+        let program = parse("exit f()\nexit f(1, 2+3)\nexit f\n").unwrap();
+        let Stmt::Exit(RExp::Term(Term::Call(f, args))) = &program.stmts[0] else {
+            panic!("Expected a call");
+        };
+        assert_eq!(f.lexeme, "f");
+        assert!(args.is_empty());
+        let Stmt::Exit(RExp::Term(Term::Call(_, args))) = &program.stmts[1] else {
+            panic!("Expected a call");
+        };
+        assert_eq!(args.len(), 2);
+        assert!(matches!(args[1], RExp::Add(_, _)));
+        assert!(matches!(
+            &program.stmts[2],
+            Stmt::Exit(RExp::Term(Term::LExp(LExp::Ident(f)))) if f.lexeme == "f"
+        ));
+
+        let stmts: Vec<String> = program.stmts.iter().map(|stmt| stmt.to_string()).collect();
+        assert_eq!(stmts, ["Exit(f())", "Exit(f(1, (2 + 3)))", "Exit(f)"]);
+
+        // Newlines don't end the statement inside the argument list.
+        let program = parse("exit f(\n    1,\n    g(2)\n) * 3\n").unwrap();
+        assert_eq!(program.stmts[0].to_string(), "Exit((f(1, g(2)) * 3))");
+
+        let program = parse("exit f(1,)\n").unwrap();
+        assert_eq!(program.stmts[0].to_string(), "Exit(f(1))");
+        let res = parse("exit f(1 2)\n");
+        assert!(matches!(res, Err(CompileError::ExpectedEBrace(_))));
+        let res = parse("exit f(1, 2\n");
+        assert!(matches!(res, Err(CompileError::ExpectedEBrace(_))));
+        let res = parse("exit f(,)\n");
+        assert!(matches!(res, Err(CompileError::UnexpectedToken(_))));
+        let res = parse("f() = 1\n");
+        assert!(matches!(res, Err(CompileError::RExpOnLHS(_))));
+    }
+
+    #[test]
+    fn assign_to_bracketed_lexp() {
+        let program = parse("(x) = 5\n((y.z)) = 6\n").unwrap();
+        let stmts: Vec<String> = program.stmts.iter().map(|stmt| stmt.to_string()).collect();
+        assert_eq!(stmts, ["Assign(x, 5)", "Assign(y.z, 6)"]);
+
+        let res = parse("(x + 1) = 5\n");
+        assert!(matches!(res, Err(CompileError::RExpOnLHS(_))));
+    }
+
+    #[test]
+    fn parser_config() {
+        let source = "let a = 1\n\n\nexit a // done\n";
+        let parser = Parser::new(String::from(source));
+        assert!(parser.lexer.collapse_newlines);
+        assert!(!parser.lexer.preserve_trivia);
+
+        let mut config = ParserConfig::default();
+        config.collapse_newlines(false);
+        let mut parser = Parser::new_with_config(String::from(source), &config);
+        assert!(!parser.lexer.collapse_newlines);
+        assert!(!parser.lexer.preserve_trivia);
+        parser.parse_program().unwrap();
+        assert_eq!(parser.program.stmts.len(), 2);
+    }
+
+    #[test]
+    fn parse_from_reader() {
+        let reader = std::io::Cursor::new("let a = 1\nexit a + 1\n");
+        let mut parser = Parser::from_reader(reader, Some(Rc::from("<stdin>"))).unwrap();
+        parser.parse_program().unwrap();
+        let stmts: Vec<String> = parser
+            .program
+            .stmts
+            .iter()
+            .map(|stmt| stmt.to_string())
+            .collect();
+        assert_eq!(stmts, ["Initialize(a, 1)", "Exit((a + 1))"]);
+
+        let Stmt::Exit(rexp) = &parser.program.stmts[1] else {
+            panic!("Expected an exit statement");
+        };
+        let RExp::Add(lhs, _) = rexp else {
+            panic!("Expected an addition");
+        };
+        let RExp::Term(Term::LExp(LExp::Ident(ident))) = lhs.as_ref() else {
+            panic!("Expected an identifier");
+        };
+        assert_eq!(ident.file.as_deref(), Some("<stdin>"));
+    }
+
+    #[test]
+    fn program_accessors() {
+        let mut parser = Parser::new(String::from("let a = 1\n"));
+        parser.parse_program().unwrap();
+        let mut program = parser.program;
+        assert_eq!(program.len(), 1);
+        assert!(!program.is_empty());
+
+        let mut parser = Parser::new(String::from("exit a\n"));
+        parser.parse_program().unwrap();
+        for stmt in parser.program.stmts {
+            program.push(stmt);
+        }
+        for stmt in program.iter_mut() {
+            if let Stmt::Initialize(decl, _) = stmt {
+                decl.mutable = true;
+            }
+        }
+        let stmts: Vec<String> = program.iter().map(|s| s.to_string()).collect();
+        assert_eq!(stmts, ["Initialize(mut a, 1)", "Exit(a)"]);
+        assert!(Program { stmts: Vec::new() }.is_empty());
+    }
+
+    #[test]
+    fn programs_from_several_files() {
+        let parse_file = |source: &str, file: &str| {
+            let mut parser = Parser::from_reader(source.as_bytes(), Some(Rc::from(file))).unwrap();
+            parser.parse_program().unwrap();
+            return parser.program;
+        };
+        let mut program = parse_file("let x = 40\nfn f() {\n}\n", "a.tl");
+        program
+            .append(parse_file("let y = x\nexit y + 2\n", "b.tl"))
+            .unwrap();
+        let stmts: Vec<String> = program.stmts.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            stmts,
+            [
+                "Initialize(x, 40)",
+                "fn f() {\n}",
+                "Initialize(y, x)",
+                "Exit((y + 2))"
+            ]
+        );
+
+        // Redeclaring a name within one file is shadowing, but not across files.
+        let err = program
+            .append(parse_file("let z = 1\nlet z = 2\nfn x() {\n}\n", "c.tl"))
+            .unwrap_err();
+        let CompileError::Redefinition(redefinition) = err else {
+            panic!("Expected a redefinition, got {:?}", err);
+        };
+        let (ident, previous) = *redefinition;
+        assert_eq!(ident.file.as_deref(), Some("c.tl"));
+        assert_eq!((ident.start.row, ident.start.col), (3, 4));
+        assert_eq!(previous.file.as_deref(), Some("a.tl"));
+        assert_eq!((previous.start.row, previous.start.col), (1, 5));
+        assert_eq!(program.stmts.len(), 4);
+    }
+
+    #[test]
+    fn missing_input_file() {
+        let path = std::env::temp_dir().join("toylang_tests_missing_input.tl");
+        let res = Parser::from_file(Rc::from(path.to_str().unwrap()));
+        let err = res.err().expect("Parsing a missing file should fail");
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn operator_table() {
+        let mut lexer = Lexer::new(String::from(
+            "= : . + - * / == != < <= > >= && || ! { } ( ) let mut exit if else fn return as a 1\n",
+        ));
+        let mut ops = Vec::new();
+        while lexer.peek().tokentype != TT::EndOfFile {
+            let tokentype = lexer.peek().tokentype;
+            assert_eq!(
+                is_op(&tokentype),
+                op_prec_and_assoc(&tokentype).is_some(),
+                "{:?}",
+                tokentype
+            );
+            if is_op(&tokentype) {
+                ops.push(tokentype);
+            }
+            lexer.consume().unwrap();
+        }
+        let table: Vec<TT> = BINARY_OPERATORS
+            .iter()
+            .map(|(op, _, _)| op.clone())
+            .collect();
+        assert_eq!(ops.len(), table.len());
+        for op in table.iter() {
+            assert!(ops.contains(op), "{:?}", op);
+            // Every operator must also have an expression to turn into.
+            let lhs = RExp::Term(Term::IntLit(IntLiteral {
+                file: None,
+                start: Location::default(),
+                end: Location::default(),
+                lexeme: String::from("1"),
+            }));
+            let rhs = RExp::Term(Term::IntLit(IntLiteral {
+                file: None,
+                start: Location::default(),
+                end: Location::default(),
+                lexeme: String::from("2"),
+            }));
+            RExp::combine(op, lhs, rhs);
+        }
+    }
+
+    #[test]
+    fn parse_from_tokens() {
+        let token = |col: usize, tokentype: TT| Token {
+            file: None,
+            start: Location {
+                row: 1,
+                col,
+                offset: col - 1,
+            },
+            end: Location {
+                row: 1,
+                col: col + 1,
+                offset: col,
+            },
+            tokentype,
+        };
+        let tokens = vec![
+            token(1, TT::Let),
+            token(5, TT::Ident(String::from("a"))),
+            token(7, TT::Assign),
+            token(9, TT::IntLiteral(String::from("2"))),
+            token(10, TT::LineComment(String::from("// two"))),
+            token(10, TT::NewLine),
+            token(1, TT::Exit),
+            token(6, TT::Ident(String::from("a"))),
+            token(8, TT::Asterisk),
+            token(10, TT::SBrace),
+            token(11, TT::IntLiteral(String::from("1"))),
+            token(13, TT::Plus),
+            token(15, TT::IntLiteral(String::from("3"))),
+            token(16, TT::EBrace),
+        ];
+        let mut parser = Parser::from_tokens(tokens);
+        parser.parse_program().unwrap();
+        let stmts: Vec<String> = parser
+            .program
+            .stmts
+            .iter()
+            .map(|stmt| stmt.to_string())
+            .collect();
+        assert_eq!(stmts, ["Initialize(a, 2)", "Exit((a * ((1 + 3))))"]);
+    }
+
+    #[test]
+    fn fn_definition() {
+        let program = parse("fn main() {\n    return 7\n}\n").unwrap();
+        assert_eq!(program.stmts.len(), 1);
+        let Stmt::FnDef(ident, body) = &program.stmts[0] else {
+            panic!("expected a function definition, got: {}", program.stmts[0]);
+        };
+        assert_eq!(ident.lexeme, "main");
+        assert_eq!(body.len(), 1);
+        assert_eq!(format!("{}", body[0]), "Return(7)");
+    }
+
+    #[test]
+    fn top_level_return() {
+        let program = parse("return 1\n").unwrap();
+        assert_eq!(format!("{}", program.stmts[0]), "Return(1)");
+    }
+
+    #[test]
+    fn logical_operators() {
+        let program = parse("let a = 1\nexit not a == 1 or a and !(a - 1)\nnot a\n").unwrap();
+        assert_eq!(
+            format!("{}", program.stmts[1]),
+            "Exit(((!a == 1) || (a && !((a - 1)))))"
+        );
+        assert_eq!(format!("{}", program.stmts[2]), "RExp(!a)");
+        // The words are keywords, not names.
+        assert!(parse("let and = 1\n").is_err());
+    }
+
+    #[test]
+    fn nested_fn_is_misplaced() {
+        let cases = [
+            ("{\n    fn f() {\n        return 1\n    }\n}\n", (2, 5)),
+            ("fn main() {\n    fn f() {\n    }\n}\n", (2, 5)),
+            ("if 1 fn f() {\n}\n", (1, 6)),
+        ];
+        for (source, start) in cases {
+            let Err(CompileError::MisplacedStatement(span, message)) = parse(source) else {
+                panic!("Expected a misplaced statement in {:?}", source);
+            };
+            assert_eq!((span.start.row, span.start.col), start, "{:?}", source);
+            assert_eq!(message, "functions can only be defined at the top level");
+        }
+    }
+
+    #[test]
+    fn let_without_a_name() {
+        for source in ["let = 5\n", "let 5 = 3\n", "let mut = 1\n"] {
+            let offset = source.find(['=', '5']).unwrap();
+            let location = Location {
+                row: 1,
+                col: offset + 1,
+                offset,
+            };
+            assert_eq!(
+                parse(source).unwrap_err(),
+                CompileError::ExpectedIdent(location),
+                "{:?}",
+                source
+            );
+        }
+
+        let mut config = ParserConfig::default();
+        config.recover(true);
+        let source =
+            "let = 5\nlet a = 1\n{\n    let 5 = { 3 }\n    exit a\n}\n{ let = 2 }\nexit a.\n";
+        let mut parser = Parser::new_with_config(String::from(source), &config);
+        let res = parser.parse_program();
+        // Only a `let` without a name is recovered from.
+        assert!(matches!(res, Err(CompileError::ExpectedIdent(location))
+            if (location.row, location.col) == (8, 7)));
+        let locations: Vec<(usize, usize)> = parser
+            .errors
+            .iter()
+            .map(|err| match err {
+                CompileError::ExpectedIdent(location) => (location.row, location.col),
+                err => panic!("Expected an identifier error, got {:?}", err),
+            })
+            .collect();
+        assert_eq!(locations, [(1, 5), (4, 9), (7, 7)]);
+        let stmts: Vec<String> = parser.program.stmts.iter().map(|s| s.to_string()).collect();
+        assert_eq!(stmts, ["Initialize(a, 1)", "{\n  Exit(a)\n}", "{\n}"]);
+    }
+
+    #[test]
+    fn nesting_too_deep() {
+        // The literal inside the innermost brackets or blocks is one level deeper.
+        let max = DEFAULT_MAX_DEPTH as usize;
+        let brackets = |depth| format!("exit {}1{}\n", "(".repeat(depth), ")".repeat(depth));
+        parse(&brackets(max - 1)).unwrap();
+        let blocks = |depth| format!("{}exit 1\n{}", "{\n".repeat(depth), "}\n".repeat(depth));
+        parse(&blocks(max - 1)).unwrap();
+        let res = parse(&brackets(10_000));
+        assert!(matches!(res, Err(CompileError::NestingTooDeep(location))
+            if (location.row, location.col) == (1, 6 + max)));
+
+        let mut config = ParserConfig::default();
+        config.max_depth(3);
+        let cases = [
+            ("exit ---1\n", (1, 9)),
+            (
+                "{\n    {\n        {\n            exit 1\n        }\n    }\n}\n",
+                (4, 18),
+            ),
+            ("if 1 if 1 if 1 exit 1\n", (1, 21)),
+        ];
+        for (source, start) in cases {
+            let mut parser = Parser::new_with_config(String::from(source), &config);
+            let Err(CompileError::NestingTooDeep(location)) = parser.parse_program() else {
+                panic!("Expected the nesting in {:?} to be too deep", source);
+            };
+            assert_eq!((location.row, location.col), start, "{:?}", source);
+            assert_eq!(parser.depth, 0, "{:?}", source);
+        }
     }
 }
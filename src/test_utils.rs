@@ -0,0 +1,146 @@
+use std::{
+    process::Command,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    codegen::Asm,
+    interpret::interpret_with_output,
+    optimize::{optimize, OptLevel},
+    parser::Parser,
+};
+
+static BUILD_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn has(program: &str, arg: &str) -> bool {
+    return Command::new(program).arg(arg).output().is_ok();
+}
+
+/// Whether test programs can be built and run, which needs Windows along with the tools.
+pub fn toolchain_available() -> bool {
+    return cfg!(windows) && nasm_available() && has("gcc", "--version");
+}
+
+/// Assembling for win64 works on any host, only linking and running need Windows.
+pub fn nasm_available() -> bool {
+    return has("nasm", "-v");
+}
+
+/// A fresh path in the temp directory to build a test program at, without an extension.
+pub fn temp_stem() -> Rc<str> {
+    let dir = std::env::temp_dir().join("toylang_tests");
+    std::fs::create_dir_all(&dir).unwrap();
+    let id = BUILD_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let stem = dir.join(format!("test_{}_{}", std::process::id(), id));
+    return Rc::from(stem.to_str().unwrap());
+}
+
+/// Optimizes `source` at `level`, compiles it into an executable and runs it, returning its
+/// exit code.
+///
+/// The generated code targets win64, so this returns `None` (and the caller should
+/// skip its runtime assertions) unless we're on Windows with `nasm` and `gcc` available.
+pub fn compile_and_run_at(source: &str, level: OptLevel) -> Option<i32> {
+    return compile_and_run_with_output(source, level).map(|(code, _)| code);
+}
+
+/// Like `compile_and_run_at`, but also returns what the program wrote to stdout.
+pub fn compile_and_run_with_output(source: &str, level: OptLevel) -> Option<(i32, Vec<u8>)> {
+    if !toolchain_available() {
+        return None;
+    }
+
+    let stem = temp_stem();
+
+    let mut parser = Parser::new(String::from(source));
+    parser.parse_program().unwrap();
+    optimize(&mut parser.program.stmts, level).unwrap();
+    let mut asm = Asm::default();
+    asm.opt_level = level;
+    asm.gen(&parser.program.stmts).unwrap();
+    let report = asm.compile(stem.clone()).unwrap();
+    assert!(report.success, "{:?}", report);
+
+    let output = Command::new(format!("{stem}.exe")).output().unwrap();
+    return Some((output.status.code()?, output.stdout));
+}
+
+/// Checks that `source` exits with `expected` when it is interpreted, and also when it is
+/// compiled and run if the toolchain is available. Unlike `compile_and_run_at`, this checks the
+/// exit code on every host.
+pub fn assert_exit_code(source: &str, expected: i64) {
+    assert_exit_code_at(source, OptLevel::O0, expected);
+}
+
+/// Like `assert_exit_code`, but optimizes the compiled program at `level` first.
+pub fn assert_exit_code_at(source: &str, level: OptLevel, expected: i64) {
+    let mut parser = Parser::new(String::from(source));
+    parser.parse_program().unwrap();
+    let (res, _) = interpret_with_output(&parser.program);
+    let code = res.unwrap_or_else(|err| panic!("{:?}\n{}", err, source));
+    assert_eq!(code, expected, "interpreted\n{}", source);
+    if let Some(code) = compile_and_run_at(source, level) {
+        assert_eq!(code, expected as i32, "at {:?}\n{}", level, source);
+    }
+}
+
+/// Like `assert_exit_code`, but checks what `source` prints rather than its exit code.
+pub fn assert_output(source: &str, expected: &[u8]) {
+    let mut parser = Parser::new(String::from(source));
+    parser.parse_program().unwrap();
+    let (res, output) = interpret_with_output(&parser.program);
+    res.unwrap_or_else(|err| panic!("{:?}\n{}", err, source));
+    assert_eq!(output, expected, "interpreted\n{}", source);
+    if let Some((_, output)) = compile_and_run_with_output(source, OptLevel::O0) {
+        assert_eq!(output, expected, "{}", source);
+    }
+}
+
+/// Compares `actual` with the checked-in file `tests/golden/{name}`.
+///
+/// With `UPDATE_GOLDEN=1` set, the file is overwritten with `actual` instead, so that an
+/// intended change to the output can be reviewed in the diff of the golden file.
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Could not read {}: {}", path.display(), err));
+    if expected == actual {
+        return;
+    }
+
+    let expected_lines: Vec<&str> = expected.lines().collect();
+    let actual_lines: Vec<&str> = actual.lines().collect();
+    let line_count = expected_lines.len().max(actual_lines.len());
+    // Only the line endings differ if all the lines match.
+    let first = (0..line_count)
+        .find(|i| expected_lines.get(*i) != actual_lines.get(*i))
+        .unwrap_or(line_count);
+    let mut message = format!(
+        "{} doesn't match, starting at line {}:\n",
+        path.display(),
+        first + 1
+    );
+    let context = first.saturating_sub(2)..first + 3;
+    for i in context.clone().filter(|i| *i < expected_lines.len()) {
+        let marker = if i < first { ' ' } else { '-' };
+        message.push_str(&format!(
+            "{} {:>4} | {}\n",
+            marker,
+            i + 1,
+            expected_lines[i]
+        ));
+    }
+    for i in context.filter(|i| *i >= first && *i < actual_lines.len()) {
+        message.push_str(&format!("+ {:>4} | {}\n", i + 1, actual_lines[i]));
+    }
+    message.push_str("Rerun with UPDATE_GOLDEN=1 set if the change is intended.");
+    panic!("{}", message);
+}
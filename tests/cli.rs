@@ -0,0 +1,215 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+fn toylang() -> Command {
+    return Command::new(env!("CARGO_BIN_EXE_toylang"));
+}
+
+#[test]
+fn no_arguments_prints_usage() {
+    let output = toylang().output().unwrap();
+    assert!(!output.status.success());
+    assert_eq!(output.status.code(), Some(2));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no input file given"), "{}", stderr);
+    assert!(stderr.contains("Usage: toylang"), "{}", stderr);
+}
+
+#[test]
+fn help_prints_usage() {
+    let output = toylang().arg("--help").output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Usage: toylang"));
+}
+
+#[test]
+fn unknown_flag_is_rejected() {
+    let output = toylang().args(["--fast", "prog.tl"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown option `--fast`"));
+}
+
+#[test]
+fn version_is_printed() {
+    let output = toylang().arg("--version").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("toylang {}\n", env!("CARGO_PKG_VERSION"))
+    );
+}
+
+#[test]
+fn missing_input_is_reported() {
+    let path = std::env::temp_dir().join("toylang_cli_missing_input.tl");
+    let output = toylang().arg(&path).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("Error: could not read `{}`", path.display())),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn program_is_read_from_stdin() {
+    let dir = std::env::temp_dir().join("toylang_cli_stdin");
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("prog");
+
+    let mut child = toylang()
+        .args(["--emit-asm", "-o", output.to_str().unwrap(), "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"exit 42\n").unwrap();
+    let status = child.wait_with_output().unwrap().status;
+    assert!(status.success());
+
+    let asm = std::fs::read_to_string(dir.join("prog.asm")).unwrap();
+    assert!(asm.contains("mov rax, 42"), "{}", asm);
+}
+
+#[test]
+fn tokens_are_dumped() {
+    let mut child = toylang()
+        .args(["--dump-tokens", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"exit 1").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "<stdin>:1:1-1:5 Exit \"exit\"",
+            "<stdin>:1:6-1:6 IntLiteral \"1\"",
+            "<stdin>:1:6-1:6 EndOfFile \"\"",
+        ],
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn symbols_are_dumped() {
+    let dir = std::env::temp_dir().join("toylang_cli_dump_symbols");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut child = toylang()
+        .args(["--dump-symbols", "--emit-asm", "-o"])
+        .arg(dir.join("prog"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"let x = 1\nexit x\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("_start:\n    x_1: i64 [rbp-8]\n"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn program_is_interpreted() {
+    let mut child = toylang()
+        .args(["--interpret", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"let x = 6\nprint x\nexit x / 2\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.ends_with("\n6\n"), "{}", stdout);
+}
+
+#[test]
+fn several_files_make_one_program() {
+    let dir = std::env::temp_dir().join("toylang_cli_several_files");
+    std::fs::create_dir_all(&dir).unwrap();
+    let a = dir.join("a.tl");
+    let b = dir.join("b.tl");
+    std::fs::write(&a, "let x = 40\n").unwrap();
+    std::fs::write(&b, "exit x + 2\n").unwrap();
+
+    let output = dir.join("out");
+    let status = toylang()
+        .arg("--emit-asm")
+        .arg("-o")
+        .arg(&output)
+        .args([&a, &b])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let asm = std::fs::read_to_string(dir.join("out.asm")).unwrap();
+    assert_eq!(asm.matches("_start:").count(), 1, "{}", asm);
+    assert!(asm.contains("mov rax, 40"), "{}", asm);
+
+    // Both files declaring `x` at the top level is an error pointing into both of them.
+    let c = dir.join("c.tl");
+    std::fs::write(&c, "let y = 1\nlet x = 2\n").unwrap();
+    let output = toylang()
+        .args(["--emit-asm", "-o"])
+        .arg(&output)
+        .args([&a, &c])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Redefinition"), "{}", stdout);
+    for path in [&a, &c] {
+        let file = format!("{:?}", path.to_str().unwrap());
+        assert!(stdout.contains(&file), "{}", stdout);
+    }
+}
+
+#[test]
+fn gas_syntax_is_written_to_a_dot_s_file() {
+    let dir = std::env::temp_dir().join("toylang_cli_gas");
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("prog");
+
+    let mut child = toylang()
+        .args(["--emit-asm", "--syntax", "gas", "-o"])
+        .arg(&output)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"exit 42\n").unwrap();
+    assert!(child.wait_with_output().unwrap().status.success());
+
+    let asm = std::fs::read_to_string(dir.join("prog.s")).unwrap();
+    assert!(asm.contains("mov $42, %rax"), "{}", asm);
+}
@@ -0,0 +1,55 @@
+use std::{
+    io,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static BUILD_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn has(program: &str, arg: &str) -> bool {
+    return Command::new(program).arg(arg).output().is_ok();
+}
+
+/// Whether programs can be built and run, which needs Windows along with `nasm` and `gcc`.
+pub fn toolchain_available() -> bool {
+    return cfg!(windows) && has("nasm", "-v") && has("gcc", "--version");
+}
+
+/// Compiles `source` with the `toylang` binary into an executable and runs it, returning its
+/// exit code.
+///
+/// Fails with `io::ErrorKind::Unsupported` when the toolchain isn't available, which the
+/// caller should treat as a reason to skip its assertions rather than as a failure.
+pub fn compile_and_run(source: &str) -> io::Result<i32> {
+    if !toolchain_available() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "nasm and gcc on Windows are needed to run programs",
+        ));
+    }
+
+    let dir = std::env::temp_dir().join("toylang_integration_tests");
+    std::fs::create_dir_all(&dir)?;
+    let id = BUILD_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let stem = dir.join(format!("test_{}_{}", std::process::id(), id));
+    let input = stem.with_extension("tl");
+    std::fs::write(&input, source)?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_toylang"))
+        .arg("-o")
+        .arg(&stem)
+        .arg(&input)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Could not compile {}:\n{}",
+            input.display(),
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    let status = Command::new(stem.with_extension("exe")).status()?;
+    return status
+        .code()
+        .ok_or_else(|| io::Error::other("The program was terminated by a signal"));
+}
@@ -0,0 +1,79 @@
+default rel
+global _start
+extern ExitProcess
+section .text
+_start:
+    mov rbp, rsp
+    sub rsp, 16
+    ; {
+    
+    ; let a = 7
+    
+    
+    ; 7
+    mov rax, 7
+    push rax
+    
+    ; let a_1 = 7
+    pop rax
+    mov qword [rbp-8], rax
+    
+    ; a_1
+    push qword [rbp-8]
+    
+    ; 2
+    mov rax, 2
+    push rax
+    
+    ; a + 2
+    pop rbx
+    pop rax
+    add rax, rbx
+    push rax
+    
+    ; 3
+    mov rax, 3
+    push rax
+    
+    ; (..) * 3
+    pop rbx
+    pop rax
+    imul rbx
+    push rax
+    
+    ; 10
+    mov rax, 10
+    push rax
+    
+    ; a_1
+    push qword [rbp-8]
+    
+    ; 10 / a
+    pop rbx
+    pop rax
+    test rbx, rbx
+    jz div_by_zero
+    cqo
+    idiv rbx
+    push rax
+    
+    ; (..) - (..)
+    pop rbx
+    pop rax
+    sub rax, rbx
+    push rax
+    
+    ; exit ((((a + 2)) * 3) - (10 / a))
+    pop rax
+    mov rcx, rax
+    call ExitProcess
+    ; }
+    
+    ; exit 0
+    xor rcx, rcx
+    call ExitProcess
+    
+div_by_zero:
+    ; exit 136
+    mov rcx, 136
+    call ExitProcess
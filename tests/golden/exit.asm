@@ -0,0 +1,21 @@
+default rel
+global _start
+extern ExitProcess
+section .text
+_start:
+    mov rbp, rsp
+    ; {
+    
+    ; 3
+    mov rax, 3
+    push rax
+    
+    ; exit 3
+    pop rax
+    mov rcx, rax
+    call ExitProcess
+    ; }
+    
+    ; exit 0
+    xor rcx, rcx
+    call ExitProcess
@@ -0,0 +1,65 @@
+default rel
+global _start
+extern ExitProcess
+section .text
+_start:
+    mov rbp, rsp
+    sub rsp, 16
+    ; {
+    
+    ; let a = 5
+    
+    
+    ; 5
+    mov rax, 5
+    push rax
+    
+    ; let a_1 = 5
+    pop rax
+    mov qword [rbp-8], rax
+    
+    ; a_1
+    push qword [rbp-8]
+    
+    ; 3
+    mov rax, 3
+    push rax
+    
+    ; a > 3
+    pop rbx
+    pop rax
+    cmp rax, rbx
+    jle else_start_0
+    ; if
+    ; {
+    
+    ; 1
+    mov rax, 1
+    push rax
+    
+    ; exit 1
+    pop rax
+    mov rcx, rax
+    call ExitProcess
+    ; }
+    jmp else_end_0
+else_start_0:
+    ; else {
+    ; {
+    
+    ; 2
+    mov rax, 2
+    push rax
+    
+    ; exit 2
+    pop rax
+    mov rcx, rax
+    call ExitProcess
+    ; }
+    ; }
+else_end_0:
+    ; }
+    
+    ; exit 0
+    xor rcx, rcx
+    call ExitProcess
@@ -0,0 +1,35 @@
+use std::process::Command;
+
+use toylang::parser::{Parser, Stmt};
+
+#[test]
+fn front_end_builds_without_std() {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| String::from("cargo"));
+    // A target directory of its own, so that this doesn't wait on the build running the tests.
+    let output = Command::new(cargo)
+        .current_dir(manifest_dir)
+        .args(["build", "--lib", "--no-default-features", "--target-dir"])
+        .arg(
+            std::path::Path::new(manifest_dir)
+                .join("target")
+                .join("no_std"),
+        )
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn parses_from_a_string() {
+    let mut parser = Parser::new(String::from("let a = 1\nexit a + 1\n"));
+    parser.parse_program().unwrap();
+    assert!(matches!(
+        parser.program.stmts.as_slice(),
+        [Stmt::Initialize(_, _), Stmt::Exit(_)]
+    ));
+}
@@ -0,0 +1,12 @@
+use std::io;
+
+mod common;
+
+#[test]
+fn exit_code_is_returned() {
+    match common::compile_and_run("exit 42\n") {
+        Ok(code) => assert_eq!(code, 42),
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => (),
+        Err(err) => panic!("{}", err),
+    }
+}